@@ -74,6 +74,11 @@ pub fn build(b: *Builder) !void {
     const rom_offset = b.option([]const u8, "rom-offset", "Insert N padding bytes before code. " ++
         "Not supported by all targets (default = 0)") orelse "0";
 
+    // Allows concurrent builds (e.g., by `tools/runbench`) to use separate
+    // directories. Use this together with `--cache-dir`.
+    const output_dir = b.option([]const u8, "output-dir", "Directory to put the built executables in " ++
+        "(default = zig-cache)") orelse "zig-cache";
+
     const target = try CrossTarget.parse(.{
         .arch_os_abi = "thumb-freestanding-eabi",
         .cpu_features = "cortex_m33-dsp-fp16-fpregs-vfp2sp-vfp3d16sp-vfp4d16sp",
@@ -119,7 +124,7 @@ pub fn build(b: *Builder) !void {
     exe_s.setTarget(target);
     exe_s.setBuildMode(mode);
     exe_s.addCSourceFile("common/startup.S", as_flags);
-    exe_s.setOutputDir("zig-cache");
+    exe_s.setOutputDir(output_dir);
     exe_s.addPackagePath("arm_cmse", "../src/drivers/arm_cmse.zig");
     exe_s.addPackagePath("arm_m", "../src/drivers/arm_m.zig");
     exe_s.addBuildOption([]const u8, "BOARD", try allocPrint(b.allocator, "\"{}\"", .{target_board}));
@@ -137,13 +142,21 @@ pub fn build(b: *Builder) !void {
     // exported by the Secure code. Usually it's generated by passing the
     // `--cmse-implib` option to a supported version of `arm-none-eabi-gcc`, but
     // since it might not be available, we use a custom tool to do that.
-    const implib_path = "zig-cache/secure_implib.s";
+    const implib_path = try allocPrint(b.allocator, "{}/secure_implib.s", .{output_dir});
+    const exe_s_stamped_path = try allocPrint(b.allocator, "{}/{}-stamped", .{ output_dir, exe_s_name });
     var implib_args = std.ArrayList([]const u8).init(b.allocator);
     try implib_args.appendSlice(&[_][]const u8{
         mkimplib,
         exe_s.getOutputPath(),
         "-o",
         implib_path,
+        "--scan-sg-gadgets",
+        "--verify-veneers",
+        // Write a copy of `exe_s` stamped with its identity so that Non-Secure
+        // images can detect a mismatched Secure image (see
+        // `nonsecure-common/init.zig`). The copy is the one to be programmed.
+        "--stamp-image-id",
+        exe_s_stamped_path,
     });
 
     const implib = b.addSystemCommand(implib_args.items);
@@ -206,9 +219,11 @@ pub fn build(b: *Builder) !void {
         .target_board = target_board,
         .as_flags = as_flags,
         .rom_offset = rom_offset,
+        .output_dir = output_dir,
         .implib_path = implib_path,
         .implib_step = &implib.step,
         .exe_s = exe_s,
+        .exe_s_stamped_path = exe_s_stamped_path,
         .kernel_include_dirs = &kernel_include_dirs,
         .kernel = kernel,
     };
@@ -348,11 +363,13 @@ const NsAppDeps = struct {
     target_board: []const u8,
     as_flags: []const []const u8,
     rom_offset: []const u8,
+    output_dir: []const u8,
 
     // Secure dependency
     implib_path: []const u8,
     implib_step: *Step,
     exe_s: *LibExeObjStep,
+    exe_s_stamped_path: []const u8,
 
     // FreeRTOS
     kernel_include_dirs: []const []const u8,
@@ -412,7 +429,7 @@ fn defineNonSecureApp(
     } else {
         exe_ns.addCSourceFile("../src/nonsecure_vector_ses.S", as_flags);
     }
-    exe_ns.setOutputDir("zig-cache");
+    exe_ns.setOutputDir(ns_app_deps.output_dir);
     exe_ns.addIncludeDir("../include");
     exe_ns.addPackagePath("arm_m", "../src/drivers/arm_m.zig");
     exe_ns.enable_lto = true;
@@ -469,7 +486,7 @@ fn defineNonSecureApp(
     try qemu_args.appendSlice(&[_][]const u8{
         "qemu-system-arm",
         "-kernel",
-        exe_s.getOutputPath(),
+        ns_app_deps.exe_s_stamped_path,
         "-device",
         qemu_device_arg,
         "-machine",
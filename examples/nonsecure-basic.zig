@@ -3,6 +3,7 @@ const std = @import("std");
 const arm_m = @import("arm_m");
 const warn = @import("nonsecure-common/debug.zig").warn;
 const port = @import("ports/" ++ @import("build_options").BOARD ++ "/nonsecure.zig");
+const nonsecure_init = @import("nonsecure-common/init.zig");
 
 // The (unprocessed) Non-Secure exception vector table.
 // zig fmt: off
@@ -13,6 +14,7 @@ export const raw_exception_vectors linksection(".text.raw_isr_vector") =
 // zig fmt: on
 export fn main() void {
     port.init();
+    nonsecure_init.checkSecureImageId();
 
     warn("yay\r\n", .{});
 
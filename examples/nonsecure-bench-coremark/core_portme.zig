@@ -7,6 +7,7 @@ const warn = @import("../nonsecure-common/debug.zig").warn;
 const timer = @import("../ports/" ++ @import("build_options").BOARD ++ "/timer.zig").timer0;
 const port_ns = @import("../ports/" ++ @import("build_options").BOARD ++ "/nonsecure.zig");
 const port = @import("../ports/" ++ @import("build_options").BOARD ++ "/common.zig");
+const nonsecure_init = @import("../nonsecure-common/init.zig");
 
 const tzmcfi = @cImport(@cInclude("TZmCFI/Gateway.h"));
 
@@ -65,6 +66,7 @@ comptime {
 /// Target specific initialization code
 export fn portable_init(p: *c.core_portable, _argc: *c_int, _argv: ?[*]([*]u8)) void {
     port_ns.init();
+    nonsecure_init.checkSecureImageId();
 
     p.portable_id = 1;
     warn("* portable_init\r\n", .{});
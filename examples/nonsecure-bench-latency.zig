@@ -28,6 +28,7 @@ fn comm() *volatile CommBlock {
 
 export fn main() void {
     port.init();
+    nonsecure_init.checkSecureImageId();
     nonsecure_init.disableNestedExceptionIfDisallowed();
 
     warn("Starting the interrupt latency benchmark...\r\n", .{});
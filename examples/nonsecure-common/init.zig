@@ -2,6 +2,9 @@ const arm_m = @import("arm_m");
 const eql = @import("std").mem.eql;
 const scb = arm_m.scb;
 
+const gateways = @import("../common/gateways.zig");
+const warn = @import("debug.zig").warn;
+
 const SHADOW_EXC_STACK_TYPE = @import("build_options").SHADOW_EXC_STACK_TYPE;
 
 const no_nested_exceptions = eql(u8, SHADOW_EXC_STACK_TYPE, "Unnested") or eql(u8, SHADOW_EXC_STACK_TYPE, "Null");
@@ -13,3 +16,29 @@ pub fn disableNestedExceptionIfDisallowed() void {
         scb.setPriorityGrouping(7);
     }
 }
+
+/// Defined by the import library (`tzmcfi_mkimplib --stamp-image-id`). The
+/// address of this symbol is the identity of the Secure image we were linked
+/// against. (It's declared as `u8` so that the compiler doesn't assume its
+/// alignment.)
+extern var __tzmcfi_secure_image_id: u8;
+
+/// Make sure the running Secure image is the one we were linked against.
+/// Otherwise, calls to Secure gateways would jump to random locations in the
+/// Non-Secure-callable region and end up in a HardFault.
+///
+/// The linker script places `TCGetSecureImageId` first in the
+/// Non-Secure-callable region (and `tzmcfi_mkimplib` checks this), so its
+/// address is the same in any Secure image built for the same port.
+pub fn checkSecureImageId() void {
+    const expected = @ptrToInt(&__tzmcfi_secure_image_id);
+    const actual = gateways.TCGetSecureImageId(0, 0, 0, 0);
+    if (actual != expected) {
+        // `warn` uses a Secure gateway too, so this message might not make it
+        // out. Still, it's worth a try.
+        warn("error: The Secure image (ID = 0x{x}) is not the one this image was linked against (ID = 0x{x}). Please reflash it.\r\n", .{ actual, expected });
+
+        // Don't use `@panic` - the panic handler calls Secure gateways
+        while (true) {}
+    }
+}
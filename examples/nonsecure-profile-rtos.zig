@@ -29,6 +29,7 @@ export const raw_exception_vectors linksection(".text.raw_isr_vector") = @import
 // after initializing data sections.
 export fn main() void {
     port.init();
+    nonsecure_init.checkSecureImageId();
     nonsecure_init.disableNestedExceptionIfDisallowed();
 
     warn("%output-start\r\n", .{});
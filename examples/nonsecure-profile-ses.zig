@@ -32,6 +32,7 @@ fn comm() *volatile CommBlock {
 
 export fn main() void {
     port.init();
+    nonsecure_init.checkSecureImageId();
     nonsecure_init.disableNestedExceptionIfDisallowed();
 
     warn("Starting the SES profiling application...\r\n", .{});
@@ -2,6 +2,7 @@
 const std = @import("std");
 const warn = @import("nonsecure-common/debug.zig").warn;
 const port = @import("ports/" ++ @import("build_options").BOARD ++ "/nonsecure.zig");
+const nonsecure_init = @import("nonsecure-common/init.zig");
 
 // FreeRTOS-related thingy
 const os = @cImport({
@@ -20,6 +21,7 @@ export const raw_exception_vectors linksection(".text.raw_isr_vector") = @import
 // after initializing data sections.
 export fn main() void {
     port.init();
+    nonsecure_init.checkSecureImageId();
 
     warn("Creating an idle task.\r\n", .{});
     _ = os.xTaskCreateRestricted(&idle_task_params, 0);
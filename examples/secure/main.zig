@@ -206,6 +206,29 @@ comptime {
     arm_cmse.exportNonSecureCallable("getSampledPc", nsGetSampledPc);
 }
 
+// ----------------------------------------------------------------------------
+
+/// The identity of this Secure image. The compiler leaves it as zero, and
+/// `tzmcfi_mkimplib --stamp-image-id` fills it in a copy of the linked image
+/// (`secure-stamped`), which is the one to be programmed. Non-Secure
+/// images receive the same value through the import library as
+/// `__tzmcfi_secure_image_id`.
+export const __tzmcfi_image_id: u32 = 0;
+
+/// Retrieve the identity of this Secure image.
+fn nsGetSecureImageId(_r0: usize, _r1: usize, _r2: usize, _r3: usize) callconv(.C) usize {
+    // The value is modified after compilation. Don't let the compiler
+    // constant-fold it.
+    return @ptrCast(*const volatile u32, &__tzmcfi_image_id).*;
+}
+
+comptime {
+    // The linker script places this section first in the Non-Secure-callable
+    // region so that Non-Secure images can call it without knowing which
+    // Secure image is running
+    arm_cmse.exportNonSecureCallableInSection("TCGetSecureImageId", nsGetSecureImageId, ".gnu.sgstubs.image_id");
+}
+
 // ----------------------------------------------------------------------------
 // Build the exception vector table
 // zig fmt: off
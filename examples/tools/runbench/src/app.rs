@@ -1,10 +1,20 @@
 use atomic_refcell::AtomicRefCell;
+use futures::StreamExt;
 use regex::bytes::Regex;
-use serde::Serialize;
-use std::{error::Error, future::Future, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    error::Error,
+    future::Future,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
-use super::{build_target, subprocess, target, BuildOpt, SesType};
+use super::{
+    build_cache, build_target, matrix, output, provenance, size, stats, subprocess, symbolize,
+    target,
+};
 
 pub mod bench_coremark;
 pub mod bench_latency;
@@ -14,30 +24,6 @@ pub mod profile_ses;
 
 /// Describes the traits of a Non-Secure benchmark application.
 pub trait AppTraits {
-    /// Return a flag indicating whether the test conditions for this benchmark
-    /// should include the use of shadow exception stacks.
-    ///
-    /// When this flag is `false`, `ses` will be always enabled.
-    fn should_use_shadow_exception_stacks(&self) -> bool;
-
-    /// Return a flag indicating whether the test conditions for this benchmark
-    /// should include the use of shadow stacks.
-    fn should_use_shadow_stacks(&self) -> bool;
-
-    /// Return a flag indicating whether the test conditions for this benchmark
-    /// should include the use of the context management API.
-    ///
-    /// When this flag is `false`, `ctx` will be always enabled.
-    fn should_use_context_management(&self) -> bool;
-
-    /// Return a flag indicating whether the test conditions for this benchmark
-    /// should include the use of accelerated privilege escalation.
-    fn should_use_accel_raise_pri(&self) -> bool;
-
-    /// Return a flag indicating whether the test conditions for this benchmark
-    /// should include the use of the LLVM icall sanitizer.
-    fn should_use_icall_sanitizer(&self) -> bool;
-
     /// The name of the benchmark application used for a `zig build` parameter
     /// and the produced binary.
     fn name(&self) -> String;
@@ -49,7 +35,8 @@ pub trait AppTraits {
         b"%output-end"
     }
 
-    /// Post-process the output.
+    /// Post-process the output. The result is parsed as JSON5 and checked
+    /// against `schema`.
     ///
     /// By default, this method extracts the contents between `b"%output-start"`
     /// and `b"%output-end"`.
@@ -63,6 +50,48 @@ pub trait AppTraits {
             )
         }
     }
+
+    /// Get the expected structure of the post-processed output.
+    ///
+    /// The default implementation accepts anything.
+    fn schema(&self) -> &'static output::Schema {
+        &output::Schema::Any
+    }
+
+    /// Check if the number at the specified JSON Pointer in the processed
+    /// output is a metric to summarize when a configuration is run more than
+    /// once.
+    ///
+    /// The default implementation returns `true`.
+    fn is_metric(&self, _path: &str) -> bool {
+        true
+    }
+
+    /// Check if a larger value of the specified metric (as shown in reports)
+    /// is better.
+    ///
+    /// The default implementation returns `false`, which is suitable for
+    /// execution times.
+    fn higher_is_better(&self, _metric: &str) -> bool {
+        false
+    }
+
+    /// Reduce the metrics (pairs of JSON Pointers and values) of a build
+    /// configuration to the ones shown in reports.
+    ///
+    /// The default implementation returns `metrics` as-is.
+    fn condense_metrics(&self, metrics: Vec<(String, f64)>) -> Vec<(String, f64)> {
+        metrics
+    }
+
+    /// Check if the processed output is a list of sampled program counters
+    /// (objects having a `pc` field), which are resolved to functions and
+    /// source lines after each run (see [`symbolize`]).
+    ///
+    /// The default implementation returns `false`.
+    fn has_pc_samples(&self) -> bool {
+        false
+    }
 }
 
 lazy_static::lazy_static! {
@@ -70,125 +99,410 @@ lazy_static::lazy_static! {
 }
 
 pub(crate) async fn run(opt: &super::Opt, traits: impl AppTraits) -> Result<(), Box<dyn Error>> {
-    let mut target = build_target(opt).await?;
+    let matrix = matrix::Matrix::load(&opt.matrix_path, &super::BenchmarkType::names())?;
+    let mut build_opts = matrix.build_opts(&traits.name(), &opt.vary_axes())?;
 
-    let build_opts = BuildOpt::all_valid_values(opt).filter(|bo| {
-        if !traits.should_use_shadow_exception_stacks() && bo.ses != Some(SesType::Safe) {
-            return false;
-        }
-        if !traits.should_use_shadow_stacks() && bo.ss {
-            return false;
-        }
-        if !traits.should_use_context_management() && !bo.ctx {
-            return false;
-        }
-        if !traits.should_use_accel_raise_pri() && bo.accel_raise_pri {
-            return false;
-        }
-        if !traits.should_use_icall_sanitizer() && bo.icall {
-            return false;
+    if let Some(filter) = &opt.filter {
+        filter.validate(&matrix)?;
+        build_opts.retain(|bo| filter.matches(bo));
+    }
+
+    if opt.list {
+        for bo in build_opts.iter() {
+            println!("{}", bo);
+            println!(
+                "    {} {}",
+                opt.zig_cmd.to_string_lossy(),
+                zig_build_args(opt, &traits, bo).join(" ")
+            );
         }
-        true
-    });
+        return Ok(());
+    }
 
     log::info!("The following build options will be tested:");
-    for bo in build_opts.clone() {
+    for bo in build_opts.iter() {
         log::info!(" - {}", bo);
     }
-    let build_opts_len = build_opts.clone().count();
+    let num_configs = build_opts.len();
+
+    // Targets which can run more than one program at once get one instance
+    // per job
+    let num_run_jobs = if opt.target().allows_parallel_runs() {
+        opt.jobs.get()
+    } else {
+        1
+    };
+    let mut targets = Vec::with_capacity(num_run_jobs);
+    for _ in 0..num_run_jobs {
+        targets.push(build_target(opt).await?);
+    }
 
-    let output_dir = opt.output_dir();
-    log::info!("The output will be saved to: {:?}", output_dir);
-    tokio::fs::create_dir_all(&output_dir)
-        .await
-        .map_err(|e| RunBenchmarkError::CreateOutputDirError(e.into()))?;
+    let (output_dir, resumed_meta) = if let Some(dir) = &opt.resume {
+        log::info!("Resuming the run saved in: {:?}", dir);
+        let meta = read_metadata(dir)
+            .await
+            .map_err(RunBenchmarkError::ReadMetadataError)?;
+        if meta.benchmark != opt.benchmark() || meta.target != opt.target() {
+            return Err(RunBenchmarkError::ResumeMismatch.into());
+        }
+        (dir.clone(), Some(meta))
+    } else {
+        let output_dir = opt.output_dir();
+        log::info!("The output will be saved to: {:?}", output_dir);
+        tokio::fs::create_dir_all(&output_dir)
+            .await
+            .map_err(|e| RunBenchmarkError::CreateOutputDirError(e.into()))?;
+        (output_dir, None)
+    };
+    let meta_path = output_dir.join("meta.json");
 
-    let mut meta = Metadata {
-        benchmark: opt.benchmark,
-        target: opt.target,
-        exe_names: MetaExeNames {
-            secure: "secure".to_owned() + ".elf",
-            non_secure: traits.name() + ".elf",
-        },
-        matrix: Vec::new(),
+    log::info!("Recording the environment");
+    let session = provenance::Session::query(&opt.zig_cmd, targets[0].tool_versions()).await;
+    let zig_version = session.tool_versions.get("zig").cloned();
+    let mut sessions = resumed_meta
+        .as_ref()
+        .map(|meta| meta.sessions.clone())
+        .unwrap_or_default();
+    sessions.push(session);
+
+    let build_cache = match (opt.no_build_cache, zig_version) {
+        (true, _) => None,
+        (false, None) => {
+            // Builds by different versions of Zig must not be mixed up
+            log::warn!("Disabling the build cache because the version of Zig is unknown");
+            None
+        }
+        (false, Some(zig_version)) => {
+            log::info!("Hashing the source files for the build cache");
+            Some(
+                build_cache::BuildCache::new(
+                    opt.build_cache_dir(),
+                    Path::new("."),
+                    &[&output_dir, &opt.zig_cache_dir],
+                    &zig_version,
+                )
+                .map_err(RunBenchmarkError::BuildCacheError)?,
+            )
+        }
     };
 
-    for (i, bo) in build_opts.enumerate() {
-        log::info!("* Build option: {} ({} of {})", bo, i + 1, build_opts_len);
+    let exe_names = MetaExeNames {
+        secure: "secure".to_owned() + ".elf",
+        non_secure: traits.name() + ".elf",
+    };
 
-        // The ELF images
-        let secure_elf = opt.zig_cache_dir.join("secure");
-        let nonsecure_elf = opt.zig_cache_dir.join(traits.name());
+    // Keep the results of the configurations saved by the resumed run but not
+    // selected this time (e.g., because of a different `--filter`)
+    let kept_runs: Vec<serde_json::Value> = resumed_meta
+        .iter()
+        .flat_map(|meta| meta.matrix.iter().zip(meta.raw_matrix.iter()))
+        .filter(|(run, _)| !build_opts.iter().any(|bo| bo.to_string() == run.name))
+        .map(|(run, raw)| {
+            log::info!("{}: Keeping the saved results", run.name);
+            raw.clone()
+        })
+        .collect();
 
-        // Delete the images just in case
-        log::trace!("Deleting {:?} and {:?}", secure_elf, nonsecure_elf);
-        let (r1, r2) = tokio::join!(
-            tokio::fs::remove_file(&secure_elf),
-            tokio::fs::remove_file(&nonsecure_elf),
-        );
-        let _ = (ignore_not_found(r1)?, ignore_not_found(r2)?);
+    let mut meta = Metadata {
+        benchmark: opt.benchmark(),
+        target: opt.target(),
+        exe_names: exe_names.clone(),
+        sessions,
+        matrix: kept_runs,
+        descriptions: resumed_meta
+            .as_ref()
+            .map(|meta| meta.descriptions.clone())
+            .unwrap_or_default(),
+    };
 
-        // Build the benchmark
-        log::info!("Building the program");
-        let mut build_args = vec!["build".to_owned(), format!("build:{}", traits.name())];
-        bo.append_zig_buld_opts_to(|o| {
-            build_args.push(o.to_owned());
-        });
-        build_args.extend(target.zig_build_flags().iter().cloned().map(str::to_owned));
-
-        subprocess::CmdBuilder::new(&opt.zig_cmd)
-            .args(build_args.iter())
-            .spawn_expecting_success()
-            .await?;
-
-        // Assert the existence of the built ELF images
-        log::trace!(
-            "Checking the existence of {:?} and {:?}",
-            secure_elf,
-            nonsecure_elf
-        );
-        if !secure_elf.exists() {
-            return Err(RunBenchmarkError::BuiltExeNotFound(secure_elf).into());
+    let ctx = &RunContext {
+        opt,
+        traits: &traits,
+        output_dir: &output_dir,
+        resumed_meta: resumed_meta.as_ref(),
+        build_cache: build_cache.as_ref(),
+        exe_names: &exe_names,
+        session: meta.sessions.len() - 1,
+        num_configs,
+    };
+
+    // The indices of the build directories not in use
+    let free_build_slots = &RefCell::new((0..opt.jobs.get()).rev().collect::<Vec<_>>());
+    // The targets not in use
+    let free_targets = &RefCell::new(targets);
+
+    // Build up to `opt.jobs` configurations at once. The builds don't wait
+    // for the measurement because they are sent through a channel.
+    let (prepared_send, prepared_recv) = futures::channel::mpsc::unbounded();
+    let builder = async move {
+        let mut prepared = futures::stream::iter(build_opts.iter().enumerate())
+            .map(|(i, bo)| prepare(ctx, i, bo, free_build_slots))
+            .buffered(opt.jobs.get());
+        while let Some(result) = prepared.next().await {
+            let failed = result.is_err();
+            if prepared_send.unbounded_send(result).is_err() || failed {
+                break;
+            }
         }
-        if !nonsecure_elf.exists() {
-            return Err(RunBenchmarkError::BuiltExeNotFound(nonsecure_elf).into());
+        Ok::<_, Box<dyn Error>>(())
+    };
+
+    // Run the built configurations in order
+    let runner = async {
+        let mut results = prepared_recv
+            .map(|prepared| async move {
+                match prepared? {
+                    Prepared::Skipped(run) => Ok((*run, Vec::new())),
+                    Prepared::Built(built) => run_built(ctx, built, free_targets).await,
+                }
+            })
+            .buffered(num_run_jobs);
+        while let Some(result) = results.next().await {
+            let (run, descriptions) = result?;
+            meta.descriptions.extend(descriptions);
+            meta.matrix.push(serde_json::to_value(run).unwrap());
+
+            // Update the metadata so that the run can be resumed if it's
+            // interrupted
+            write_metadata(&meta_path, &meta).await?;
         }
+        Ok::<_, Box<dyn Error>>(())
+    };
 
-        // Copy the built ELF images
-        let secure_elf_copied = output_dir.join(format!("{}.{}", bo, meta.exe_names.secure));
-        let nonsecure_elf_copied = output_dir.join(format!("{}.{}", bo, meta.exe_names.non_secure));
-        log::trace!(
-            "Copying {:?} and {:?} to {:?} and {:?} (respectively)",
-            secure_elf,
-            nonsecure_elf,
-            secure_elf_copied,
-            nonsecure_elf_copied,
-        );
-        let (r1, r2) = tokio::join!(
-            tokio::fs::copy(&secure_elf, &secure_elf_copied),
-            tokio::fs::copy(&nonsecure_elf, &nonsecure_elf_copied),
+    futures::future::try_join(builder, runner).await?;
+
+    meta.sessions.last_mut().unwrap().finished_at = Some(provenance::now());
+    write_metadata(&meta_path, &meta).await?;
+
+    log::info!("Metadata was written to: {:?}", meta_path);
+
+    Ok(())
+}
+
+/// The parameters shared by the build configurations of a run.
+struct RunContext<'a, T> {
+    opt: &'a super::Opt,
+    traits: &'a T,
+    output_dir: &'a Path,
+    resumed_meta: Option<&'a ResumedMetadata>,
+    build_cache: Option<&'a build_cache::BuildCache>,
+    exe_names: &'a MetaExeNames,
+    /// The index of the current session in `Metadata::sessions`
+    session: usize,
+    num_configs: usize,
+}
+
+/// A build configuration ready to run.
+enum Prepared {
+    /// The results were saved by the interrupted run being resumed
+    Skipped(Box<MetaRun>),
+    Built(BuiltConfig),
+}
+
+struct BuiltConfig {
+    bo: matrix::BuildOpt,
+    build_args: Vec<String>,
+    build_key: Option<String>,
+    /// The built images copied to the output directory
+    secure_elf: PathBuf,
+    nonsecure_elf: PathBuf,
+}
+
+/// Build the `i`-th configuration unless its results are already saved, and
+/// copy the built images to the output directory.
+async fn prepare<T: AppTraits>(
+    ctx: &RunContext<'_, T>,
+    i: usize,
+    bo: &matrix::BuildOpt,
+    free_build_slots: &RefCell<Vec<usize>>,
+) -> Result<Prepared, Box<dyn Error>> {
+    let opt = ctx.opt;
+    log::info!("* Build option: {} ({} of {})", bo, i + 1, ctx.num_configs);
+
+    let build_args = zig_build_args(opt, ctx.traits, bo);
+    let build_key = ctx
+        .build_cache
+        .map(|cache| cache.key(&opt.zig_cmd, &build_args));
+
+    let secure_elf_copied = ctx
+        .output_dir
+        .join(format!("{}.{}", bo, ctx.exe_names.secure));
+    let nonsecure_elf_copied = ctx
+        .output_dir
+        .join(format!("{}.{}", bo, ctx.exe_names.non_secure));
+
+    if let Some(resumed_meta) = ctx.resumed_meta {
+        // The configuration may have been completed just before the
+        // interruption, in which case it's missing from the metadata
+        let recorded_run = resumed_meta
+            .matrix
+            .iter()
+            .find(|run| run.name == bo.to_string());
+        if recorded_run.is_some_and(|run| {
+            run.zig_build_args != build_args || run.repeat.unwrap_or(1) != opt.repeat
+        }) {
+            log::warn!(
+                "{}: The build flags or the number of runs have changed since the \
+                 interrupted run; running again",
+                bo
+            );
+        } else if has_valid_results(ctx.output_dir, ctx.traits, bo, opt.repeat).await {
+            log::info!("{}: Skipping because the results are already saved", bo);
+            return Ok(Prepared::Skipped(Box::new(MetaRun {
+                build_opt: bo.clone(),
+                name: bo.to_string(),
+                zig_build_args: build_args,
+                repeat: opt.repeat,
+                build_key,
+                sizes: measure_sizes(&secure_elf_copied, &nonsecure_elf_copied),
+                exe_sha256: hash_exes(&secure_elf_copied, &nonsecure_elf_copied).await,
+                session: recorded_run.and_then(|run| run.session),
+                runs: recorded_run.map(|run| run.runs.clone()).unwrap_or_default(),
+            })));
+        }
+    }
+
+    let exe_names = [&ctx.exe_names.secure[..], &ctx.exe_names.non_secure[..]];
+    let cached = ctx
+        .build_cache
+        .zip(build_key.as_ref())
+        .and_then(|(cache, key)| cache.lookup(key, &exe_names));
+
+    let (secure_elf, nonsecure_elf, build_slot) = if let Some(paths) = cached {
+        log::info!(
+            "{}: Using the cached build {}",
+            bo,
+            build_key.as_ref().unwrap()
         );
-        (r1?, r2?);
+        (paths[0].clone(), paths[1].clone(), None)
+    } else {
+        let build_slot = free_build_slots
+            .borrow_mut()
+            .pop()
+            .expect("no free build slots");
+        let (secure_elf, nonsecure_elf) =
+            build(opt, ctx.traits, bo, &build_args, build_slot).await?;
+
+        let (secure_elf, nonsecure_elf) = match ctx.build_cache.zip(build_key.as_ref()) {
+            Some((cache, key)) => {
+                log::debug!("Storing the build in the cache as {}", key);
+                let files = [
+                    (exe_names[0], secure_elf.as_path()),
+                    (exe_names[1], nonsecure_elf.as_path()),
+                ];
+                match cache.store(key, &files).await {
+                    Ok(paths) => (paths[0].clone(), paths[1].clone()),
+                    Err(e) => {
+                        log::warn!("Could not store the build in the cache: {}", e);
+                        (secure_elf, nonsecure_elf)
+                    }
+                }
+            }
+            None => (secure_elf, nonsecure_elf),
+        };
+        (secure_elf, nonsecure_elf, Some(build_slot))
+    };
+
+    // Copy the built ELF images
+    log::trace!(
+        "Copying {:?} and {:?} to {:?} and {:?} (respectively)",
+        secure_elf,
+        nonsecure_elf,
+        secure_elf_copied,
+        nonsecure_elf_copied,
+    );
+    let (r1, r2) = tokio::join!(
+        tokio::fs::copy(&secure_elf, &secure_elf_copied),
+        tokio::fs::copy(&nonsecure_elf, &nonsecure_elf_copied),
+    );
+    r1?;
+    r2?;
+
+    // The build directory can be reused now
+    if let Some(build_slot) = build_slot {
+        free_build_slots.borrow_mut().push(build_slot);
+    }
+
+    Ok(Prepared::Built(BuiltConfig {
+        bo: bo.clone(),
+        build_args,
+        build_key,
+        secure_elf: secure_elf_copied,
+        nonsecure_elf: nonsecure_elf_copied,
+    }))
+}
+
+/// Run a built configuration `opt.repeat` times and save the results.
+/// Returns the metadata of the configuration and the descriptions of the
+/// fields in the outputs.
+async fn run_built<T: AppTraits>(
+    ctx: &RunContext<'_, T>,
+    built: BuiltConfig,
+    free_targets: &RefCell<Vec<Box<dyn target::Target + '_>>>,
+) -> Result<(MetaRun, Vec<(String, String)>), Box<dyn Error>> {
+    let RunContext {
+        opt,
+        traits,
+        output_dir,
+        ..
+    } = *ctx;
+    let BuiltConfig {
+        bo,
+        build_args,
+        build_key,
+        secure_elf,
+        nonsecure_elf,
+    } = built;
+
+    let sizes = measure_sizes(&secure_elf, &nonsecure_elf);
+    let exe_sha256 = hash_exes(&secure_elf, &nonsecure_elf).await;
+
+    let symbolizer = if traits.has_pc_samples() {
+        symbolize::Symbolizer::load(&[("secure", &secure_elf), ("non_secure", &nonsecure_elf)])
+            .map_err(|e| log::warn!("The sampled program counters will not be symbolized: {}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    let mut target = free_targets.borrow_mut().pop().expect("no free targets");
+
+    let mut samples = Vec::new();
+    let mut descriptions = Vec::new();
+    let mut runs = Vec::new();
+    for k in 0..opt.repeat {
+        if opt.repeat > 1 {
+            log::info!("{}: Run {} of {}", bo, k + 1, opt.repeat);
+        }
+        let started_at = provenance::now();
 
         // Program the target board
-        log::info!("Programming the target board");
-        let t = AtomicRefCell::new(&mut target);
-        retry_on_fail(|| async { t.borrow_mut().program(&[&nonsecure_elf, &secure_elf]).await })
+        let program_attempts = if k == 0 || opt.reprogram {
+            log::info!("{}: Programming the target board", bo);
+            let t = AtomicRefCell::new(&mut target);
+            let ((), attempts) = retry_on_fail(|| async {
+                t.borrow_mut().program(&[&nonsecure_elf, &secure_elf]).await
+            })
             .await
             .map_err(RunBenchmarkError::ProgrammingError)?;
+            Some(attempts)
+        } else {
+            None
+        };
+
+        let stem = output_stem(&bo, k, opt.repeat);
 
         // Run the program
         let t = AtomicRefCell::new(&mut *target);
-        retry_on_fail(|| async {
-            log::info!("Running the program");
+        let (sample, run_attempts) = retry_on_fail(|| async {
+            log::info!("{}: Running the program", bo);
             let markers = [b"unhandled exception", traits.output_terminator()];
-            let output =
-                target::target_reset_and_get_output_until(*&mut *t.borrow_mut(), markers.iter())
-                    .await
-                    .map_err(|e| RunBenchmarkError::OutputAcquisitionError(e.into()))?;
+            let output = target::target_reset_and_get_output_until(*t.borrow_mut(), markers.iter())
+                .await
+                .map_err(|e| RunBenchmarkError::OutputAcquisitionError(e.into()))?;
 
             // Save the raw output
-            let save_path = output_dir.join(format!("{}.raw", bo));
+            let save_path = output_dir.join(format!("{}.raw", stem));
             log::info!("Saving the raw output to {:?}", save_path);
 
             tokio::fs::write(&save_path, &output)
@@ -197,40 +511,72 @@ pub(crate) async fn run(opt: &super::Opt, traits: impl AppTraits) -> Result<(),
 
             // Post-process the output
             log::info!("Post-processing the output");
-            if let Some(output) = traits
-                .process_output(&output)
-                .map_err(|e| RunBenchmarkError::ProcessOutputError(e))?
-            {
+            let output = process_raw_output(traits, &output)
+                .map_err(RunBenchmarkError::ProcessOutputError)?;
+            if let Some(output) = &output {
                 // Save the processed output
-                let save_path = output_dir.join(format!("{}.json", bo));
+                let save_path = output_dir.join(format!("{}.json", stem));
                 log::info!("Saving the result to {:?}", save_path);
 
-                tokio::fs::write(&save_path, output)
+                tokio::fs::write(&save_path, output.to_json())
                     .await
                     .map_err(|e| RunBenchmarkError::WriteOutputError(e.into()))?;
             } else {
                 log::info!("Post-processing yielded no results");
             }
 
-            Ok::<(), Box<dyn Error>>(())
+            Ok::<_, Box<dyn Error>>(output)
         })
         .await?;
 
-        meta.matrix.push(MetaRun {
-            build_opt: bo,
-            name: bo.to_string(),
-            zig_build_args: build_args,
+        if let (Some(symbolizer), Some(sample)) = (&symbolizer, &sample) {
+            let (annotated, histogram) = symbolize::symbolize(symbolizer, &sample.value);
+            for (suffix, value) in [("symbolized", annotated), ("histogram", histogram)].iter() {
+                let save_path = output_dir.join(format!("{}.{}.json", stem, suffix));
+                log::info!("Saving {:?}", save_path);
+                tokio::fs::write(&save_path, serde_json::to_string_pretty(value).unwrap())
+                    .await
+                    .map_err(|e| RunBenchmarkError::WriteOutputError(e.into()))?;
+            }
+        }
+
+        if let Some(sample) = sample {
+            descriptions.extend(sample.descriptions);
+            samples.push(sample.value);
+        }
+
+        runs.push(MetaSample {
+            started_at,
+            finished_at: provenance::now(),
+            program_attempts,
+            run_attempts,
         });
     }
 
-    let meta_path = output_dir.join("meta.json");
-    log::info!("Writing metadata to: {:?}", meta_path);
-    {
-        let json = serde_json::to_string_pretty(&meta).unwrap();
-        tokio::fs::write(&meta_path, json).await?;
+    // The target can be used for another configuration now
+    free_targets.borrow_mut().push(target);
+
+    if opt.repeat > 1 && !samples.is_empty() {
+        let save_path = output_dir.join(format!("{}.json", bo));
+        log::info!("Saving the statistics to {:?}", save_path);
+        let summary = summarize_samples(traits, &samples);
+        tokio::fs::write(&save_path, serde_json::to_string_pretty(&summary).unwrap())
+            .await
+            .map_err(|e| RunBenchmarkError::WriteOutputError(e.into()))?;
     }
 
-    Ok(())
+    let run = MetaRun {
+        name: bo.to_string(),
+        build_opt: bo,
+        zig_build_args: build_args,
+        repeat: opt.repeat,
+        build_key,
+        sizes,
+        exe_sha256,
+        session: Some(ctx.session),
+        runs,
+    };
+    Ok((run, descriptions))
 }
 
 #[derive(Debug, Error)]
@@ -252,6 +598,219 @@ enum RunBenchmarkError {
 
     #[error("Could not post-process the output.\n\n{0}")]
     ProcessOutputError(Box<dyn Error>),
+
+    #[error("Could not read the metadata of the run to resume.\n\n{0}")]
+    ReadMetadataError(Box<dyn Error>),
+
+    #[error("The run to resume was made for a different benchmark or target.")]
+    ResumeMismatch,
+
+    #[error("Could not write the metadata.\n\n{0}")]
+    WriteMetadataError(Box<dyn Error>),
+
+    #[error("Could not initialize the build cache.\n\n{0}")]
+    BuildCacheError(Box<dyn Error>),
+}
+
+/// Build the specified configuration. Returns the paths to the built Secure
+/// and Non-Secure images.
+///
+/// If more than one configuration is built at once, each of them is built in
+/// a separate directory specified by `build_slot` so that they don't
+/// interfere with each other.
+async fn build(
+    opt: &super::Opt,
+    traits: &impl AppTraits,
+    bo: &matrix::BuildOpt,
+    build_args: &[String],
+    build_slot: usize,
+) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    let mut build_args = build_args.to_vec();
+    let build_dir = if opt.jobs.get() > 1 {
+        let build_dir = opt
+            .zig_cache_dir
+            .join("runbench-jobs")
+            .join(build_slot.to_string());
+        build_args.push("--cache-dir".to_owned());
+        build_args.push(build_dir.join("cache").to_string_lossy().into_owned());
+        build_args.push(format!("-Doutput-dir={}", build_dir.to_string_lossy()));
+        build_dir
+    } else {
+        opt.zig_cache_dir.clone()
+    };
+
+    // The ELF images. `build.zig` writes the Secure image stamped with its
+    // identity to a separate file, which is the one to be programmed.
+    let secure_elf = build_dir.join("secure-stamped");
+    let nonsecure_elf = build_dir.join(traits.name());
+
+    // Delete the images just in case
+    log::trace!("Deleting {:?} and {:?}", secure_elf, nonsecure_elf);
+    let (r1, r2) = tokio::join!(
+        tokio::fs::remove_file(&secure_elf),
+        tokio::fs::remove_file(&nonsecure_elf),
+    );
+    let _ = (ignore_not_found(r1)?, ignore_not_found(r2)?);
+
+    // Build the benchmark
+    log::info!("{}: Building the program", bo);
+
+    subprocess::CmdBuilder::new(&opt.zig_cmd)
+        .args(build_args.iter())
+        .spawn_expecting_success()
+        .await?;
+
+    // Assert the existence of the built ELF images
+    log::trace!(
+        "Checking the existence of {:?} and {:?}",
+        secure_elf,
+        nonsecure_elf
+    );
+    if !secure_elf.exists() {
+        return Err(RunBenchmarkError::BuiltExeNotFound(secure_elf).into());
+    }
+    if !nonsecure_elf.exists() {
+        return Err(RunBenchmarkError::BuiltExeNotFound(nonsecure_elf).into());
+    }
+
+    Ok((secure_elf, nonsecure_elf))
+}
+
+async fn read_metadata(dir: &Path) -> Result<ResumedMetadata, Box<dyn Error>> {
+    let json = tokio::fs::read(dir.join("meta.json")).await?;
+    let json: serde_json::Value = serde_json::from_slice(&json)?;
+    let mut meta: ResumedMetadata = serde_json::from_value(json.clone())?;
+    if let Some(serde_json::Value::Array(raw_matrix)) = json.get("matrix") {
+        meta.raw_matrix = raw_matrix.clone();
+    }
+    Ok(meta)
+}
+
+/// Write the metadata. A temporary file is used so that `meta.json` stays
+/// intact if the program is interrupted while writing.
+async fn write_metadata(meta_path: &Path, meta: &Metadata) -> Result<(), RunBenchmarkError> {
+    let json = serde_json::to_string_pretty(meta).unwrap();
+    let temp_path = meta_path.with_extension("json.tmp");
+    async {
+        tokio::fs::write(&temp_path, json).await?;
+        tokio::fs::rename(&temp_path, meta_path).await
+    }
+    .await
+    .map_err(|e| RunBenchmarkError::WriteMetadataError(e.into()))
+}
+
+/// Get the file name stem for the output of the `k`-th (zero-based) run of
+/// the specified configuration.
+pub(crate) fn output_stem(bo: &impl std::fmt::Display, k: usize, repeat: usize) -> String {
+    if repeat == 1 {
+        bo.to_string()
+    } else {
+        format!("{}.{}", bo, k + 1)
+    }
+}
+
+/// Check if the outputs of the specified configuration are saved in
+/// `output_dir`, and the processed outputs match the raw outputs.
+async fn has_valid_results(
+    output_dir: &Path,
+    traits: &impl AppTraits,
+    bo: &matrix::BuildOpt,
+    repeat: usize,
+) -> bool {
+    let mut has_processed_output = false;
+    for k in 0..repeat {
+        let stem = output_stem(bo, k, repeat);
+        let raw = match tokio::fs::read(output_dir.join(format!("{}.raw", stem))).await {
+            Ok(raw) => raw,
+            Err(_) => return false,
+        };
+        let expected = match process_raw_output(traits, &raw) {
+            Ok(x) => x,
+            Err(e) => {
+                log::debug!("The saved raw output could not be post-processed: {}", e);
+                return false;
+            }
+        };
+        if let Some(expected) = expected {
+            let json_path = output_dir.join(format!("{}.json", stem));
+            if !tokio::fs::read(&json_path)
+                .await
+                .is_ok_and(|json| json == expected.to_json())
+            {
+                return false;
+            }
+            has_processed_output = true;
+        }
+    }
+
+    // The statistics
+    !(repeat > 1 && has_processed_output) || output_dir.join(format!("{}.json", bo)).exists()
+}
+
+/// Post-process the raw output and convert the result to strict JSON.
+fn process_raw_output(
+    traits: &impl AppTraits,
+    raw: &[u8],
+) -> Result<Option<output::ParsedOutput>, Box<dyn Error>> {
+    match traits.process_output(raw)? {
+        Some(output) => Ok(Some(output::parse(&output, traits.schema())?)),
+        None => Ok(None),
+    }
+}
+
+/// Calculate the statistics of the processed outputs of the runs of a
+/// configuration.
+fn summarize_samples(traits: &impl AppTraits, samples: &[serde_json::Value]) -> serde_json::Value {
+    let stats = stats::summarize(samples, |path| traits.is_metric(path));
+
+    serde_json::json!({
+        "repeat": samples.len(),
+        "stats": stats,
+    })
+}
+
+/// Measure the code size of the built images. Returns `None` on failure,
+/// which is not fatal.
+fn measure_sizes(secure_elf: &Path, nonsecure_elf: &Path) -> Option<size::Sizes> {
+    match size::Sizes::measure(secure_elf, nonsecure_elf) {
+        Ok(sizes) => {
+            log::info!(
+                "Code size: .text = {} (Secure) + {} (Non-Secure), NSC = {}",
+                sizes.secure.text,
+                sizes.non_secure.text,
+                sizes.secure.nsc
+            );
+            Some(sizes)
+        }
+        Err(e) => {
+            log::warn!("Could not measure the code size: {}", e);
+            None
+        }
+    }
+}
+
+/// Calculate the SHA-256 hashes of the built images. Returns `None` on
+/// failure, which is not fatal.
+async fn hash_exes(secure_elf: &Path, nonsecure_elf: &Path) -> Option<MetaExeHashes> {
+    let (secure, non_secure) = tokio::join!(
+        provenance::sha256_file(secure_elf),
+        provenance::sha256_file(nonsecure_elf),
+    );
+    match (secure, non_secure) {
+        (Ok(secure), Ok(non_secure)) => Some(MetaExeHashes { secure, non_secure }),
+        (Err(e), _) | (_, Err(e)) => {
+            log::warn!("Could not hash the executables: {}", e);
+            None
+        }
+    }
+}
+
+/// Get the arguments to pass to `zig` to build the specified configuration.
+fn zig_build_args(opt: &super::Opt, traits: &impl AppTraits, bo: &matrix::BuildOpt) -> Vec<String> {
+    let mut build_args = vec!["build".to_owned(), format!("build:{}", traits.name())];
+    build_args.extend(bo.zig_build_flags().iter().cloned());
+    build_args.extend(opt.target().zig_build_flags().iter().map(|&f| f.to_owned()));
+    build_args
 }
 
 fn ignore_not_found(r: Result<(), std::io::Error>) -> Result<(), std::io::Error> {
@@ -267,10 +826,44 @@ struct Metadata {
     benchmark: super::BenchmarkType,
     target: super::TargetType,
     exe_names: MetaExeNames,
-    matrix: Vec<MetaRun>,
+    /// The environments in which the results were produced. There's more
+    /// than one session if the run was resumed.
+    sessions: Vec<provenance::Session>,
+    /// `MetaRun`s, including those carried over verbatim from the resumed run
+    matrix: Vec<serde_json::Value>,
+    /// The descriptions of the fields in the processed outputs, taken from
+    /// the comments in the outputs
+    descriptions: BTreeMap<String, String>,
 }
 
-#[derive(Serialize)]
+/// The part of `Metadata` needed to resume a run.
+#[derive(Deserialize)]
+struct ResumedMetadata {
+    benchmark: super::BenchmarkType,
+    target: super::TargetType,
+    matrix: Vec<ResumedMetaRun>,
+    /// The elements of `matrix` as they are recorded
+    #[serde(skip)]
+    raw_matrix: Vec<serde_json::Value>,
+    #[serde(default)]
+    descriptions: BTreeMap<String, String>,
+    #[serde(default)]
+    sessions: Vec<provenance::Session>,
+}
+
+#[derive(Deserialize)]
+struct ResumedMetaRun {
+    name: String,
+    zig_build_args: Vec<String>,
+    /// Missing in the metadata written by older versions
+    repeat: Option<usize>,
+    #[serde(default)]
+    session: Option<usize>,
+    #[serde(default)]
+    runs: Vec<MetaSample>,
+}
+
+#[derive(Clone, Serialize)]
 struct MetaExeNames {
     secure: String,
     non_secure: String,
@@ -278,27 +871,67 @@ struct MetaExeNames {
 
 #[derive(Serialize)]
 struct MetaRun {
-    build_opt: BuildOpt,
+    build_opt: matrix::BuildOpt,
     name: String,
     zig_build_args: Vec<String>,
+    /// The number of runs
+    repeat: usize,
+    /// The key of the build in the build cache. `None` if the cache is
+    /// disabled.
+    build_key: Option<String>,
+    /// The code size of the built images. `None` if it couldn't be measured.
+    sizes: Option<size::Sizes>,
+    /// The SHA-256 hashes of the built images. `None` if they couldn't be
+    /// calculated.
+    exe_sha256: Option<MetaExeHashes>,
+    /// The index of the session (in `Metadata::sessions`) which produced the
+    /// results. `None` if unknown.
+    session: Option<usize>,
+    runs: Vec<MetaSample>,
+}
+
+#[derive(Serialize)]
+struct MetaExeHashes {
+    secure: String,
+    non_secure: String,
+}
+
+/// A single run of a build configuration.
+#[derive(Clone, Serialize, Deserialize)]
+struct MetaSample {
+    /// RFC 3339
+    started_at: String,
+    /// RFC 3339
+    finished_at: String,
+    /// The number of attempts made to program the target. `None` if the
+    /// target wasn't programmed for this run.
+    program_attempts: Option<u32>,
+    /// The number of attempts made to run the program and process the output
+    run_attempts: u32,
 }
 
-async fn retry_on_fail<R, T, E: std::fmt::Debug>(mut f: impl FnMut() -> R) -> Result<T, E>
+/// Call `f` until it succeeds, up to three times. Returns the result and the
+/// number of attempts made.
+async fn retry_on_fail<R, T, E: std::fmt::Debug>(mut f: impl FnMut() -> R) -> Result<(T, u32), E>
 where
     R: Future<Output = Result<T, E>>,
 {
-    let mut count = 3u32;
+    const MAX_ATTEMPTS: u32 = 3;
+    let mut attempts = 0;
     loop {
+        attempts += 1;
         match f().await {
-            Ok(x) => return Ok(x),
+            Ok(x) => return Ok((x, attempts)),
             Err(e) => {
                 log::warn!("Attempt failed: {:?}", e);
-                count -= 1;
-                if count == 0 {
+                if attempts == MAX_ATTEMPTS {
                     log::warn!("Retry limit reached");
                     return Err(e);
                 } else {
-                    log::warn!("Retrying... (remaining count = {:?})", count);
+                    log::warn!(
+                        "Retrying... (remaining count = {:?})",
+                        MAX_ATTEMPTS - attempts
+                    );
                 }
             }
         }
@@ -2,37 +2,27 @@ use regex::bytes::Regex;
 use std::error::Error;
 use thiserror::Error;
 
+use crate::output::Schema;
+
 pub(crate) struct BenchCoreMarkTraits;
 
 impl super::AppTraits for BenchCoreMarkTraits {
-    fn should_use_shadow_exception_stacks(&self) -> bool {
-        false
-    }
-
-    fn should_use_shadow_stacks(&self) -> bool {
-        true
+    fn name(&self) -> String {
+        "bench-coremark".to_string()
     }
 
-    fn should_use_context_management(&self) -> bool {
-        false
+    fn output_terminator(&self) -> &[u8] {
+        b"* portable_fini - system halted"
     }
 
-    fn should_use_accel_raise_pri(&self) -> bool {
-        false
+    fn schema(&self) -> &'static Schema {
+        &Schema::Object(&[("score", Schema::Number)])
     }
 
-    fn should_use_icall_sanitizer(&self) -> bool {
+    fn higher_is_better(&self, _metric: &str) -> bool {
         true
     }
 
-    fn name(&self) -> String {
-        "bench-coremark".to_string()
-    }
-
-    fn output_terminator(&self) -> &[u8] {
-        b"* portable_fini - system halted"
-    }
-
     fn process_output(&self, output: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
         if let Some(m) = ERROR_RE.captures(output) {
             Err(CoreMarkError(String::from_utf8_lossy(&m[1]).to_string()).into())
@@ -1,27 +1,36 @@
+use crate::{output::Schema, stats::Summary};
+
 pub(crate) struct BenchLatencyTraits;
 
 impl super::AppTraits for BenchLatencyTraits {
-    fn should_use_shadow_exception_stacks(&self) -> bool {
-        true
-    }
-
-    fn should_use_shadow_stacks(&self) -> bool {
-        false
-    }
-
-    fn should_use_context_management(&self) -> bool {
-        false
+    fn name(&self) -> String {
+        "bench-latency".to_string()
     }
 
-    fn should_use_accel_raise_pri(&self) -> bool {
-        false
+    fn schema(&self) -> &'static Schema {
+        &Schema::Array(&Schema::Object(&[
+            ("cycles", Schema::Number),
+            ("sp", Schema::Number),
+            ("delay", Schema::Number),
+        ]))
     }
 
-    fn should_use_icall_sanitizer(&self) -> bool {
-        false
+    fn is_metric(&self, path: &str) -> bool {
+        // `delay` is a parameter, and `sp` is for diagnostics
+        path.ends_with("/cycles")
     }
 
-    fn name(&self) -> String {
-        "bench-latency".to_string()
+    fn condense_metrics(&self, metrics: Vec<(String, f64)>) -> Vec<(String, f64)> {
+        // Each element corresponds to a different timer skew. Reports are
+        // interested in the worst case.
+        let cycles: Vec<f64> = metrics.iter().map(|&(_, x)| x).collect();
+        match Summary::new(&cycles) {
+            Some(summary) => vec![
+                ("/cycles (min)".to_owned(), summary.min),
+                ("/cycles (mean)".to_owned(), summary.mean),
+                ("/cycles (max)".to_owned(), summary.max),
+            ],
+            None => Vec::new(),
+        }
     }
 }
@@ -1,27 +1,22 @@
+use crate::output::Schema;
+
 pub(crate) struct BenchRtosTraits;
 
 impl super::AppTraits for BenchRtosTraits {
-    fn should_use_shadow_exception_stacks(&self) -> bool {
-        true
-    }
-
-    fn should_use_shadow_stacks(&self) -> bool {
-        true
-    }
-
-    fn should_use_context_management(&self) -> bool {
-        true
-    }
-
-    fn should_use_accel_raise_pri(&self) -> bool {
-        true
-    }
-
-    fn should_use_icall_sanitizer(&self) -> bool {
-        true
-    }
-
     fn name(&self) -> String {
         "bench-rtos".to_string()
     }
+
+    fn schema(&self) -> &'static Schema {
+        &Schema::Object(&[
+            ("Overhead", Schema::Number),
+            ("NewTask", Schema::Number),
+            ("DelTask", Schema::Number),
+            ("NewTask+disp", Schema::Number),
+            ("DelTask+disp", Schema::Number),
+            ("SemTake", Schema::Number),
+            ("SemGive", Schema::Number),
+            ("SemGive+disp", Schema::Number),
+        ])
+    }
 }
@@ -1,27 +1,26 @@
+use crate::output::Schema;
+
 pub(crate) struct ProfileRtosTraits;
 
 impl super::AppTraits for ProfileRtosTraits {
-    fn should_use_shadow_exception_stacks(&self) -> bool {
-        true
+    fn name(&self) -> String {
+        "profile-rtos".to_string()
     }
 
-    fn should_use_shadow_stacks(&self) -> bool {
-        true
+    fn schema(&self) -> &'static Schema {
+        &Schema::Array(&Schema::Object(&[
+            ("cycles", Schema::Number),
+            ("pc", Schema::Number),
+            ("pc_hex", Schema::String),
+        ]))
     }
 
-    fn should_use_context_management(&self) -> bool {
-        true
+    fn is_metric(&self, _path: &str) -> bool {
+        // Sampled program counters aren't something to average
+        false
     }
 
-    fn should_use_accel_raise_pri(&self) -> bool {
+    fn has_pc_samples(&self) -> bool {
         true
     }
-
-    fn should_use_icall_sanitizer(&self) -> bool {
-        true
-    }
-
-    fn name(&self) -> String {
-        "profile-rtos".to_string()
-    }
 }
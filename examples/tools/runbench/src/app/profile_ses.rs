@@ -1,27 +1,27 @@
+use crate::output::Schema;
+
 pub(crate) struct ProfileSesTraits;
 
 impl super::AppTraits for ProfileSesTraits {
-    fn should_use_shadow_exception_stacks(&self) -> bool {
-        true
-    }
-
-    fn should_use_shadow_stacks(&self) -> bool {
-        false
-    }
-
-    fn should_use_context_management(&self) -> bool {
-        false
+    fn name(&self) -> String {
+        "profile-ses".to_string()
     }
 
-    fn should_use_accel_raise_pri(&self) -> bool {
-        false
+    fn schema(&self) -> &'static Schema {
+        &Schema::Array(&Schema::Object(&[
+            ("delay", Schema::Number),
+            ("cycles", Schema::Number),
+            ("pc", Schema::Number),
+            ("pc_hex", Schema::String),
+        ]))
     }
 
-    fn should_use_icall_sanitizer(&self) -> bool {
+    fn is_metric(&self, _path: &str) -> bool {
+        // Sampled program counters aren't something to average
         false
     }
 
-    fn name(&self) -> String {
-        "profile-ses".to_string()
+    fn has_pc_samples(&self) -> bool {
+        true
     }
 }
@@ -0,0 +1,382 @@
+//! A content-addressed cache of built images.
+//!
+//! A build is identified by the hash of the source files, the Zig command
+//! and its version, and the `zig build` arguments. Each cache entry is a
+//! directory named after the hash, containing the built ELF images. A build
+//! is skipped if its entry exists, so rerunning a matrix after changing only
+//! host-side code (such as runbench itself) doesn't rebuild anything.
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// The directories containing the files used to build the images, relative
+/// to the `examples` directory.
+const SOURCE_ROOTS: &[&str] = &[".", "../src", "../include", "../tools/mkimplib/src"];
+
+/// The individual files used to build the images, relative to the `examples`
+/// directory. `mkimplib` is built by `build.zig`, so its dependencies matter.
+/// A missing file is hashed as such.
+const SOURCE_FILES: &[&str] = &["../tools/mkimplib/Cargo.toml", "../Cargo.lock"];
+
+/// The directories under `SOURCE_ROOTS` not affecting the built images,
+/// relative to the `examples` directory.
+const EXCLUDED_DIRS: &[&str] = &["zig-cache", "runbench.artifacts", "tools"];
+
+#[derive(Error, Debug)]
+pub enum BuildCacheError {
+    #[error("{0:?} is not the `examples` directory (`build.zig` is missing)")]
+    NotExamplesDir(PathBuf),
+
+    #[error("The source directory {0:?} does not exist")]
+    MissingSourceRoot(PathBuf),
+}
+
+pub struct BuildCache {
+    dir: PathBuf,
+    /// The hash of the source files
+    source_digest: String,
+    /// The output of `zig version`
+    zig_version: String,
+}
+
+impl BuildCache {
+    /// Open the cache in `dir`, hashing the current source files in and
+    /// around `examples_dir` (the `examples` directory). The directories in
+    /// `excluded` (e.g., the output directory) are not hashed.
+    /// `zig_version` is the output of `zig version`.
+    pub fn new(
+        dir: PathBuf,
+        examples_dir: &Path,
+        excluded: &[&Path],
+        zig_version: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        if !examples_dir.join("build.zig").is_file() {
+            return Err(BuildCacheError::NotExamplesDir(examples_dir.to_owned()).into());
+        }
+
+        let mut excluded: Vec<PathBuf> = EXCLUDED_DIRS
+            .iter()
+            .map(|path| examples_dir.join(path))
+            .chain(excluded.iter().map(|path| path.to_path_buf()))
+            .chain(std::iter::once(dir.clone()))
+            .filter_map(|path| path.canonicalize().ok())
+            .collect();
+        excluded.sort();
+
+        let mut hasher = Sha256::new();
+        let mut num_files = 0;
+        for root in SOURCE_ROOTS.iter() {
+            let root = examples_dir.join(root);
+            if !root.is_dir() {
+                return Err(BuildCacheError::MissingSourceRoot(root).into());
+            }
+            hash_dir(&mut hasher, examples_dir, &root, &excluded, &mut num_files)?;
+        }
+        for file in SOURCE_FILES.iter() {
+            match std::fs::read(examples_dir.join(file)) {
+                Ok(contents) => {
+                    hash_file(&mut hasher, Path::new(file), &contents);
+                    num_files += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    hasher.update(file.as_bytes());
+                    hasher.update(b"\0missing\0");
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let source_digest = format!("{:x}", hasher.finalize());
+        log::debug!("Hashed {} source files: {}", num_files, source_digest);
+
+        Ok(Self {
+            dir,
+            source_digest,
+            zig_version: zig_version.to_owned(),
+        })
+    }
+
+    /// Get the key identifying the build made by the specified command.
+    pub fn key(&self, zig_cmd: &OsStr, build_args: &[String]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.source_digest.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(zig_cmd.to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self.zig_version.as_bytes());
+        for arg in build_args.iter() {
+            hasher.update(b"\0");
+            hasher.update(arg.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Get the paths to the cached images of the specified file names.
+    /// Returns `None` on a cache miss.
+    pub fn lookup(&self, key: &str, names: &[&str]) -> Option<Vec<PathBuf>> {
+        let entry = self.dir.join(key);
+        let paths: Vec<_> = names.iter().map(|name| entry.join(name)).collect();
+        if paths.iter().all(|path| path.is_file()) {
+            Some(paths)
+        } else {
+            None
+        }
+    }
+
+    /// Store the images (pairs of file names and paths) in the cache. Returns
+    /// the paths to the stored images.
+    pub async fn store(&self, key: &str, files: &[(&str, &Path)]) -> io::Result<Vec<PathBuf>> {
+        let entry = self.dir.join(key);
+
+        // Populate a temporary directory first so that incomplete entries
+        // are never seen
+        let temp_entry = self.dir.join(format!("{}.tmp", key));
+        let _ = tokio::fs::remove_dir_all(&temp_entry).await;
+        tokio::fs::create_dir_all(&temp_entry).await?;
+        for (name, path) in files.iter() {
+            tokio::fs::copy(path, temp_entry.join(name)).await?;
+        }
+
+        let _ = tokio::fs::remove_dir_all(&entry).await;
+        tokio::fs::rename(&temp_entry, &entry).await?;
+
+        Ok(files.iter().map(|(name, _)| entry.join(name)).collect())
+    }
+}
+
+/// Hash the files in `dir` recursively, in a deterministic order. Paths are
+/// hashed relative to `base`.
+fn hash_dir(
+    hasher: &mut Sha256,
+    base: &Path,
+    dir: &Path,
+    excluded: &[PathBuf],
+    num_files: &mut usize,
+) -> io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if path
+                .canonicalize()
+                .is_ok_and(|path| excluded.binary_search(&path).is_ok())
+            {
+                continue;
+            }
+            hash_dir(hasher, base, &path, excluded, num_files)?;
+        } else if file_type.is_file() {
+            let contents = std::fs::read(&path)?;
+            hash_file(hasher, path.strip_prefix(base).unwrap_or(&path), &contents);
+            *num_files += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn hash_file(hasher: &mut Sha256, rel_path: &Path, contents: &[u8]) {
+    hasher.update(rel_path.to_string_lossy().as_bytes());
+    hasher.update(b"\0");
+    hasher.update((contents.len() as u64).to_le_bytes());
+    hasher.update(contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TempDir;
+    use std::fs;
+
+    /// Create a source tree mimicking the repository. Returns the `examples`
+    /// directory.
+    fn source_tree(dir: &TempDir) -> PathBuf {
+        for (name, contents) in [
+            ("examples/build.zig", "build"),
+            ("examples/secure.zig", "secure"),
+            ("examples/zig-cache/cached.o", "cached"),
+            ("examples/tools/runbench/src/main.rs", "runbench"),
+            ("src/monitor.zig", "monitor"),
+            ("include/TZmCFI/Gateway.h", "gateway"),
+            ("tools/mkimplib/src/lib.rs", "mkimplib"),
+            ("tools/mkimplib/Cargo.toml", "manifest"),
+        ]
+        .iter()
+        {
+            write(dir, name, contents);
+        }
+        dir.path().join("examples")
+    }
+
+    fn write(dir: &TempDir, name: &str, contents: &str) {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn open(dir: &TempDir, examples_dir: &Path) -> Result<BuildCache, Box<dyn Error>> {
+        BuildCache::new(dir.path().join("cache"), examples_dir, &[], "0.6.0")
+    }
+
+    fn source_digest(dir: &TempDir, examples_dir: &Path) -> String {
+        open(dir, examples_dir).unwrap().source_digest
+    }
+
+    #[test]
+    fn reject_non_examples_dir() {
+        let dir = TempDir::new("build_cache_reject_non_examples_dir");
+        source_tree(&dir);
+        let e = open(&dir, dir.path()).err().unwrap();
+        assert!(matches!(
+            e.downcast_ref::<BuildCacheError>(),
+            Some(BuildCacheError::NotExamplesDir(_))
+        ));
+    }
+
+    #[test]
+    fn reject_missing_source_root() {
+        let dir = TempDir::new("build_cache_reject_missing_source_root");
+        let examples_dir = source_tree(&dir);
+        fs::remove_dir_all(dir.path().join("include")).unwrap();
+        let e = open(&dir, &examples_dir).err().unwrap();
+        assert!(matches!(
+            e.downcast_ref::<BuildCacheError>(),
+            Some(BuildCacheError::MissingSourceRoot(_))
+        ));
+    }
+
+    #[test]
+    fn hash_sources() {
+        let dir = TempDir::new("build_cache_hash_sources");
+        let examples_dir = source_tree(&dir);
+        let mut digest = source_digest(&dir, &examples_dir);
+
+        // Independent of the location of the tree
+        let other_dir = TempDir::new("build_cache_hash_sources_other");
+        let other_examples_dir = source_tree(&other_dir);
+        assert_eq!(source_digest(&other_dir, &other_examples_dir), digest);
+
+        for name in [
+            "examples/secure.zig",
+            "src/monitor.zig",
+            "include/TZmCFI/Gateway.h",
+            "tools/mkimplib/src/lib.rs",
+            "tools/mkimplib/Cargo.toml",
+            "Cargo.lock",
+        ]
+        .iter()
+        {
+            write(&dir, name, "changed");
+            let new_digest = source_digest(&dir, &examples_dir);
+            assert_ne!(new_digest, digest, "{}", name);
+            digest = new_digest;
+        }
+
+        for name in [
+            "examples/zig-cache/cached.o",
+            "examples/tools/runbench/src/main.rs",
+            "examples/.gitignore",
+            "cache/entry/secure.elf",
+            "README.md",
+        ]
+        .iter()
+        {
+            write(&dir, name, "changed");
+            assert_eq!(source_digest(&dir, &examples_dir), digest, "{}", name);
+        }
+
+        // Excluded explicitly
+        write(&dir, "examples/out/result.json", "{}");
+        let cache = BuildCache::new(
+            dir.path().join("cache"),
+            &examples_dir,
+            &[&examples_dir.join("out")],
+            "0.6.0",
+        )
+        .unwrap();
+        assert_eq!(cache.source_digest, digest);
+    }
+
+    #[test]
+    fn key() {
+        let key = |source_digest: &str, zig_version: &str, zig_cmd: &str, args: &[&str]| {
+            let cache = BuildCache {
+                dir: PathBuf::new(),
+                source_digest: source_digest.to_owned(),
+                zig_version: zig_version.to_owned(),
+            };
+            let args: Vec<_> = args.iter().map(|&arg| arg.to_owned()).collect();
+            cache.key(OsStr::new(zig_cmd), &args)
+        };
+        let args = ["-Dcfi-ctx", "-Drelease-fast"];
+        assert_eq!(
+            key("digest", "0.6.0", "zig", &args),
+            key("digest", "0.6.0", "zig", &args)
+        );
+
+        // Each input changes the key
+        let mut keys = vec![
+            key("digest", "0.6.0", "zig", &args),
+            key("other", "0.6.0", "zig", &args),
+            key("digest", "0.7.0", "zig", &args),
+            key("digest", "0.6.0", "zig-dev", &args),
+            key("digest", "0.6.0", "zig", &["-Dcfi-ctx"]),
+            key("digest", "0.6.0", "zig", &["-Drelease-fast", "-Dcfi-ctx"]),
+            key("digest", "0.6.0", "zig", &["-Dcfi-ctx-Drelease-fast"]),
+        ];
+        let num_keys = keys.len();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), num_keys);
+    }
+
+    #[tokio::test]
+    async fn store_and_lookup() {
+        let dir = TempDir::new("build_cache_store_and_lookup");
+        let cache = BuildCache {
+            dir: dir.path().join("cache"),
+            source_digest: String::new(),
+            zig_version: String::new(),
+        };
+        let secure = dir.write("secure.elf", "secure");
+        let non_secure = dir.write("app.elf", "non-secure");
+        let names = ["secure.elf", "app.elf"];
+
+        assert_eq!(cache.lookup("key", &names), None);
+
+        let stored = cache
+            .store("key", &[("secure.elf", &secure), ("app.elf", &non_secure)])
+            .await
+            .unwrap();
+        assert_eq!(cache.lookup("key", &names), Some(stored.clone()));
+        assert_eq!(fs::read_to_string(&stored[1]).unwrap(), "non-secure");
+        assert!(!cache.dir.join("key.tmp").exists());
+
+        // A partial entry is a miss
+        write(&dir, "cache/partial/secure.elf", "secure");
+        write(&dir, "cache/unfinished.tmp/secure.elf", "secure");
+        write(&dir, "cache/unfinished.tmp/app.elf", "non-secure");
+        assert_eq!(cache.lookup("partial", &names), None);
+        assert_eq!(cache.lookup("unfinished", &names), None);
+
+        // Replaces a partial entry
+        cache
+            .store(
+                "partial",
+                &[("secure.elf", &secure), ("app.elf", &non_secure)],
+            )
+            .await
+            .unwrap();
+        assert!(cache.lookup("partial", &names).is_some());
+    }
+}
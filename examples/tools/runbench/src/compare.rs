@@ -0,0 +1,223 @@
+//! The `compare` subcommand, which detects performance regressions between
+//! two artifacts directories.
+use std::{error::Error, path::PathBuf};
+use structopt::StructOpt;
+use thiserror::Error;
+
+use super::report::{format_overhead, format_value, metric, Results};
+
+#[derive(StructOpt)]
+pub struct CompareOpt {
+    /// The artifacts directory of the old run
+    #[structopt(parse(from_os_str))]
+    old_dir: PathBuf,
+
+    /// The artifacts directory of the new run
+    #[structopt(parse(from_os_str))]
+    new_dir: PathBuf,
+
+    /// A metric is considered changed if it changed by more than this
+    /// percentage. Defaults to 5 unless `--threshold-abs` is given. A change
+    /// from zero exceeds any percentage
+    #[structopt(long = "threshold-percent")]
+    threshold_percent: Option<f64>,
+
+    /// A metric is considered changed if it changed by more than this amount
+    /// (e.g., cycles). When given, this alone decides whether a change from
+    /// zero counts
+    #[structopt(long = "threshold-abs")]
+    threshold_abs: Option<f64>,
+}
+
+impl CompareOpt {
+    /// Check if the change from `old_value` by `delta` exceeds either of the
+    /// thresholds.
+    fn exceeds_thresholds(&self, old_value: f64, delta: f64) -> bool {
+        let threshold_percent = match (self.threshold_percent, self.threshold_abs) {
+            (None, None) => Some(5.0),
+            (threshold_percent, _) => threshold_percent,
+        };
+
+        let exceeds_abs = self.threshold_abs.is_some_and(|t| delta.abs() > t);
+        let exceeds_percent = threshold_percent.is_some_and(|t| {
+            if old_value == 0.0 {
+                // The percentage is infinite. Leave it to `threshold_abs` if
+                // given.
+                self.threshold_abs.is_none()
+            } else {
+                (delta / old_value).abs() * 100.0 > t
+            }
+        });
+
+        exceeds_abs || exceeds_percent
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CompareError {
+    #[error("The artifacts directories are for different benchmarks ({0} and {1})")]
+    BenchmarkMismatch(String, String),
+
+    #[error("The artifacts directories have no build configurations in common")]
+    NoCommonConfigs,
+
+    #[error("{0} metric(s) regressed")]
+    Regressed(usize),
+}
+
+struct Change<'a> {
+    config: &'a str,
+    metric: &'a str,
+    old: f64,
+    new: f64,
+    is_regression: bool,
+}
+
+pub fn run(opt: &CompareOpt) -> Result<(), Box<dyn Error>> {
+    let old = Results::load(&opt.old_dir)?;
+    let new = Results::load(&opt.new_dir)?;
+
+    if old.benchmark != new.benchmark {
+        return Err(CompareError::BenchmarkMismatch(
+            old.benchmark.to_string(),
+            new.benchmark.to_string(),
+        )
+        .into());
+    }
+
+    let traits = new.benchmark.traits();
+
+    let mut num_compared = 0;
+    let mut changes = Vec::new();
+    for (config, new_metrics) in new.configs.iter() {
+        let old_metrics = match old.config(config) {
+            Some(x) => x,
+            None => {
+                log::warn!("'{}' is missing from the old run", config);
+                continue;
+            }
+        };
+        num_compared += 1;
+
+        for (metric_name, new_value) in new_metrics.iter() {
+            let old_value = match metric(old_metrics, metric_name) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let delta = new_value - old_value;
+            if delta == 0.0 || !opt.exceeds_thresholds(old_value, delta) {
+                continue;
+            }
+
+            changes.push(Change {
+                config,
+                metric: metric_name,
+                old: old_value,
+                new: *new_value,
+                is_regression: (delta > 0.0) != traits.higher_is_better(metric_name),
+            });
+        }
+    }
+
+    for (config, _) in old.configs.iter() {
+        if new.config(config).is_none() {
+            log::warn!("'{}' is missing from the new run", config);
+        }
+    }
+
+    if num_compared == 0 {
+        return Err(CompareError::NoCommonConfigs.into());
+    }
+
+    let num_regressions = changes.iter().filter(|c| c.is_regression).count();
+    log::info!(
+        "Compared {} build configuration(s): {} regression(s), {} improvement(s)",
+        num_compared,
+        num_regressions,
+        changes.len() - num_regressions
+    );
+
+    if !changes.is_empty() {
+        println!("| Configuration | Metric | Old | New | Change | |");
+        println!("|:--|:--|--:|--:|--:|:--|");
+        for change in changes.iter() {
+            let delta = change.new - change.old;
+            let relative = if change.old == 0.0 {
+                None
+            } else {
+                Some(delta / change.old)
+            };
+            let (absolute, relative) = format_overhead(delta, relative);
+            println!(
+                "| `{}` | {} | {} | {} | {} | {} |",
+                change.config,
+                change.metric,
+                format_value(change.old),
+                format_value(change.new),
+                if relative.is_empty() {
+                    absolute
+                } else {
+                    format!("{}, {}", absolute, relative)
+                },
+                if change.is_regression {
+                    "regression"
+                } else {
+                    "improvement"
+                }
+            );
+        }
+    }
+
+    if num_regressions > 0 {
+        Err(CompareError::Regressed(num_regressions).into())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(threshold_percent: Option<f64>, threshold_abs: Option<f64>) -> CompareOpt {
+        CompareOpt {
+            old_dir: PathBuf::new(),
+            new_dir: PathBuf::new(),
+            threshold_percent,
+            threshold_abs,
+        }
+    }
+
+    #[test]
+    fn default_percentage() {
+        let opt = opt(None, None);
+        assert!(opt.exceeds_thresholds(100.0, 6.0));
+        assert!(!opt.exceeds_thresholds(100.0, -5.0));
+        assert!(opt.exceeds_thresholds(0.0, 1.0));
+    }
+
+    #[test]
+    fn thresholds_trigger_independently() {
+        let opt = opt(Some(10.0), Some(50.0));
+        // Only the percentage is exceeded
+        assert!(opt.exceeds_thresholds(100.0, 20.0));
+        // Only the absolute change is exceeded
+        assert!(opt.exceeds_thresholds(10000.0, 60.0));
+        assert!(!opt.exceeds_thresholds(10000.0, 40.0));
+    }
+
+    #[test]
+    fn abs_only() {
+        let opt = opt(None, Some(50.0));
+        assert!(!opt.exceeds_thresholds(1.0, 40.0));
+        assert!(opt.exceeds_thresholds(1.0, 60.0));
+    }
+
+    #[test]
+    fn change_from_zero() {
+        assert!(opt(Some(5.0), None).exceeds_thresholds(0.0, 1.0));
+        assert!(!opt(Some(5.0), Some(10.0)).exceeds_thresholds(0.0, 10.0));
+        assert!(opt(Some(5.0), Some(10.0)).exceeds_thresholds(0.0, 11.0));
+    }
+}
@@ -0,0 +1,373 @@
+//! Boolean expressions to select a subset of build configurations
+//! (`--filter`).
+//!
+//! ```text
+//! expr  := and ( ("or" | "||") and )*
+//! and   := not ( ("and" | "&&") not )*
+//! not   := ("not" | "!") not | atom
+//! atom  := "(" expr ")" | AXIS | AXIS "=" VALUE | AXIS "!=" VALUE
+//! ```
+//!
+//! `AXIS` alone tests if the axis is enabled, i.e., its value is none of
+//! `false`, `0`, `none`, and `off`. For example, `ses=safe and ss and not
+//! icall` selects the configurations using safe shadow exception stacks and
+//! shadow stacks (either aborting or not) but not the icall sanitizer.
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+use super::matrix::{BuildOpt, Matrix, MatrixError, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Enabled(String),
+    Eq(String, String),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid filter expression: {0}")]
+pub struct ParseFilterError(String);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Eq,
+    Ne,
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "'{}'", word),
+            Self::Eq => f.write_str("'='"),
+            Self::Ne => f.write_str("'!='"),
+            Self::Not => f.write_str("'not'"),
+            Self::And => f.write_str("'and'"),
+            Self::Or => f.write_str("'or'"),
+            Self::Open => f.write_str("'('"),
+            Self::Close => f.write_str("')'"),
+        }
+    }
+}
+
+/// Split `s` into tokens, each paired with its byte offset in `s`.
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, ParseFilterError> {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || "_-.".contains(c);
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let offset = s.len() - rest.len();
+        let (token, len) = if rest.starts_with("!=") {
+            (Token::Ne, 2)
+        } else if rest.starts_with("&&") {
+            (Token::And, 2)
+        } else if rest.starts_with("||") {
+            (Token::Or, 2)
+        } else if c == '=' {
+            (Token::Eq, 1)
+        } else if c == '!' {
+            (Token::Not, 1)
+        } else if c == '(' {
+            (Token::Open, 1)
+        } else if c == ')' {
+            (Token::Close, 1)
+        } else if is_word_char(c) {
+            let len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+            let token = match &rest[..len] {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                word => Token::Word(word.to_owned()),
+            };
+            (token, len)
+        } else {
+            return Err(ParseFilterError(format!(
+                "unexpected character {:?} at offset {}",
+                c, offset
+            )));
+        };
+        tokens.push((offset, token));
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser.
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<(usize, Token)>>,
+    /// The length of the input, reported as the offset of the end
+    len: usize,
+}
+
+impl Parser {
+    /// The offset of the next token.
+    fn offset(&mut self) -> usize {
+        self.tokens.peek().map_or(self.len, |&(offset, _)| offset)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.peek().map(|(_, t)| t) == Some(token) {
+            self.tokens.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn word(&mut self) -> Result<String, ParseFilterError> {
+        match self.tokens.next() {
+            Some((_, Token::Word(word))) => Ok(word),
+            Some((offset, token)) => Err(ParseFilterError(format!(
+                "expected a name or value at offset {}, found {}",
+                offset, token
+            ))),
+            None => Err(ParseFilterError(format!(
+                "expected a name or value at offset {}, found the end",
+                self.len
+            ))),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Filter, ParseFilterError> {
+        let mut lhs = self.and()?;
+        while self.eat(&Token::Or) {
+            lhs = Filter::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Filter, ParseFilterError> {
+        let mut lhs = self.not()?;
+        while self.eat(&Token::And) {
+            lhs = Filter::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Filter, ParseFilterError> {
+        if self.eat(&Token::Not) {
+            Ok(Filter::Not(Box::new(self.not()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<Filter, ParseFilterError> {
+        let open_offset = self.offset();
+        if self.eat(&Token::Open) {
+            let inner = self.expr()?;
+            if !self.eat(&Token::Close) {
+                return Err(ParseFilterError(format!(
+                    "unclosed '(' at offset {}",
+                    open_offset
+                )));
+            }
+            return Ok(inner);
+        }
+
+        let axis = self.word()?;
+        if self.eat(&Token::Eq) {
+            Ok(Filter::Eq(axis, self.word()?))
+        } else if self.eat(&Token::Ne) {
+            Ok(Filter::Not(Box::new(Filter::Eq(axis, self.word()?))))
+        } else {
+            Ok(Filter::Enabled(axis))
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?.into_iter().peekable(),
+            len: s.len(),
+        };
+        let filter = parser.expr()?;
+        if let Some((offset, token)) = parser.tokens.next() {
+            return Err(ParseFilterError(format!(
+                "unexpected {} at offset {}",
+                token, offset
+            )));
+        }
+        Ok(filter)
+    }
+}
+
+impl Filter {
+    /// Check the axes and values referenced by the expression.
+    pub fn validate(&self, matrix: &Matrix) -> Result<(), MatrixError> {
+        match self {
+            Self::Enabled(axis) => matrix.check_axis(axis),
+            Self::Eq(axis, value) => matrix.check_value_str(axis, value),
+            Self::Not(x) => x.validate(matrix),
+            Self::And(x, y) | Self::Or(x, y) => {
+                x.validate(matrix)?;
+                y.validate(matrix)
+            }
+        }
+    }
+
+    pub fn matches(&self, bo: &BuildOpt) -> bool {
+        self.eval(&|axis| bo.value(axis))
+    }
+
+    /// Evaluate the expression, looking up the value of each axis by
+    /// `value`. An axis without a value is neither enabled nor equal to
+    /// anything.
+    pub fn eval<'a, F>(&self, value: &F) -> bool
+    where
+        F: Fn(&str) -> Option<&'a Value>,
+    {
+        match self {
+            Self::Enabled(axis) => value(axis).is_some_and(is_enabled),
+            Self::Eq(axis, x) => value(axis).is_some_and(|v| v.to_string() == *x),
+            Self::Not(x) => !x.eval(value),
+            Self::And(x, y) => x.eval(value) && y.eval(value),
+            Self::Or(x, y) => x.eval(value) || y.eval(value),
+        }
+    }
+}
+
+fn is_enabled(value: &Value) -> bool {
+    match value {
+        Value::Bool(x) => *x,
+        Value::Int(x) => *x != 0,
+        Value::String(x) => x != "none" && x != "off",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled(axis: &str) -> Filter {
+        Filter::Enabled(axis.to_owned())
+    }
+
+    fn eq(axis: &str, value: &str) -> Filter {
+        Filter::Eq(axis.to_owned(), value.to_owned())
+    }
+
+    fn not(x: Filter) -> Filter {
+        Filter::Not(Box::new(x))
+    }
+
+    fn and(x: Filter, y: Filter) -> Filter {
+        Filter::And(Box::new(x), Box::new(y))
+    }
+
+    fn or(x: Filter, y: Filter) -> Filter {
+        Filter::Or(Box::new(x), Box::new(y))
+    }
+
+    fn parse(s: &str) -> Filter {
+        s.parse().unwrap()
+    }
+
+    fn parse_error(s: &str) -> String {
+        s.parse::<Filter>().unwrap_err().0
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("a or b and c"),
+            or(enabled("a"), and(enabled("b"), enabled("c")))
+        );
+        assert_eq!(
+            parse("a && b || c"),
+            or(and(enabled("a"), enabled("b")), enabled("c"))
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(parse("not a and b"), and(not(enabled("a")), enabled("b")));
+        assert_eq!(parse("!!a || b"), or(not(not(enabled("a"))), enabled("b")));
+    }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(
+            parse("a or b or c"),
+            or(or(enabled("a"), enabled("b")), enabled("c"))
+        );
+        assert_eq!(
+            parse("a and b and c"),
+            and(and(enabled("a"), enabled("b")), enabled("c"))
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(
+            parse("(a or b) and c"),
+            and(or(enabled("a"), enabled("b")), enabled("c"))
+        );
+        assert_eq!(parse("not (a and b)"), not(and(enabled("a"), enabled("b"))));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(
+            parse("ses=safe and ss!=false"),
+            and(eq("ses", "safe"), not(eq("ss", "false")))
+        );
+        assert_eq!(parse("not ses = none"), not(eq("ses", "none")));
+    }
+
+    #[test]
+    fn error_offsets() {
+        assert_eq!(
+            parse_error("a and $"),
+            "unexpected character '$' at offset 6"
+        );
+        assert_eq!(
+            parse_error("a and"),
+            "expected a name or value at offset 5, found the end"
+        );
+        assert_eq!(
+            parse_error("a = or b"),
+            "expected a name or value at offset 4, found 'or'"
+        );
+        assert_eq!(parse_error("a (b)"), "unexpected '(' at offset 2");
+        assert_eq!(parse_error("x or (a and b"), "unclosed '(' at offset 5");
+        assert_eq!(parse_error("a)"), "unexpected ')' at offset 1");
+    }
+
+    #[test]
+    fn eval() {
+        let values = [
+            ("ctx", Value::Bool(true)),
+            ("ses", Value::String("safe".to_owned())),
+            ("ss", Value::String("off".to_owned())),
+            ("rom_offset", Value::Int(0)),
+        ];
+        let value = |axis: &str| {
+            values
+                .iter()
+                .find(|(name, _)| *name == axis)
+                .map(|(_, value)| value)
+        };
+        let eval = |s: &str| parse(s).eval(&value);
+
+        assert!(eval("ctx and ses"));
+        assert!(!eval("ss or rom_offset"));
+        assert!(eval("ses=safe and rom_offset=0 and ctx=true"));
+        assert!(eval("ses!=none && !(ss=aborting)"));
+        // An axis without a value
+        assert!(!eval("icall"));
+        assert!(!eval("icall=false"));
+        assert!(eval("icall!=false"));
+    }
+}
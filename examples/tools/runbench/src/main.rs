@@ -1,32 +1,96 @@
-use serde::Serialize;
-use std::{error::Error, ffi::OsString, fmt, path::PathBuf};
-use structopt::StructOpt;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, ffi::OsString, path::PathBuf};
+use structopt::{
+    clap::{self, AppSettings},
+    StructOpt,
+};
 use thiserror::Error;
 
 mod app;
+mod build_cache;
+mod compare;
+mod filter;
+mod matrix;
+mod output;
+mod provenance;
+mod report;
+mod size;
+mod stats;
 mod subprocess;
+mod symbolize;
 mod target;
+#[cfg(test)]
+mod testdir;
 
 /// Runs a benchmark automatically under various build configurations.
 ///
 /// Note: This program must be run in the `examples` directory.
 #[derive(StructOpt)]
-#[structopt(name = "tzmcfi_runbench")]
+#[structopt(
+    name = "tzmcfi_runbench",
+    setting = AppSettings::ArgsNegateSubcommands
+)]
 struct Opt {
-    /// Benchmark to run
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// Benchmark to run. Required unless a subcommand is given
     #[structopt(
         possible_values(&BenchmarkType::variants()), case_insensitive = true
     )]
-    benchmark: BenchmarkType,
+    benchmark: Option<BenchmarkType>,
 
-    /// Target to run the benchmark on
+    /// Target to run the benchmark on. Required unless a subcommand is
+    /// given
     #[structopt(
         short = "t", long = "target",
         possible_values(&TargetType::variants()), case_insensitive = true
     )]
-    target: TargetType,
+    target: Option<TargetType>,
 
-    /// Include `-Drom-offset=N` in the test condition set
+    /// Path to the build matrix file (TOML or JSON), which describes the
+    /// build configurations to test
+    #[structopt(
+        long = "matrix",
+        default_value = "runbench-matrix.toml",
+        parse(from_os_str)
+    )]
+    matrix_path: PathBuf,
+
+    /// Test all values of the specified axis of the build matrix instead of
+    /// its `default_values`
+    #[structopt(long = "vary", number_of_values = 1)]
+    vary: Vec<String>,
+
+    /// Only test the build configurations matching the specified boolean
+    /// expression over the axes of the build matrix, e.g.,
+    /// `"ses=safe and ss and not icall"`
+    #[structopt(long = "filter")]
+    filter: Option<filter::Filter>,
+
+    /// Print the build configurations to test and the `zig build` command
+    /// lines to build them, and exit without running the benchmark
+    #[structopt(long = "list")]
+    list: bool,
+
+    /// Run each build configuration the specified number of times. The
+    /// statistics of the samples are saved in addition to the samples
+    #[structopt(long = "repeat", default_value = "1")]
+    repeat: usize,
+
+    /// Build the specified number of configurations at once, each in a
+    /// separate directory in the `zig-cache` directory. On QEMU, the
+    /// configurations are also run in parallel
+    #[structopt(short = "j", long = "jobs", default_value = "1")]
+    jobs: std::num::NonZeroUsize,
+
+    /// Program the target board before every run instead of once per build
+    /// configuration (only meaningful with `--repeat`)
+    #[structopt(long = "reprogram")]
+    reprogram: bool,
+
+    /// Include `-Drom-offset=N` in the test condition set. Equivalent to
+    /// `--vary rom_offset`
     #[structopt(long = "vary-rom-offset")]
     vary_rom_offset: bool,
 
@@ -40,6 +104,15 @@ struct Opt {
     )]
     zig_cache_dir: PathBuf,
 
+    /// Path to the directory to cache built images in. Defaults to
+    /// `runbench` in the `zig-cache` directory
+    #[structopt(long = "build-cache-dir", parse(from_os_str))]
+    build_cache_dir: Option<PathBuf>,
+
+    /// Always build the images instead of using the cached ones
+    #[structopt(long = "no-build-cache", conflicts_with = "build-cache-dir")]
+    no_build_cache: bool,
+
     /// Path to save results in
     #[structopt(
         short = "o",
@@ -48,6 +121,16 @@ struct Opt {
     )]
     output_dir_template: String,
 
+    /// Resume an interrupted run whose results are saved in the specified
+    /// directory. The build configurations whose results are already saved
+    /// are skipped
+    #[structopt(
+        long = "resume",
+        parse(from_os_str),
+        conflicts_with = "output-dir-template"
+    )]
+    resume: Option<PathBuf>,
+
     /// Command to invoke the Zig compiler
     #[structopt(long = "zig", default_value = "zig", parse(from_os_str), env = "ZIG")]
     zig_cmd: OsString,
@@ -79,7 +162,68 @@ struct Opt {
     qemu_system_arm_cmd: OsString,
 }
 
+#[derive(StructOpt)]
+enum Command {
+    /// Summarize the results in an artifacts directory in a table
+    #[structopt(name = "report")]
+    Report(report::ReportOpt),
+
+    /// Compare the results in two artifacts directories and fail if any
+    /// metrics regressed
+    #[structopt(name = "compare")]
+    Compare(compare::CompareOpt),
+
+    /// Resolve the program counters sampled by `profile-ses` or
+    /// `profile-rtos` in an artifacts directory to functions and source lines
+    #[structopt(name = "symbolize")]
+    Symbolize(symbolize::SymbolizeOpt),
+
+    /// Tabulate the code size of each build configuration in an artifacts
+    /// directory, relative to the configuration without CFI features
+    #[structopt(name = "size")]
+    Size(size::SizeOpt),
+}
+
 impl Opt {
+    /// Exit with an error if `benchmark` or `target` is missing when running
+    /// a benchmark. (They are optional for subcommands.)
+    fn check_required_args(&self) {
+        if self.command.is_some() {
+            return;
+        }
+
+        let missing: Vec<_> = [
+            ("<benchmark>", self.benchmark.is_none()),
+            ("--target <target>", self.target.is_none()),
+        ]
+        .iter()
+        .filter(|(_, is_missing)| *is_missing)
+        .map(|(name, _)| *name)
+        .collect();
+
+        if !missing.is_empty() {
+            clap::Error::with_description(
+                &format!(
+                    "The following required arguments were not provided: {}",
+                    missing.join(", ")
+                ),
+                clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit();
+        }
+    }
+
+    /// Get the benchmark to run. Panics if a subcommand is given.
+    fn benchmark(&self) -> BenchmarkType {
+        self.benchmark.expect("no benchmark specified")
+    }
+
+    /// Get the target to run the benchmark on. Panics if a subcommand is
+    /// given.
+    fn target(&self) -> TargetType {
+        self.target.expect("no target specified")
+    }
+
     /// Replace variables in `output_dir_template` and form the final output
     /// directory path.
     ///
@@ -91,13 +235,28 @@ impl Opt {
         self.output_dir_template
             .replace("%date%", &now.format("%Y%m%d").to_string())
             .replace("%time%", &now.format("%H%M%S").to_string())
-            .replace("%target%", &self.target.to_string())
-            .replace("%benchmark%", &self.benchmark.to_string())
+            .replace("%target%", &self.target().to_string())
+            .replace("%benchmark%", &self.benchmark().to_string())
             .into()
     }
+
+    fn build_cache_dir(&self) -> PathBuf {
+        self.build_cache_dir
+            .clone()
+            .unwrap_or_else(|| self.zig_cache_dir.join("runbench"))
+    }
+
+    /// Get the build matrix axes to test all values of.
+    fn vary_axes(&self) -> Vec<String> {
+        let mut axes = self.vary.clone();
+        if self.vary_rom_offset {
+            axes.push("rom_offset".to_owned());
+        }
+        axes
+    }
 }
 
-#[derive(Clone, Copy, arg_enum_proc_macro::ArgEnum, Serialize)]
+#[derive(Clone, Copy, PartialEq, arg_enum_proc_macro::ArgEnum, Serialize, Deserialize)]
 enum BenchmarkType {
     Rtos,
     Latency,
@@ -106,20 +265,79 @@ enum BenchmarkType {
     ProfileRtos,
 }
 
-#[derive(Clone, Copy, arg_enum_proc_macro::ArgEnum, Serialize)]
+#[derive(Clone, Copy, PartialEq, arg_enum_proc_macro::ArgEnum, Serialize, Deserialize)]
 enum TargetType {
     Qemu,
     Lpc55s69,
 }
 
+impl BenchmarkType {
+    const ALL: [Self; 5] = [
+        Self::Rtos,
+        Self::Latency,
+        Self::CoreMark,
+        Self::ProfileSes,
+        Self::ProfileRtos,
+    ];
+
+    /// The names of all benchmarks (`AppTraits::name`).
+    fn names() -> Vec<String> {
+        Self::ALL.iter().map(|b| b.traits().name()).collect()
+    }
+
+    fn traits(self) -> Box<dyn app::AppTraits> {
+        match self {
+            Self::Rtos => Box::new(app::bench_rtos::BenchRtosTraits),
+            Self::Latency => Box::new(app::bench_latency::BenchLatencyTraits),
+            Self::CoreMark => Box::new(app::bench_coremark::BenchCoreMarkTraits),
+            Self::ProfileSes => Box::new(app::profile_ses::ProfileSesTraits),
+            Self::ProfileRtos => Box::new(app::profile_rtos::ProfileRtosTraits),
+        }
+    }
+}
+
+impl TargetType {
+    /// The build flags to pass to `zig build`.
+    fn zig_build_flags(self) -> &'static [&'static str] {
+        match self {
+            Self::Qemu => &["-Dtarget-board=an505"],
+            Self::Lpc55s69 => &["-Dtarget-board=lpc55s69"],
+        }
+    }
+
+    /// Check if more than one program can be run at once (`--jobs`). Each
+    /// program is run on a separate instance of `target::Target`.
+    fn allows_parallel_runs(self) -> bool {
+        match self {
+            Self::Qemu => true,
+            Self::Lpc55s69 => false,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     // Parse command-line arguments
     let opt = Opt::from_args();
+    opt.check_required_args();
+
+    if let Some(command) = &opt.command {
+        let result = match command {
+            Command::Report(report_opt) => report::run(report_opt),
+            Command::Compare(compare_opt) => compare::run(compare_opt),
+            Command::Symbolize(symbolize_opt) => symbolize::run(symbolize_opt),
+            Command::Size(size_opt) => size::run(size_opt),
+        };
+        if let Err(e) = result {
+            log::error!("Command failed.\n\n{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let result = match opt.benchmark {
+    let result = match opt.benchmark() {
         BenchmarkType::Rtos => app::run(&opt, app::bench_rtos::BenchRtosTraits).await,
         BenchmarkType::Latency => app::run(&opt, app::bench_latency::BenchLatencyTraits).await,
         BenchmarkType::CoreMark => app::run(&opt, app::bench_coremark::BenchCoreMarkTraits).await,
@@ -141,202 +359,12 @@ struct BuildTargetError(
 );
 
 async fn build_target(opt: &Opt) -> Result<Box<dyn target::Target + '_>, BuildTargetError> {
-    match opt.target {
-        TargetType::Lpc55s69 => Ok(Box::new(target::lpc55s69::Lpc55s69Target::new(&opt).await?)),
+    match opt.target() {
+        TargetType::Lpc55s69 => Ok(Box::new(target::lpc55s69::Lpc55s69Target::new(opt).await?)),
         TargetType::Qemu => Ok(Box::new(
-            target::qemu::QemuTarget::new(&opt)
+            target::qemu::QemuTarget::new(opt)
                 .await
                 .map_err(|e| Box::new(e) as Box<dyn Error>)?,
         )),
     }
 }
-
-/// The build options defined by `build.zig`
-#[derive(Debug, Clone, Copy, Serialize)]
-struct BuildOpt {
-    mode: BuildMode,
-    ctx: bool,
-    ses: Option<SesType>,
-    ss: bool,
-    aborting_ss: bool,
-    icall: bool,
-    accel_raise_pri: bool,
-    rom_offset: u8,
-}
-
-#[derive(Debug, Clone, Copy, Serialize)]
-enum BuildMode {
-    ReleaseFast,
-    ReleaseSmall,
-}
-
-#[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq)]
-enum SesType {
-    Safe,
-    Unnested,
-    Naive,
-    Null,
-}
-
-impl fmt::Display for BuildOpt {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.mode)?;
-
-        macro_rules! e {
-            ($($tt:tt)*) => {{
-                write!(f, "+")?;
-                write!(f, $($tt)*)?;
-            }};
-        }
-
-        if self.ctx {
-            e!("ctx");
-        }
-        if let Some(ses) = self.ses {
-            e!("ses({})", ses);
-        }
-        if self.ss {
-            if self.aborting_ss {
-                e!("ss(aborting)");
-            } else {
-                e!("ss(non-aborting)");
-            }
-        }
-        if self.icall {
-            e!("icall");
-        }
-        if self.accel_raise_pri {
-            e!("ape");
-        }
-        if self.rom_offset != 0 {
-            e!("off({})", self.rom_offset);
-        }
-        Ok(())
-    }
-}
-
-impl fmt::Display for SesType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(match self {
-            Self::Safe => "safe",
-            Self::Unnested => "unnested",
-            Self::Naive => "naive",
-            Self::Null => "null",
-        })
-    }
-}
-
-impl BuildOpt {
-    fn all_valid_values(opt: &Opt) -> impl Iterator<Item = Self> + Clone {
-        use itertools::iproduct;
-        // `iproduct!` can'be used with more than 8 iterators
-        // <https://github.com/rust-itertools/itertools/issues/384>
-        iproduct!(
-            iproduct!(
-                [BuildMode::ReleaseFast, BuildMode::ReleaseSmall]
-                    .iter()
-                    .cloned(),
-                [false, true].iter().cloned(),
-                [
-                    None,
-                    Some(SesType::Null),
-                    Some(SesType::Naive),
-                    Some(SesType::Unnested),
-                    Some(SesType::Safe)
-                ]
-                .iter()
-                .cloned(),
-                [false, true].iter().cloned()
-            ),
-            [false, true].iter().cloned(),
-            [false, true].iter().cloned(),
-            [false, true].iter().cloned(),
-            if opt.vary_rom_offset {
-                &[0, 4, 8, 12][..]
-            } else {
-                &[0][..]
-            }
-            .iter()
-            .cloned()
-        )
-        .map(
-            |((mode, ctx, ses, ss), aborting_ss, icall, accel_raise_pri, rom_offset)| {
-                Self {
-                    mode,
-                    ctx,
-                    ses,
-                    ss,
-                    aborting_ss,
-                    icall,
-                    accel_raise_pri,
-                    rom_offset,
-                }
-            },
-        )
-        .filter(|o| o.validate().is_ok())
-    }
-
-    fn validate(&self) -> Result<(), &'static str> {
-        if self.ss && !self.ctx {
-            // Shadow stacks are managed by context management API.
-            return Err("cfi-ss requires cfi-ctx");
-        }
-
-        if self.ses.is_some() && !self.ctx {
-            // Shadow exception stacks are managed by context management API.
-            return Err("cfi-ses requires cfi-ctx");
-        }
-
-        if self.ss && self.ses.is_none() {
-            // TZmCFI's shadow stacks do not work without shadow exception stacks.
-            // Probably because the shadow stack routines mess up the lowest bit
-            // of `EXC_RETURN`.
-            return Err("cfi-ss requires cfi-ses");
-        }
-
-        if self.aborting_ss && !self.ss {
-            // `aborting_ss` makes no sense without `ss`
-            return Err("aborting-ss requires cfi-ss");
-        }
-
-        if self.accel_raise_pri && !self.ctx {
-            // `TCRaisePrivilege` is a Secure function, so each thread needs its own
-            // Secure stack
-            return Err("-Daccel-raise-pri requires -Dcfi-ctx");
-        }
-
-        Ok(())
-    }
-
-    fn append_zig_buld_opts_to(&self, mut o: impl FnMut(String)) {
-        match self.mode {
-            BuildMode::ReleaseFast => o("-Drelease-fast".to_owned()),
-            BuildMode::ReleaseSmall => o("-Drelease-small".to_owned()),
-        }
-        o("-Dcfi=false".to_owned());
-        if self.ctx {
-            o("-Dcfi-ctx".to_owned());
-        }
-        if let Some(ses) = self.ses {
-            o("-Dcfi-ses".to_owned());
-            o(format!("-Dcfi-ses-type={}", ses));
-        }
-        if self.ss {
-            o("-Dcfi-ss".to_owned());
-        }
-        if self.aborting_ss {
-            o("-Dcfi-aborting-ss".to_owned());
-        }
-        if self.icall {
-            o("-Dcfi-icall".to_owned());
-        }
-        if self.accel_raise_pri {
-            o("-Daccel-raise-pri".to_owned());
-        } else {
-            o("-Daccel-raise-pri=false".to_owned());
-        }
-        if self.rom_offset != 0 {
-            o(format!("-Drom-offset={}", self.rom_offset));
-        }
-    }
-}
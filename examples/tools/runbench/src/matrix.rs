@@ -0,0 +1,821 @@
+//! The build matrix, which describes the build configurations to test.
+//!
+//! The matrix is loaded from a TOML or JSON file (`--matrix`) consisting of
+//! the following elements:
+//!
+//!  - `flags`: `zig build` flags passed to every build.
+//!  - `axis`: The dimensions of the matrix. Each axis corresponds to one or
+//!    more `build.zig` options and lists the values to test, the flags to
+//!    pass for each value, and the label representing each value in build
+//!    configuration names.
+//!  - `constraint`: Conditions a build configuration must meet to be valid.
+//!  - `benchmark.<name>`: Restrictions and additional constraints applied
+//!    when running a particular benchmark.
+//!
+//! See `examples/runbench-matrix.toml` for an example.
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+    /// The `zig build` flags passed to every build
+    #[serde(default)]
+    flags: Vec<String>,
+    #[serde(rename = "axis")]
+    axes: Vec<Axis>,
+    #[serde(rename = "constraint", default)]
+    constraints: Vec<Constraint>,
+    /// Indexed by `AppTraits::name`
+    #[serde(rename = "benchmark", default)]
+    benchmarks: HashMap<String, BenchmarkOverride>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Axis {
+    name: String,
+    values: Vec<Value>,
+    /// The values to test unless the axis is specified by `--vary`. Defaults
+    /// to `values`.
+    default_values: Option<Vec<Value>>,
+    /// The `zig build` flags for each value. `*` matches the values not
+    /// listed. `{value}` is replaced with the value.
+    #[serde(default)]
+    flags: HashMap<String, Vec<String>>,
+    /// The label representing each value in build configuration names. `*`
+    /// matches the values not listed. `{value}` is replaced with the value.
+    /// The value is omitted from the name if the label is empty or missing.
+    #[serde(default)]
+    label: HashMap<String, String>,
+}
+
+/// A value of an axis.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+/// A build configuration is valid only if it has the values listed in
+/// `require` whenever it has the values listed in `when`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Constraint {
+    #[serde(default)]
+    when: HashMap<String, OneOrMany>,
+    require: HashMap<String, OneOrMany>,
+    /// Explains why the constraint exists
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(Value),
+    Many(Vec<Value>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BenchmarkOverride {
+    /// Restricts the values of the specified axes. Takes precedence over
+    /// `--vary`.
+    #[serde(default)]
+    axes: HashMap<String, Vec<Value>>,
+    #[serde(rename = "constraint", default)]
+    constraints: Vec<Constraint>,
+}
+
+#[derive(Error, Debug)]
+pub enum MatrixError {
+    #[error("Could not read the matrix file {path:?}.\n\n{error}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error("Could not parse the matrix file {path:?}.\n\n{message}")]
+    Parse { path: PathBuf, message: String },
+
+    #[error("The axis '{0}' is defined more than once")]
+    DuplicateAxis(String),
+
+    #[error("The axis '{0}' has no values")]
+    NoValues(String),
+
+    #[error("Unknown benchmark '{0}'")]
+    UnknownBenchmark(String),
+
+    #[error("Unknown axis '{0}'")]
+    UnknownAxis(String),
+
+    #[error("'{value}' is not a value of the axis '{axis}'")]
+    UnknownValue { axis: String, value: String },
+
+    #[error("More than one build configuration is named '{0}'. Check the labels")]
+    DuplicateName(String),
+}
+
+/// A build configuration, i.e., a point in the build matrix.
+#[derive(Debug, Clone)]
+pub struct BuildOpt {
+    /// The values of the axes, in the order they are defined
+    values: Vec<(String, Value)>,
+    name: String,
+    zig_build_flags: Vec<String>,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bool(x) => write!(f, "{}", x),
+            Self::Int(x) => write!(f, "{}", x),
+            Self::String(x) => f.write_str(x),
+        }
+    }
+}
+
+impl OneOrMany {
+    fn values(&self) -> &[Value] {
+        match self {
+            Self::One(value) => std::slice::from_ref(value),
+            Self::Many(values) => values,
+        }
+    }
+}
+
+impl Matrix {
+    /// Load a matrix file. The format is determined by the extension: JSON if
+    /// `.json`, TOML otherwise. `benchmarks` lists the names of the known
+    /// benchmarks.
+    pub fn load(path: &Path, benchmarks: &[String]) -> Result<Self, MatrixError> {
+        let text = std::fs::read_to_string(path).map_err(|error| MatrixError::Read {
+            path: path.to_owned(),
+            error,
+        })?;
+
+        let parse_error = |message: String| MatrixError::Parse {
+            path: path.to_owned(),
+            message,
+        };
+
+        let matrix: Self = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str(&text).map_err(|e| parse_error(e.to_string()))?
+        } else {
+            toml::from_str(&text).map_err(|e| parse_error(e.to_string()))?
+        };
+
+        matrix
+            .validate(benchmarks)
+            .map_err(|e| parse_error(e.to_string()))?;
+
+        Ok(matrix)
+    }
+
+    /// Check the references to benchmarks, axes, and values so that typos
+    /// don't go unnoticed.
+    fn validate(&self, benchmarks: &[String]) -> Result<(), MatrixError> {
+        for name in self.benchmarks.keys() {
+            if !benchmarks.contains(name) {
+                return Err(MatrixError::UnknownBenchmark(name.clone()));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for axis in self.axes.iter() {
+            if !names.insert(&axis.name) {
+                return Err(MatrixError::DuplicateAxis(axis.name.clone()));
+            }
+            if axis.values.is_empty() {
+                return Err(MatrixError::NoValues(axis.name.clone()));
+            }
+            for value in axis.default_values.iter().flatten() {
+                axis.check_value(value)?;
+            }
+            for key in axis.flags.keys().chain(axis.label.keys()) {
+                if key != "*" && !axis.values.iter().any(|v| v.to_string() == *key) {
+                    return Err(axis.unknown_value(key));
+                }
+            }
+        }
+
+        let constraints = self.constraints.iter().chain(
+            self.benchmarks
+                .values()
+                .flat_map(|bench| bench.constraints.iter()),
+        );
+        for constraint in constraints {
+            for (name, values) in constraint.when.iter().chain(constraint.require.iter()) {
+                let axis = self.axis(name)?;
+                for value in values.values() {
+                    axis.check_value(value)?;
+                }
+            }
+        }
+
+        for bench in self.benchmarks.values() {
+            for (name, values) in bench.axes.iter() {
+                let axis = self.axis(name)?;
+                for value in values.iter() {
+                    axis.check_value(value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn axis(&self, name: &str) -> Result<&Axis, MatrixError> {
+        self.axes
+            .iter()
+            .find(|axis| axis.name == name)
+            .ok_or_else(|| MatrixError::UnknownAxis(name.to_owned()))
+    }
+
+    /// Check that `name` is an axis of the matrix.
+    pub fn check_axis(&self, name: &str) -> Result<(), MatrixError> {
+        self.axis(name).map(|_| ())
+    }
+
+    /// Check that `value` is the string representation of a value of the
+    /// axis `name`.
+    pub fn check_value_str(&self, name: &str, value: &str) -> Result<(), MatrixError> {
+        let axis = self.axis(name)?;
+        if axis.values.iter().any(|v| v.to_string() == value) {
+            Ok(())
+        } else {
+            Err(axis.unknown_value(value))
+        }
+    }
+
+    /// Enumerate the valid build configurations for the specified benchmark.
+    /// The axes listed in `vary` use all of their values instead of
+    /// `default_values`.
+    pub fn build_opts(
+        &self,
+        benchmark: &str,
+        vary: &[String],
+    ) -> Result<Vec<BuildOpt>, MatrixError> {
+        for name in vary.iter() {
+            self.axis(name)?;
+        }
+
+        let bench = self.benchmarks.get(benchmark);
+
+        let axis_values: Vec<&[Value]> = self
+            .axes
+            .iter()
+            .map(|axis| {
+                if let Some(values) = bench.and_then(|bench| bench.axes.get(&axis.name)) {
+                    &values[..]
+                } else if vary.contains(&axis.name) {
+                    &axis.values
+                } else {
+                    axis.default_values.as_ref().unwrap_or(&axis.values)
+                }
+            })
+            .collect();
+
+        let constraints: Vec<&Constraint> = self
+            .constraints
+            .iter()
+            .chain(bench.into_iter().flat_map(|bench| bench.constraints.iter()))
+            .collect();
+
+        // Enumerate the Cartesian product. The last axis varies the fastest.
+        let mut build_opts = Vec::new();
+        let mut indices = vec![0; self.axes.len()];
+        if axis_values.iter().all(|values| !values.is_empty()) {
+            loop {
+                let values: Vec<(&Axis, &Value)> = self
+                    .axes
+                    .iter()
+                    .zip(axis_values.iter().zip(indices.iter()))
+                    .map(|(axis, (values, &i))| (axis, &values[i]))
+                    .collect();
+
+                let bo = self.build_opt(&values);
+                match constraints.iter().find(|c| !c.is_satisfied_by(&values)) {
+                    Some(c) => log::trace!("Excluding {}: {}", bo, c.message),
+                    None => build_opts.push(bo),
+                }
+
+                // Increment `indices`
+                let mut carry = true;
+                for (i, values) in indices.iter_mut().zip(axis_values.iter()).rev() {
+                    *i += 1;
+                    if *i < values.len() {
+                        carry = false;
+                        break;
+                    }
+                    *i = 0;
+                }
+                if carry {
+                    break;
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        for bo in build_opts.iter() {
+            if !names.insert(&bo.name) {
+                return Err(MatrixError::DuplicateName(bo.name.clone()));
+            }
+        }
+
+        Ok(build_opts)
+    }
+
+    fn build_opt(&self, values: &[(&Axis, &Value)]) -> BuildOpt {
+        let mut zig_build_flags = self.flags.clone();
+        let mut labels = Vec::new();
+
+        for (axis, value) in values.iter() {
+            let value_str = value.to_string();
+            if let Some(flags) = axis.lookup(&axis.flags, &value_str) {
+                zig_build_flags.extend(flags.iter().map(|f| f.replace("{value}", &value_str)));
+            }
+            if let Some(label) = axis.lookup(&axis.label, &value_str) {
+                if !label.is_empty() {
+                    labels.push(label.replace("{value}", &value_str));
+                }
+            }
+        }
+
+        BuildOpt {
+            values: values
+                .iter()
+                .map(|(axis, value)| (axis.name.clone(), (*value).clone()))
+                .collect(),
+            name: labels.join("+"),
+            zig_build_flags,
+        }
+    }
+}
+
+impl Axis {
+    fn check_value(&self, value: &Value) -> Result<(), MatrixError> {
+        if self.values.contains(value) {
+            Ok(())
+        } else {
+            Err(self.unknown_value(&value.to_string()))
+        }
+    }
+
+    fn unknown_value(&self, value: &str) -> MatrixError {
+        MatrixError::UnknownValue {
+            axis: self.name.clone(),
+            value: value.to_owned(),
+        }
+    }
+
+    fn lookup<'a, T>(&self, map: &'a HashMap<String, T>, value: &str) -> Option<&'a T> {
+        map.get(value).or_else(|| map.get("*"))
+    }
+}
+
+impl Constraint {
+    fn is_satisfied_by(&self, values: &[(&Axis, &Value)]) -> bool {
+        let matches = |conditions: &HashMap<String, OneOrMany>| {
+            conditions.iter().all(|(name, allowed)| {
+                values
+                    .iter()
+                    .find(|(axis, _)| axis.name == *name)
+                    .is_some_and(|(_, value)| allowed.values().contains(value))
+            })
+        };
+        !matches(&self.when) || matches(&self.require)
+    }
+}
+
+impl BuildOpt {
+    /// The `zig build` flags to build this configuration.
+    pub fn zig_build_flags(&self) -> &[String] {
+        &self.zig_build_flags
+    }
+
+    /// The value of the specified axis.
+    pub fn value(&self, axis: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == axis)
+            .map(|(_, value)| value)
+    }
+}
+
+impl fmt::Display for BuildOpt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Serialized as a map from axis names to values.
+impl Serialize for BuildOpt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (name, value) in self.values.iter() {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATRIX: &str = r#"
+        flags = ["-Dcommon"]
+
+        [[axis]]
+        name = "mode"
+        values = ["Fast", "Small"]
+        flags = { "*" = ["-Dmode={value}"] }
+        label = { "*" = "{value}" }
+
+        [[axis]]
+        name = "ctx"
+        values = [false, true]
+        flags = { true = ["-Dctx"] }
+        label = { true = "ctx" }
+
+        [[axis]]
+        name = "offset"
+        values = [0, 4]
+        default_values = [0]
+        flags = { 4 = ["-Doffset={value}"] }
+        label = { 4 = "off({value})" }
+    "#;
+
+    fn parse(text: &str) -> Result<Matrix, MatrixError> {
+        let matrix: Matrix = toml::from_str(text).unwrap();
+        matrix.validate(&["bench-a".to_owned()])?;
+        Ok(matrix)
+    }
+
+    fn names(matrix: &Matrix, benchmark: &str, vary: &[&str]) -> Vec<String> {
+        let vary: Vec<String> = vary.iter().map(|&s| s.to_owned()).collect();
+        matrix
+            .build_opts(benchmark, &vary)
+            .unwrap()
+            .iter()
+            .map(|bo| bo.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn enumerate() {
+        let matrix = parse(MATRIX).unwrap();
+        assert_eq!(
+            names(&matrix, "bench-a", &[]),
+            ["Fast", "Fast+ctx", "Small", "Small+ctx"]
+        );
+
+        let build_opts = matrix.build_opts("bench-a", &[]).unwrap();
+        let bo = &build_opts[3];
+        assert_eq!(bo.zig_build_flags(), ["-Dcommon", "-Dmode=Small", "-Dctx"]);
+        assert_eq!(bo.value("mode"), Some(&Value::String("Small".to_owned())));
+        assert_eq!(bo.value("ctx"), Some(&Value::Bool(true)));
+        assert_eq!(bo.value("offset"), Some(&Value::Int(0)));
+        assert_eq!(bo.value("foo"), None);
+    }
+
+    #[test]
+    fn vary() {
+        let matrix = parse(MATRIX).unwrap();
+        assert_eq!(
+            names(&matrix, "bench-a", &["offset"]),
+            [
+                "Fast",
+                "Fast+off(4)",
+                "Fast+ctx",
+                "Fast+ctx+off(4)",
+                "Small",
+                "Small+off(4)",
+                "Small+ctx",
+                "Small+ctx+off(4)",
+            ]
+        );
+
+        let e = matrix
+            .build_opts("bench-a", &["foo".to_owned()])
+            .unwrap_err();
+        assert!(
+            matches!(e, MatrixError::UnknownAxis(ref axis) if axis == "foo"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn constraints() {
+        let matrix = parse(&format!(
+            r#"{}
+            [[constraint]]
+            when = {{ offset = 4 }}
+            require = {{ ctx = true, mode = ["Small"] }}
+            message = "offsets require ctx and Small"
+
+            [[constraint]]
+            require = {{ mode = "Fast" }}
+            message = "only Fast is supported"
+            "#,
+            MATRIX
+        ))
+        .unwrap();
+        assert_eq!(names(&matrix, "bench-a", &["offset"]), ["Fast", "Fast+ctx"]);
+    }
+
+    #[test]
+    fn benchmark_overrides() {
+        let matrix = parse(&format!(
+            r#"{}
+            [benchmark.bench-a.axes]
+            mode = ["Small"]
+            offset = [0, 4]
+
+            [[benchmark.bench-a.constraint]]
+            when = {{ offset = 4 }}
+            require = {{ ctx = true }}
+            message = "offsets require ctx"
+            "#,
+            MATRIX
+        ))
+        .unwrap();
+
+        // The axes restricted by the benchmark ignore `--vary`
+        assert_eq!(
+            names(&matrix, "bench-a", &[]),
+            ["Small", "Small+ctx", "Small+ctx+off(4)"]
+        );
+        assert_eq!(
+            names(&matrix, "bench-b", &[]),
+            ["Fast", "Fast+ctx", "Small", "Small+ctx"]
+        );
+    }
+
+    fn parse_error(extra: &str) -> MatrixError {
+        parse(&format!("{}\n{}", MATRIX, extra)).unwrap_err()
+    }
+
+    #[test]
+    fn reject_duplicate_axis() {
+        let e = parse_error("[[axis]]\nname = \"ctx\"\nvalues = [true]");
+        assert!(
+            matches!(e, MatrixError::DuplicateAxis(ref axis) if axis == "ctx"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn reject_axis_without_values() {
+        let e = parse_error("[[axis]]\nname = \"icall\"\nvalues = []");
+        assert!(
+            matches!(e, MatrixError::NoValues(ref axis) if axis == "icall"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn reject_unknown_values() {
+        let is_unknown_value = |e: &MatrixError, expected_axis: &str, expected_value: &str| {
+            matches!(e, MatrixError::UnknownValue { axis, value }
+                if axis == expected_axis && value == expected_value)
+        };
+
+        let e =
+            parse_error("[[axis]]\nname = \"icall\"\nvalues = [false]\ndefault_values = [true]");
+        assert!(is_unknown_value(&e, "icall", "true"), "{}", e);
+
+        let e = parse_error(
+            "[[axis]]\nname = \"icall\"\nvalues = [false]\nlabel = { ture = \"icall\" }",
+        );
+        assert!(is_unknown_value(&e, "icall", "ture"), "{}", e);
+
+        let e = parse_error(
+            "[[constraint]]\nwhen = { mode = \"fast\" }\nrequire = { ctx = true }\nmessage = \"\"",
+        );
+        assert!(is_unknown_value(&e, "mode", "fast"), "{}", e);
+
+        let e = parse_error("[benchmark.bench-a.axes]\noffset = [8]");
+        assert!(is_unknown_value(&e, "offset", "8"), "{}", e);
+    }
+
+    #[test]
+    fn reject_unknown_references() {
+        let e = parse_error(
+            "[[constraint]]\nwhen = { icall = true }\nrequire = { ctx = true }\nmessage = \"\"",
+        );
+        assert!(
+            matches!(e, MatrixError::UnknownAxis(ref axis) if axis == "icall"),
+            "{}",
+            e
+        );
+
+        let e = parse_error("[benchmark.bench-b.axes]\nctx = [true]");
+        assert!(
+            matches!(e, MatrixError::UnknownBenchmark(ref name) if name == "bench-b"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn reject_duplicate_names() {
+        let matrix = parse(&format!(
+            "{}\n[[axis]]\nname = \"icall\"\nvalues = [false, true]",
+            MATRIX
+        ))
+        .unwrap();
+        let e = matrix.build_opts("bench-a", &[]).unwrap_err();
+        assert!(
+            matches!(e, MatrixError::DuplicateName(ref name) if name == "Fast"),
+            "{}",
+            e
+        );
+    }
+
+    /// A build configuration in the matrix which was hard-coded in
+    /// `tzmcfi_runbench` before `runbench-matrix.toml` was introduced
+    #[derive(Clone, Copy)]
+    struct LegacyBuildOpt {
+        mode: &'static str,
+        ctx: bool,
+        ses: Option<&'static str>,
+        ss: bool,
+        aborting_ss: bool,
+        icall: bool,
+        ape: bool,
+        rom_offset: u8,
+    }
+
+    /// Enumerate the legacy build configurations of a benchmark in the order
+    /// of `iproduct!`. `should_use` is the return values of
+    /// `AppTraits::should_use_{shadow_exception_stacks, shadow_stacks,
+    /// context_management, accel_raise_pri, icall_sanitizer}`. Returns the
+    /// names and the sorted `zig build` flags of the configurations.
+    fn legacy_build_opts(
+        should_use: [bool; 5],
+        vary_rom_offset: bool,
+    ) -> Vec<(String, Vec<String>)> {
+        let bools = [false, true];
+        let sess = [
+            None,
+            Some("null"),
+            Some("naive"),
+            Some("unnested"),
+            Some("safe"),
+        ];
+        let rom_offsets: &[u8] = if vary_rom_offset {
+            &[0, 4, 8, 12]
+        } else {
+            &[0]
+        };
+
+        let mut all = Vec::new();
+        for &mode in ["ReleaseFast", "ReleaseSmall"].iter() {
+            for &ctx in bools.iter() {
+                for &ses in sess.iter() {
+                    for &ss in bools.iter() {
+                        for &aborting_ss in bools.iter() {
+                            for &icall in bools.iter() {
+                                for &ape in bools.iter() {
+                                    for &rom_offset in rom_offsets.iter() {
+                                        all.push(LegacyBuildOpt {
+                                            mode,
+                                            ctx,
+                                            ses,
+                                            ss,
+                                            aborting_ss,
+                                            icall,
+                                            ape,
+                                            rom_offset,
+                                        });
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let [use_ses, use_ss, use_ctx, use_ape, use_icall] = should_use;
+        let mut build_opts = Vec::new();
+        for bo in all {
+            // `BuildOpt::validate`
+            let invalid = [
+                bo.ss && !bo.ctx,
+                bo.ses.is_some() && !bo.ctx,
+                bo.ss && bo.ses.is_none(),
+                bo.aborting_ss && !bo.ss,
+                bo.ape && !bo.ctx,
+            ];
+            // `app::run`
+            let excluded = [
+                !use_ses && bo.ses != Some("safe"),
+                !use_ss && bo.ss,
+                !use_ctx && !bo.ctx,
+                !use_ape && bo.ape,
+                !use_icall && bo.icall,
+            ];
+            if invalid.iter().chain(excluded.iter()).any(|&x| x) {
+                continue;
+            }
+
+            // `BuildOpt::fmt` and `BuildOpt::append_zig_buld_opts_to`
+            let mut name = bo.mode.to_owned();
+            let mut flags = vec!["-Dcfi=false".to_owned()];
+            flags.push(match bo.mode {
+                "ReleaseFast" => "-Drelease-fast".to_owned(),
+                _ => "-Drelease-small".to_owned(),
+            });
+            if bo.ctx {
+                name += "+ctx";
+                flags.push("-Dcfi-ctx".to_owned());
+            }
+            if let Some(ses) = bo.ses {
+                name += &format!("+ses({})", ses);
+                flags.push("-Dcfi-ses".to_owned());
+                flags.push(format!("-Dcfi-ses-type={}", ses));
+            }
+            if bo.ss {
+                if bo.aborting_ss {
+                    name += "+ss(aborting)";
+                    flags.push("-Dcfi-aborting-ss".to_owned());
+                } else {
+                    name += "+ss(non-aborting)";
+                }
+                flags.push("-Dcfi-ss".to_owned());
+            }
+            if bo.icall {
+                name += "+icall";
+                flags.push("-Dcfi-icall".to_owned());
+            }
+            if bo.ape {
+                name += "+ape";
+                flags.push("-Daccel-raise-pri".to_owned());
+            } else {
+                flags.push("-Daccel-raise-pri=false".to_owned());
+            }
+            if bo.rom_offset != 0 {
+                name += &format!("+off({})", bo.rom_offset);
+                flags.push(format!("-Drom-offset={}", bo.rom_offset));
+            }
+            flags.sort();
+            build_opts.push((name, flags));
+        }
+        build_opts
+    }
+
+    #[test]
+    fn shipped_matrix_matches_legacy_matrix() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../runbench-matrix.toml");
+        let matrix = Matrix::load(&path, &crate::BenchmarkType::names()).unwrap();
+
+        let benchmarks = [
+            ("bench-coremark", [false, true, false, false, true]),
+            ("bench-latency", [true, false, false, false, false]),
+            ("bench-rtos", [true; 5]),
+            ("profile-rtos", [true; 5]),
+            ("profile-ses", [true, false, false, false, false]),
+        ];
+        for &(benchmark, should_use) in benchmarks.iter() {
+            for &vary_rom_offset in [false, true].iter() {
+                let vary = if vary_rom_offset {
+                    vec!["rom_offset".to_owned()]
+                } else {
+                    vec![]
+                };
+                let build_opts: Vec<_> = matrix
+                    .build_opts(benchmark, &vary)
+                    .unwrap()
+                    .iter()
+                    .map(|bo| {
+                        let mut flags = bo.zig_build_flags().to_vec();
+                        flags.sort();
+                        (bo.to_string(), flags)
+                    })
+                    .collect();
+                assert_eq!(
+                    build_opts,
+                    legacy_build_opts(should_use, vary_rom_offset),
+                    "{} (vary_rom_offset = {})",
+                    benchmark,
+                    vary_rom_offset
+                );
+            }
+        }
+    }
+}
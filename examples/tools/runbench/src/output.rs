@@ -0,0 +1,255 @@
+//! Converts the output of benchmark applications to strict JSON.
+//!
+//! The applications print their results in JSON5, which allows comments,
+//! trailing commas, and hexadecimal numbers. The output is parsed, checked
+//! against a per-benchmark [`Schema`], and written out as normalized JSON.
+//! Comments following object fields are kept as the descriptions of the
+//! fields:
+//!
+//! ```text
+//! "NewTask": 1234, /* [cycles] (Unpriv xTaskCreateRestricted without dispatch) */
+//! ```
+use serde_json::Value;
+use std::fmt::Write;
+use thiserror::Error;
+
+/// The expected structure of the output of a benchmark application.
+#[derive(Debug)]
+pub enum Schema {
+    /// Accepts anything
+    Any,
+    Number,
+    String,
+    /// An array whose elements match the specified schema
+    Array(&'static Schema),
+    /// An object with exactly the specified fields
+    Object(&'static [(&'static str, Schema)]),
+}
+
+#[derive(Error, Debug)]
+pub enum OutputError {
+    #[error("The output is not a valid UTF-8 string.")]
+    Utf8(#[source] std::str::Utf8Error),
+
+    #[error("Could not parse the output as JSON5.\n\n{0}")]
+    Parse(#[source] json5::Error),
+
+    #[error("The output does not match the expected schema: {message} at '{path}'")]
+    Schema { path: String, message: String },
+}
+
+/// The output of a benchmark application converted to JSON.
+#[derive(Debug)]
+pub struct ParsedOutput {
+    pub value: Value,
+    /// The pairs of field names and comments following them
+    pub descriptions: Vec<(String, String)>,
+}
+
+/// Parse the output of a benchmark application and check it against
+/// `schema`.
+pub fn parse(output: &[u8], schema: &Schema) -> Result<ParsedOutput, OutputError> {
+    let text = std::str::from_utf8(output).map_err(OutputError::Utf8)?;
+    let value: Value = json5::from_str(text).map_err(OutputError::Parse)?;
+
+    schema.check(&value, &mut String::new())?;
+
+    Ok(ParsedOutput {
+        value,
+        descriptions: extract_descriptions(text),
+    })
+}
+
+impl ParsedOutput {
+    /// Get the normalized JSON representation.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(&self.value).unwrap()
+    }
+}
+
+impl Schema {
+    /// Check `value` against the schema. `path` is the JSON Pointer of
+    /// `value`, used for error messages.
+    fn check(&self, value: &Value, path: &mut String) -> Result<(), OutputError> {
+        let mismatch = |path: &str, message: String| OutputError::Schema {
+            path: if path.is_empty() { "/" } else { path }.to_owned(),
+            message,
+        };
+
+        match (self, value) {
+            (Self::Any, _)
+            | (Self::Number, Value::Number(_))
+            | (Self::String, Value::String(_)) => Ok(()),
+            (Self::Array(elem_schema), Value::Array(elems)) => {
+                let len = path.len();
+                for (i, elem) in elems.iter().enumerate() {
+                    write!(path, "/{}", i).unwrap();
+                    elem_schema.check(elem, path)?;
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            (Self::Object(field_schemas), Value::Object(fields)) => {
+                if let Some(key) = fields
+                    .keys()
+                    .find(|key| !field_schemas.iter().any(|(name, _)| name == key))
+                {
+                    return Err(mismatch(path, format!("unexpected field '{}'", key)));
+                }
+
+                let len = path.len();
+                for (name, field_schema) in field_schemas.iter() {
+                    let field = fields
+                        .get(*name)
+                        .ok_or_else(|| mismatch(path, format!("missing field '{}'", name)))?;
+                    write!(path, "/{}", name.replace('~', "~0").replace('/', "~1")).unwrap();
+                    field_schema.check(field, path)?;
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            _ => Err(mismatch(
+                path,
+                format!("expected {}, found {}", self.kind(), kind_of(value)),
+            )),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Any => "anything",
+            Self::Number => "a number",
+            Self::String => "a string",
+            Self::Array(_) => "an array",
+            Self::Object(_) => "an object",
+        }
+    }
+}
+
+fn kind_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Find the comments following object fields on the same line.
+fn extract_descriptions(text: &str) -> Vec<(String, String)> {
+    let mut descriptions = Vec::new();
+    // The last field name on the current line
+    let mut line_key: Option<String> = None;
+    // The last string literal, which might be a field name
+    let mut last_string: Option<String> = None;
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\n' => {
+                line_key = None;
+                last_string = None;
+            }
+            '"' | '\'' => {
+                let mut string = String::new();
+                while let Some((_, c2)) = chars.next() {
+                    match c2 {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                string.push(escaped);
+                            }
+                        }
+                        _ if c2 == c => break,
+                        _ => string.push(c2),
+                    }
+                }
+                last_string = Some(string);
+            }
+            ':' => line_key = last_string.take(),
+            '/' if text[i..].starts_with("/*") => {
+                let start = i + 2;
+                let end = text[start..].find("*/").map_or(text.len(), |n| start + n);
+                while chars.peek().is_some_and(|&(j, _)| j < end + 2) {
+                    chars.next();
+                }
+                if let Some(key) = line_key.take() {
+                    descriptions.push((key, text[start..end].trim().to_owned()));
+                }
+            }
+            '/' if text[i..].starts_with("//") => {
+                let end = text[i..].find('\n').map_or(text.len(), |n| i + n);
+                while chars.peek().is_some_and(|&(j, _)| j < end) {
+                    chars.next();
+                }
+                if let Some(key) = line_key.take() {
+                    descriptions.push((key, text[i + 2..end].trim().to_owned()));
+                }
+            }
+            c if !c.is_whitespace() => last_string = None,
+            _ => {}
+        }
+    }
+
+    descriptions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptions(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(key, description)| (key.to_owned(), description.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn same_line_comments() {
+        let text = r#"{
+  /* [0] */
+  "Overhead": 3, /* [cycles] (this value is subtracted) */
+  "NewTask": 0x10, // [cycles] line comment
+  "DelTask": 5,
+}"#;
+        assert_eq!(
+            extract_descriptions(text),
+            descriptions(&[
+                ("Overhead", "[cycles] (this value is subtracted)"),
+                ("NewTask", "[cycles] line comment"),
+            ])
+        );
+    }
+
+    #[test]
+    fn comments_on_separate_lines_are_ignored() {
+        let text = "{\n  \"a\": 1,\n  /* not about a */\n  // nor this\n}";
+        assert_eq!(extract_descriptions(text), descriptions(&[]));
+    }
+
+    #[test]
+    fn comment_markers_in_strings() {
+        let text = "{\n  'a': \"x /* y */ \\\" // z\", /* desc */\n}";
+        assert_eq!(extract_descriptions(text), descriptions(&[("a", "desc")]));
+    }
+
+    #[test]
+    fn last_field_on_line() {
+        let text = "{ \"a\": 1, \"b\": [2, 3], /* about b */ \"c\": 4 }";
+        assert_eq!(
+            extract_descriptions(text),
+            descriptions(&[("b", "about b")])
+        );
+    }
+
+    #[test]
+    fn string_values_are_not_keys() {
+        let text = "[\n  \"a\", /* an element */\n  { \"b\": \"c\" } // about b\n]";
+        assert_eq!(
+            extract_descriptions(text),
+            descriptions(&[("b", "about b")])
+        );
+    }
+}
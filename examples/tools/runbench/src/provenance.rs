@@ -0,0 +1,93 @@
+//! Records the environment in which the results were produced, so that old
+//! artifacts directories can be reproduced and trusted.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, ffi::OsStr, path::Path};
+
+use super::subprocess;
+
+/// The environment of a single invocation of runbench. An artifacts
+/// directory has more than one session if the run was resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// RFC 3339
+    pub started_at: String,
+    /// RFC 3339. `None` if the session was interrupted.
+    pub finished_at: Option<String>,
+    pub hostname: Option<String>,
+    /// The state of the Git working tree containing the current directory
+    pub git: Option<GitState>,
+    /// The version strings of the external tools, e.g., `zig` and `qemu`
+    pub tool_versions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitState {
+    pub commit: String,
+    /// `true` if tracked files have uncommitted changes
+    pub dirty: bool,
+}
+
+impl Session {
+    /// Examine the current environment. Information that can't be acquired is
+    /// omitted.
+    pub async fn query(zig_cmd: &OsStr, target_tool_versions: Vec<(&str, String)>) -> Self {
+        let mut tool_versions: BTreeMap<_, _> = target_tool_versions
+            .into_iter()
+            .map(|(name, version)| (name.to_owned(), version))
+            .collect();
+        if let Some(version) = command_output(zig_cmd, &["version"]).await {
+            tool_versions.insert("zig".to_owned(), version);
+        }
+
+        let git = match command_output("git".as_ref(), &["rev-parse", "HEAD"]).await {
+            Some(commit) => {
+                let status = command_output(
+                    "git".as_ref(),
+                    &["status", "--porcelain", "--untracked-files=no"],
+                )
+                .await;
+                status.map(|status| GitState {
+                    commit,
+                    dirty: !status.is_empty(),
+                })
+            }
+            None => None,
+        };
+
+        Self {
+            started_at: now(),
+            finished_at: None,
+            hostname: command_output("hostname".as_ref(), &[]).await,
+            git,
+            tool_versions,
+        }
+    }
+}
+
+/// Get the current time in RFC 3339 format.
+pub fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+/// Calculate the SHA-256 hash of a file.
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let bytes = tokio::fs::read(path).await?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+/// Run a command and get its trimmed standard output. Returns `None` on
+/// failure.
+async fn command_output(cmd: &OsStr, args: &[&str]) -> Option<String> {
+    match subprocess::CmdBuilder::new(cmd)
+        .args(args.iter())
+        .spawn_capturing_stdout()
+        .await
+    {
+        Ok(output) => Some(String::from_utf8_lossy(&output).trim().to_owned()),
+        Err(e) => {
+            log::warn!("Could not record provenance information: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,564 @@
+//! The `report` subcommand, which summarizes the results saved in an
+//! artifacts directory in a table.
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+use thiserror::Error;
+
+use super::{stats, BenchmarkType};
+
+#[derive(StructOpt)]
+pub struct ReportOpt {
+    /// The artifacts directory to summarize
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// The name of the build configuration to calculate overheads against.
+    /// Defaults to the first configuration
+    #[structopt(short = "b", long = "baseline")]
+    baseline: Option<String>,
+
+    /// Output format
+    #[structopt(
+        short = "f", long = "format", default_value = "markdown",
+        possible_values(&ReportFormat::variants()), case_insensitive = true
+    )]
+    format: ReportFormat,
+}
+
+#[derive(Clone, Copy, arg_enum_proc_macro::ArgEnum)]
+pub enum ReportFormat {
+    Markdown,
+    Csv,
+    Latex,
+}
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("Could not read the metadata in {0:?}.\n\n{1}")]
+    ReadMetadata(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("Could not read the result {0:?}.\n\n{1}")]
+    ReadResult(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("The build configuration '{0}' is not in the artifacts directory")]
+    UnknownBaseline(String),
+
+    #[error("The artifacts directory contains no results")]
+    NoResults,
+}
+
+/// The part of `meta.json` needed to read the results.
+#[derive(Deserialize)]
+struct ArtifactsMetadata {
+    benchmark: BenchmarkType,
+    matrix: Vec<ArtifactsMetaRun>,
+    #[serde(default)]
+    descriptions: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ArtifactsMetaRun {
+    name: String,
+    #[serde(default)]
+    repeat: Option<usize>,
+}
+
+/// The metrics of the build configurations in an artifacts directory.
+pub struct Results {
+    pub benchmark: BenchmarkType,
+    /// The pairs of build configuration names and their metrics, in the order
+    /// they were run
+    pub configs: Vec<(String, Vec<(String, f64)>)>,
+    pub descriptions: BTreeMap<String, String>,
+}
+
+impl Results {
+    /// Read the results in an artifacts directory. The metrics of a
+    /// configuration run more than once are the means of the samples.
+    pub fn load(dir: &Path) -> Result<Self, ReportError> {
+        let meta_path = dir.join("meta.json");
+        let meta: ArtifactsMetadata = std::fs::read(&meta_path)
+            .map_err(Box::from)
+            .and_then(|json| serde_json::from_slice(&json).map_err(Box::from))
+            .map_err(|e| ReportError::ReadMetadata(meta_path.clone(), e))?;
+
+        let traits = meta.benchmark.traits();
+
+        let mut configs = Vec::new();
+        for run in meta.matrix.iter() {
+            let path = dir.join(format!("{}.json", run.name));
+            let result: serde_json::Value = match std::fs::read(&path) {
+                Ok(json) => serde_json::from_slice(&json)
+                    .map_err(|e| ReportError::ReadResult(path.clone(), e.into()))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    log::warn!("{:?} does not exist; ignoring '{}'", path, run.name);
+                    continue;
+                }
+                Err(e) => return Err(ReportError::ReadResult(path, e.into())),
+            };
+
+            let metrics = if run.repeat.unwrap_or(1) > 1 {
+                // Statistics (see `app::summarize_samples`)
+                result["stats"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(path, summary)| Some((path.clone(), summary["mean"].as_f64()?)))
+                    .collect()
+            } else {
+                stats::metrics(&result, |path| traits.is_metric(path))
+            };
+
+            // Strip the leading `/` from the JSON Pointers
+            let metrics = traits
+                .condense_metrics(metrics)
+                .into_iter()
+                .map(|(path, value)| (path.strip_prefix('/').unwrap_or(&path).to_owned(), value))
+                .collect();
+
+            configs.push((run.name.clone(), metrics));
+        }
+
+        Ok(Self {
+            benchmark: meta.benchmark,
+            configs,
+            descriptions: meta.descriptions,
+        })
+    }
+
+    /// Get the names of the metrics, in the order they first appear.
+    pub fn metric_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (_, metrics) in self.configs.iter() {
+            for (name, _) in metrics.iter() {
+                if !names.contains(&&name[..]) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    pub fn config(&self, name: &str) -> Option<&[(String, f64)]> {
+        self.configs
+            .iter()
+            .find(|(config_name, _)| config_name == name)
+            .map(|(_, metrics)| &metrics[..])
+    }
+}
+
+/// Find a metric by name.
+pub fn metric(metrics: &[(String, f64)], name: &str) -> Option<f64> {
+    metrics
+        .iter()
+        .find(|(metric_name, _)| metric_name == name)
+        .map(|&(_, value)| value)
+}
+
+pub fn run(opt: &ReportOpt) -> Result<(), Box<dyn Error>> {
+    let results = Results::load(&opt.dir)?;
+
+    let baseline_name = match &opt.baseline {
+        Some(name) => name.clone(),
+        None => match results.configs.first() {
+            Some((name, _)) => name.clone(),
+            None => return Err(ReportError::NoResults.into()),
+        },
+    };
+    let baseline = results
+        .config(&baseline_name)
+        .ok_or_else(|| ReportError::UnknownBaseline(baseline_name.clone()))?;
+
+    let metric_names = results.metric_names();
+
+    let rows: Vec<Row<'_>> = results
+        .configs
+        .iter()
+        .map(|(name, metrics)| {
+            let cells = metric_names
+                .iter()
+                .map(|metric_name| {
+                    let value = metric(metrics, metric_name)?;
+                    let base = metric(baseline, metric_name).unwrap_or(f64::NAN);
+                    let relative = if base != 0.0 && base.is_finite() {
+                        Some(value / base - 1.0)
+                    } else {
+                        None
+                    };
+                    Some(Cell {
+                        value,
+                        absolute: value - base,
+                        relative,
+                    })
+                })
+                .collect();
+            (&name[..], cells)
+        })
+        .collect();
+
+    let table = Table {
+        benchmark: results.benchmark,
+        caption: &baseline_name,
+        baseline: Some(&baseline_name),
+        metric_names: &metric_names,
+        descriptions: &results.descriptions,
+        rows: &rows,
+    };
+
+    print!("{}", table.format(opt.format));
+
+    Ok(())
+}
+
+pub struct Table<'a> {
+    pub benchmark: BenchmarkType,
+    /// Describes the baseline in the heading
+    pub caption: &'a str,
+    /// Overheads are not shown for the row of this name.
+    pub baseline: Option<&'a str>,
+    pub metric_names: &'a [&'a str],
+    pub descriptions: &'a BTreeMap<String, String>,
+    pub rows: &'a [Row<'a>],
+}
+
+/// A build configuration name and the cells for the metrics
+pub type Row<'a> = (&'a str, Vec<Option<Cell>>);
+
+pub struct Cell {
+    pub value: f64,
+    /// The absolute overhead. NaN if the baseline lacks the metric.
+    pub absolute: f64,
+    /// The relative overhead. `None` if it can't be calculated.
+    pub relative: Option<f64>,
+}
+
+pub fn format_value(x: f64) -> String {
+    if x.fract() == 0.0 {
+        format!("{}", x)
+    } else {
+        format!("{:.1}", x)
+    }
+}
+
+pub fn format_overhead(absolute: f64, relative: Option<f64>) -> (String, String) {
+    let absolute = if absolute.is_nan() {
+        String::new()
+    } else if absolute < 0.0 {
+        format!("-{}", format_value(-absolute))
+    } else {
+        format!("+{}", format_value(absolute))
+    };
+    let relative = relative.map_or_else(String::new, |r| format!("{:+.1}%", r * 100.0));
+    (absolute, relative)
+}
+
+impl Table<'_> {
+    pub fn format(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Latex => self.to_latex(),
+        }
+    }
+
+    fn format_cell(&self, name: &str, cell: &Option<Cell>) -> String {
+        let cell = match cell {
+            None => return "-".to_owned(),
+            Some(cell) => cell,
+        };
+        let value = format_value(cell.value);
+        if Some(name) == self.baseline {
+            return value;
+        }
+
+        let (absolute, relative) = format_overhead(cell.absolute, cell.relative);
+        if absolute.is_empty() {
+            value
+        } else if relative.is_empty() {
+            format!("{} ({})", value, absolute)
+        } else {
+            format!("{} ({}, {})", value, absolute, relative)
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Benchmark: {}, baseline: `{}`\n",
+            self.benchmark, self.caption
+        )
+        .unwrap();
+
+        writeln!(out, "| Configuration | {} |", self.metric_names.join(" | ")).unwrap();
+        writeln!(out, "|:--|{}", "--:|".repeat(self.metric_names.len())).unwrap();
+        for (name, cells) in self.rows.iter() {
+            let cells: Vec<_> = cells.iter().map(|c| self.format_cell(name, c)).collect();
+            writeln!(out, "| `{}` | {} |", name, cells.join(" | ")).unwrap();
+        }
+
+        let descriptions: Vec<_> = self
+            .metric_names
+            .iter()
+            .filter_map(|name| Some((name, self.descriptions.get(*name)?)))
+            .collect();
+        if !descriptions.is_empty() {
+            writeln!(out).unwrap();
+            for (name, description) in descriptions {
+                writeln!(out, " - **{}**: {}", name, description).unwrap();
+            }
+        }
+
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::new();
+
+        let mut header = vec!["configuration".to_owned()];
+        for name in self.metric_names.iter() {
+            header.push(name.to_string());
+            header.push(format!("{} overhead", name));
+            header.push(format!("{} relative overhead", name));
+        }
+        writeln!(out, "{}", csv_record(&header)).unwrap();
+
+        for (name, cells) in self.rows.iter() {
+            let mut record = vec![name.to_string()];
+            for cell in cells.iter() {
+                match cell {
+                    Some(cell) => {
+                        record.push(cell.value.to_string());
+                        record.push(if cell.absolute.is_nan() {
+                            String::new()
+                        } else {
+                            cell.absolute.to_string()
+                        });
+                        record.push(cell.relative.map_or_else(String::new, |r| r.to_string()));
+                    }
+                    None => record.resize(record.len() + 3, String::new()),
+                }
+            }
+            writeln!(out, "{}", csv_record(&record)).unwrap();
+        }
+
+        out
+    }
+
+    fn to_latex(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "% Benchmark: {}, baseline: {}",
+            self.benchmark, self.caption
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "\\begin{{tabular}}{{l{}}}",
+            "r".repeat(self.metric_names.len())
+        )
+        .unwrap();
+        writeln!(out, "\\hline").unwrap();
+
+        let header: Vec<_> = self.metric_names.iter().map(|n| latex_escape(n)).collect();
+        writeln!(out, "Configuration & {} \\\\", header.join(" & ")).unwrap();
+        writeln!(out, "\\hline").unwrap();
+
+        for (name, cells) in self.rows.iter() {
+            let cells: Vec<_> = cells
+                .iter()
+                .map(|c| latex_escape(&self.format_cell(name, c)))
+                .collect();
+            writeln!(
+                out,
+                "\\texttt{{{}}} & {} \\\\",
+                latex_escape(name),
+                cells.join(" & ")
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\\hline").unwrap();
+        writeln!(out, "\\end{{tabular}}").unwrap();
+        out
+    }
+}
+
+fn csv_record(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn latex_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' | '&' | '#' | '_' | '$' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '\\' => out.push_str("\\textbackslash{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TempDir;
+
+    #[test]
+    fn load_results() {
+        let dir = TempDir::new("load_results");
+        dir.write(
+            "meta.json",
+            r#"{
+                "benchmark": "Rtos",
+                "matrix": [
+                    { "name": "ReleaseFast" },
+                    { "name": "ReleaseFast+ctx", "repeat": 3 },
+                    { "name": "ReleaseSmall" }
+                ],
+                "descriptions": { "NewTask": "Creates a task" }
+            }"#,
+        );
+        dir.write("ReleaseFast.json", r#"{ "NewTask": 100, "DelTask": 50 }"#);
+        dir.write(
+            "ReleaseFast+ctx.json",
+            r#"{
+                "repeat": 3,
+                "stats": {
+                    "/NewTask": { "mean": 110.5, "min": 110 },
+                    "/SemTake": { "mean": 20, "min": 20 }
+                }
+            }"#,
+        );
+        // `ReleaseSmall.json` is missing
+
+        let results = Results::load(dir.path()).unwrap();
+        let names: Vec<_> = results.configs.iter().map(|(name, _)| &name[..]).collect();
+        assert_eq!(names, ["ReleaseFast", "ReleaseFast+ctx"]);
+
+        let fast = results.config("ReleaseFast").unwrap();
+        assert_eq!(metric(fast, "NewTask"), Some(100.0));
+        assert_eq!(metric(fast, "DelTask"), Some(50.0));
+
+        // The means of the samples
+        let ctx = results.config("ReleaseFast+ctx").unwrap();
+        assert_eq!(metric(ctx, "NewTask"), Some(110.5));
+        assert_eq!(metric(ctx, "SemTake"), Some(20.0));
+        assert_eq!(metric(ctx, "DelTask"), None);
+
+        assert_eq!(results.metric_names(), ["NewTask", "DelTask", "SemTake"]);
+        assert_eq!(results.descriptions["NewTask"], "Creates a task");
+    }
+
+    #[test]
+    fn load_malformed_result() {
+        let dir = TempDir::new("load_malformed_result");
+        dir.write(
+            "meta.json",
+            r#"{ "benchmark": "Rtos", "matrix": [{ "name": "ReleaseFast" }] }"#,
+        );
+        dir.write("ReleaseFast.json", "{");
+
+        match Results::load(dir.path()) {
+            Err(ReportError::ReadResult(path, _)) => {
+                assert_eq!(path, dir.path().join("ReleaseFast.json"))
+            }
+            _ => panic!("the malformed result was accepted"),
+        }
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let fields: Vec<String> = ["plain", "a,b", "say \"hi\"", "line\nbreak", ""]
+            .iter()
+            .map(|&s| s.to_owned())
+            .collect();
+        assert_eq!(
+            csv_record(&fields),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"line\nbreak\","
+        );
+    }
+
+    #[test]
+    fn latex_escaping() {
+        assert_eq!(
+            latex_escape("ss(non-aborting) & 50% #1 {x_y} $"),
+            "ss(non-aborting) \\& 50\\% \\#1 \\{x\\_y\\} \\$"
+        );
+        assert_eq!(
+            latex_escape("~^\\"),
+            "\\textasciitilde{}\\textasciicircum{}\\textbackslash{}"
+        );
+    }
+
+    #[test]
+    fn overhead_signs() {
+        let overhead = |absolute, relative| {
+            let (absolute, relative) = format_overhead(absolute, relative);
+            format!("{} {}", absolute, relative)
+        };
+        assert_eq!(overhead(5.0, Some(0.05)), "+5 +5.0%");
+        assert_eq!(overhead(-2.5, Some(-0.025)), "-2.5 -2.5%");
+        assert_eq!(overhead(0.0, Some(0.0)), "+0 +0.0%");
+        // The baseline is zero
+        assert_eq!(overhead(3.0, None), "+3 ");
+        // The baseline lacks the metric
+        assert_eq!(overhead(f64::NAN, None), " ");
+    }
+
+    #[test]
+    fn baseline_row() {
+        let cell = |value, absolute| {
+            Some(Cell {
+                value,
+                absolute,
+                relative: None,
+            })
+        };
+        let rows = [("a", vec![cell(10.0, 0.0)]), ("b", vec![cell(12.0, 2.0)])];
+        let descriptions = BTreeMap::new();
+        let table = |baseline| Table {
+            benchmark: BenchmarkType::CoreMark,
+            caption: "not ctx",
+            baseline,
+            metric_names: &["size"],
+            descriptions: &descriptions,
+            rows: &rows,
+        };
+
+        let markdown = table(Some("a")).to_markdown();
+        let lines: Vec<_> = markdown.lines().collect();
+        assert!(lines[0].ends_with(", baseline: `not ctx`"));
+        assert_eq!(lines[4], "| `a` | 10 |");
+        assert_eq!(lines[5], "| `b` | 12 (+2) |");
+
+        // Without a baseline row, every row shows the overheads
+        let markdown = table(None).to_markdown();
+        let lines: Vec<_> = markdown.lines().collect();
+        assert_eq!(lines[4], "| `a` | 10 (+0) |");
+        assert_eq!(lines[5], "| `b` | 12 (+2) |");
+    }
+}
@@ -0,0 +1,483 @@
+//! Code-size metrics of the built images.
+//!
+//! The sizes are measured after each build and recorded in `meta.json`. The
+//! `size` subcommand tabulates them, showing the growth of each build
+//! configuration relative to the configuration without CFI features that
+//! otherwise has the same values.
+use goblin::elf::Elf;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+use thiserror::Error;
+
+use super::{
+    filter::Filter,
+    matrix,
+    report::{Cell, ReportFormat, Row, Table},
+    symbolize::load_dwarf,
+    BenchmarkType,
+};
+
+#[derive(StructOpt)]
+pub struct SizeOpt {
+    /// The artifacts directory to summarize
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// A filter expression (see `--filter`) selecting the baseline
+    /// configurations. Each configuration is compared against the baseline
+    /// configuration having the same values for the axes not referenced by
+    /// the expression
+    #[structopt(
+        short = "b",
+        long = "baseline",
+        default_value = "not ctx and ses=none and ss=off and not icall and not accel_raise_pri"
+    )]
+    baseline: String,
+
+    /// Include the code size of each object (compilation unit)
+    #[structopt(long = "objects")]
+    objects: bool,
+
+    /// Output format
+    #[structopt(
+        short = "f", long = "format", default_value = "markdown",
+        possible_values(&ReportFormat::variants()), case_insensitive = true
+    )]
+    format: ReportFormat,
+}
+
+#[derive(Error, Debug)]
+pub enum SizeError {
+    #[error("Could not read the executable {0:?}.\n\n{1}")]
+    ReadImage(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("Could not read the metadata in {0:?}.\n\n{1}")]
+    ReadMetadata(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("The artifacts directory contains no build configurations")]
+    NoResults,
+}
+
+/// The sizes of the Secure and Non-Secure images.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sizes {
+    pub secure: ImageSizes,
+    pub non_secure: ImageSizes,
+}
+
+/// The sizes (in bytes) of the sections of an image.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageSizes {
+    pub text: u64,
+    pub rodata: u64,
+    pub data: u64,
+    pub bss: u64,
+    /// Non-Secure-callable veneers (`.gnu.sgstubs`)
+    pub nsc: u64,
+    /// The code size of each compilation unit, taken from the address ranges
+    /// in the DWARF debugging information. Code without debugging
+    /// information is not included.
+    pub objects: BTreeMap<String, u64>,
+}
+
+impl Sizes {
+    pub fn measure(secure_elf: &Path, nonsecure_elf: &Path) -> Result<Self, SizeError> {
+        Ok(Self {
+            secure: ImageSizes::measure(secure_elf)?,
+            non_secure: ImageSizes::measure(nonsecure_elf)?,
+        })
+    }
+}
+
+impl ImageSizes {
+    pub fn measure(path: &Path) -> Result<Self, SizeError> {
+        std::fs::read(path)
+            .map_err(Box::from)
+            .and_then(|bytes| Self::parse(&bytes))
+            .map_err(|e| SizeError::ReadImage(path.to_owned(), e))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let elf = Elf::parse(bytes)?;
+        let mut sizes = Self::default();
+
+        for shdr in elf.section_headers.iter() {
+            let name = elf.shdr_strtab.get_unsafe(shdr.sh_name).unwrap_or("");
+            let is = |prefix: &str| {
+                name.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            };
+            let field = if is(".text") {
+                &mut sizes.text
+            } else if is(".rodata") {
+                &mut sizes.rodata
+            } else if is(".data") {
+                &mut sizes.data
+            } else if is(".bss") {
+                &mut sizes.bss
+            } else if is(".gnu.sgstubs") {
+                &mut sizes.nsc
+            } else {
+                continue;
+            };
+            *field += shdr.sh_size;
+        }
+
+        // Missing debugging information only makes the result less
+        // informative
+        if let Err(e) = sizes.measure_objects(&elf, bytes) {
+            log::warn!("Could not measure the size of each object: {}", e);
+        }
+
+        Ok(sizes)
+    }
+
+    fn measure_objects(&mut self, elf: &Elf<'_>, bytes: &[u8]) -> gimli::Result<()> {
+        let dwarf = load_dwarf(elf, bytes)?;
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let name = match &unit.name {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => continue,
+            };
+
+            let mut size = 0;
+            let mut ranges = dwarf.unit_ranges(&unit)?;
+            while let Some(range) = ranges.next()? {
+                size += range.end.saturating_sub(range.begin);
+            }
+
+            if size > 0 {
+                *self.objects.entry(name).or_insert(0) += size;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the pairs of metric names and values.
+    fn metrics(&self, image: &str, objects: bool) -> Vec<(String, u64)> {
+        let mut metrics = vec![
+            (format!("{} .text", image), self.text),
+            (format!("{} .rodata", image), self.rodata),
+            (format!("{} .data", image), self.data),
+            (format!("{} .bss", image), self.bss),
+            (format!("{} NSC", image), self.nsc),
+        ];
+        if objects {
+            metrics.extend(
+                self.objects
+                    .iter()
+                    .map(|(name, &size)| (format!("{} {}", image, name), size)),
+            );
+        }
+        metrics
+    }
+}
+
+/// The part of `meta.json` needed to read the sizes.
+#[derive(Deserialize)]
+struct ArtifactsMetadata {
+    benchmark: BenchmarkType,
+    exe_names: ArtifactsExeNames,
+    matrix: Vec<ArtifactsMetaRun>,
+}
+
+#[derive(Deserialize)]
+struct ArtifactsExeNames {
+    secure: String,
+    non_secure: String,
+}
+
+#[derive(Deserialize)]
+struct ArtifactsMetaRun {
+    name: String,
+    build_opt: BTreeMap<String, matrix::Value>,
+    /// Missing in the metadata written by older versions
+    #[serde(default)]
+    sizes: Option<Sizes>,
+}
+
+pub fn run(opt: &SizeOpt) -> Result<(), Box<dyn Error>> {
+    let dir = &opt.dir;
+    let meta_path = dir.join("meta.json");
+    let meta: ArtifactsMetadata = std::fs::read(&meta_path)
+        .map_err(Box::from)
+        .and_then(|json| serde_json::from_slice(&json).map_err(Box::from))
+        .map_err(|e| SizeError::ReadMetadata(meta_path.clone(), e))?;
+
+    if meta.matrix.is_empty() {
+        return Err(SizeError::NoResults.into());
+    }
+
+    let mut configs = Vec::new();
+    for run in meta.matrix.iter() {
+        let sizes = match &run.sizes {
+            Some(sizes) => sizes.clone(),
+            None => Sizes::measure(
+                &dir.join(format!("{}.{}", run.name, meta.exe_names.secure)),
+                &dir.join(format!("{}.{}", run.name, meta.exe_names.non_secure)),
+            )?,
+        };
+
+        let mut metrics = sizes.secure.metrics("secure", opt.objects);
+        metrics.extend(sizes.non_secure.metrics("non-secure", opt.objects));
+        configs.push((run, metrics));
+    }
+
+    // The metrics which are non-zero in some configurations, in the order
+    // they first appear
+    let mut metric_names: Vec<&str> = Vec::new();
+    for (_, metrics) in configs.iter() {
+        for (name, size) in metrics.iter() {
+            if *size > 0 && !metric_names.contains(&&name[..]) {
+                metric_names.push(name);
+            }
+        }
+    }
+
+    let baseline_filter: Filter = opt.baseline.parse()?;
+    let mut baseline_axes = Vec::new();
+    collect_axes(&baseline_filter, &mut baseline_axes);
+    let find_baseline = |run: &ArtifactsMetaRun| {
+        configs.iter().find(|(candidate, _)| {
+            is_baseline_of(
+                &baseline_filter,
+                &baseline_axes,
+                &candidate.build_opt,
+                &run.build_opt,
+            )
+        })
+    };
+
+    let metric = |metrics: &[(String, u64)], name: &str| {
+        metrics
+            .iter()
+            .find(|(metric_name, _)| metric_name == name)
+            .map(|&(_, size)| size as f64)
+    };
+
+    let rows: Vec<Row<'_>> = configs
+        .iter()
+        .map(|(run, metrics)| {
+            let baseline = match find_baseline(run) {
+                Some((baseline, _)) if baseline.name == run.name => None,
+                Some((_, baseline)) => Some(baseline),
+                None => {
+                    log::warn!("No baseline configuration was found for '{}'", run.name);
+                    None
+                }
+            };
+
+            let cells = metric_names
+                .iter()
+                .map(|metric_name| {
+                    let value = metric(metrics, metric_name)?;
+                    let base = baseline
+                        .and_then(|b| metric(b, metric_name))
+                        .unwrap_or(f64::NAN);
+                    let relative = if base != 0.0 && base.is_finite() {
+                        Some(value / base - 1.0)
+                    } else {
+                        None
+                    };
+                    Some(Cell {
+                        value,
+                        absolute: value - base,
+                        relative,
+                    })
+                })
+                .collect();
+            (&run.name[..], cells)
+        })
+        .collect();
+
+    let table = Table {
+        benchmark: meta.benchmark,
+        caption: &opt.baseline,
+        // Each configuration has its own baseline
+        baseline: None,
+        metric_names: &metric_names,
+        descriptions: &BTreeMap::new(),
+        rows: &rows,
+    };
+
+    print!("{}", table.format(opt.format));
+
+    Ok(())
+}
+
+/// Check if `candidate` is the baseline configuration of `build_opt`, i.e.,
+/// it satisfies `filter` and has the same values for the axes other than
+/// `baseline_axes` (the axes referenced by `filter`).
+fn is_baseline_of(
+    filter: &Filter,
+    baseline_axes: &[&str],
+    candidate: &BTreeMap<String, matrix::Value>,
+    build_opt: &BTreeMap<String, matrix::Value>,
+) -> bool {
+    filter.eval(&|axis| candidate.get(axis))
+        && build_opt
+            .iter()
+            .filter(|(axis, _)| !baseline_axes.contains(&&axis[..]))
+            .all(|(axis, value)| candidate.get(axis) == Some(value))
+}
+
+/// Collect the names of the axes referenced by a filter expression.
+fn collect_axes<'a>(filter: &'a Filter, out: &mut Vec<&'a str>) {
+    match filter {
+        Filter::Enabled(axis) | Filter::Eq(axis, _) => {
+            if !out.contains(&&axis[..]) {
+                out.push(axis);
+            }
+        }
+        Filter::Not(x) => collect_axes(x, out),
+        Filter::And(x, y) | Filter::Or(x, y) => {
+            collect_axes(x, out);
+            collect_axes(y, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use goblin::elf::section_header::{SHT_NOBITS, SHT_PROGBITS, SHT_STRTAB};
+
+    /// Append each of `fields` as a little-endian integer of `size` bytes.
+    fn put(out: &mut Vec<u8>, fields: &[u32], size: usize) {
+        for &x in fields {
+            out.extend_from_slice(&x.to_le_bytes()[..size]);
+        }
+    }
+
+    /// Build an ELF32 file having the specified sections, given as
+    /// `(name, sh_type, sh_size)`.
+    fn elf_with_sections(sections: &[(&str, u32, u32)]) -> Vec<u8> {
+        const SIZEOF_EHDR: u32 = 52;
+        const SIZEOF_SHDR: u32 = 40;
+        let num_sections = sections.len() as u32 + 2;
+
+        let mut shstrtab = vec![0u8];
+        let mut names = Vec::new();
+        for &(name, _, _) in sections.iter().chain(&[(".shstrtab", 0, 0)]) {
+            names.push(shstrtab.len() as u32);
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+
+        let mut out = vec![0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        put(&mut out, &[2, 40], 2); // e_type = ET_EXEC, e_machine = EM_ARM
+        put(&mut out, &[1, 0, 0, SIZEOF_EHDR, 0x0500_0000], 4);
+        put(
+            &mut out,
+            &[
+                SIZEOF_EHDR,
+                0,
+                0,
+                SIZEOF_SHDR,
+                num_sections,
+                num_sections - 1,
+            ],
+            2,
+        );
+
+        let mut offset = SIZEOF_EHDR + SIZEOF_SHDR * num_sections;
+        put(&mut out, &[0; 10], 4);
+        for (&(_, sh_type, sh_size), &name) in sections.iter().zip(&names) {
+            put(
+                &mut out,
+                &[name, sh_type, 0, 0, offset, sh_size, 0, 0, 1, 0],
+                4,
+            );
+            if sh_type != SHT_NOBITS {
+                offset += sh_size;
+            }
+        }
+        let shstrtab_shdr = [names[sections.len()], SHT_STRTAB, 0, 0, offset];
+        put(&mut out, &shstrtab_shdr, 4);
+        put(&mut out, &[shstrtab.len() as u32, 0, 0, 1, 0], 4);
+
+        for &(_, sh_type, sh_size) in sections {
+            if sh_type != SHT_NOBITS {
+                out.resize(out.len() + sh_size as usize, 0);
+            }
+        }
+        out.extend_from_slice(&shstrtab);
+        out
+    }
+
+    #[test]
+    fn classify_sections() {
+        let bytes = elf_with_sections(&[
+            (".text", SHT_PROGBITS, 0x100),
+            (".text.hot", SHT_PROGBITS, 0x20),
+            (".textual", SHT_PROGBITS, 0x8),
+            (".rodata", SHT_PROGBITS, 0x10),
+            (".rodata.str1.1", SHT_PROGBITS, 0x4),
+            (".ARM.exidx", SHT_PROGBITS, 0x8),
+            (".data", SHT_PROGBITS, 0x30),
+            (".databank", SHT_PROGBITS, 0x8),
+            (".bss", SHT_NOBITS, 0x400),
+            (".gnu.sgstubs", SHT_PROGBITS, 0x40),
+        ]);
+        let sizes = ImageSizes::parse(&bytes).unwrap();
+
+        assert_eq!(sizes.text, 0x120);
+        assert_eq!(sizes.rodata, 0x14);
+        assert_eq!(sizes.data, 0x30);
+        assert_eq!(sizes.bss, 0x400);
+        assert_eq!(sizes.nsc, 0x40);
+        // No debugging information
+        assert!(sizes.objects.is_empty());
+    }
+
+    #[test]
+    fn reject_malformed_image() {
+        assert!(ImageSizes::parse(b"\x7fELF").is_err());
+    }
+
+    fn build_opt(mode: &str, ctx: bool, ses: &str) -> BTreeMap<String, matrix::Value> {
+        vec![
+            ("mode".to_owned(), matrix::Value::String(mode.to_owned())),
+            ("ctx".to_owned(), matrix::Value::Bool(ctx)),
+            ("ses".to_owned(), matrix::Value::String(ses.to_owned())),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn pair_baselines() {
+        let filter: Filter = "not ctx and ses=none".parse().unwrap();
+        let mut axes = Vec::new();
+        collect_axes(&filter, &mut axes);
+        assert_eq!(axes, ["ctx", "ses"]);
+
+        let configs = [
+            build_opt("ReleaseFast", false, "none"),
+            build_opt("ReleaseFast", true, "safe"),
+            build_opt("ReleaseSmall", true, "safe"),
+            build_opt("ReleaseSmall", false, "null"),
+        ];
+        let baseline_of = |config| {
+            configs
+                .iter()
+                .position(|candidate| is_baseline_of(&filter, &axes, candidate, config))
+        };
+
+        // A baseline configuration is its own baseline
+        assert_eq!(baseline_of(&configs[0]), Some(0));
+        assert_eq!(baseline_of(&configs[1]), Some(0));
+        // The values of the axes not referenced by the filter must match
+        assert_eq!(baseline_of(&configs[2]), None);
+        assert_eq!(baseline_of(&configs[3]), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Statistical summaries of repeated measurements (`--repeat`).
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The summary of the samples of a metric.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// The sample standard deviation. `None` if there's only one sample.
+    pub stddev: Option<f64>,
+    pub min: f64,
+    pub max: f64,
+    /// The 95% confidence interval of the mean based on Student's
+    /// t-distribution. `None` if there's only one sample.
+    pub ci95: Option<[f64; 2]>,
+}
+
+/// The two-sided 95% critical values of Student's t-distribution for 1–30
+/// degrees of freedom. The normal approximation is used beyond that.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+impl Summary {
+    /// Summarize the samples. Returns `None` if `samples` is empty.
+    pub fn new(samples: &[f64]) -> Option<Self> {
+        let n = samples.len();
+        if n == 0 {
+            return None;
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        // The two indices are equal if `n` is odd
+        let median = (sorted[(n - 1) / 2] + sorted[n / 2]) / 2.0;
+
+        let stddev = if n >= 2 {
+            let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            Some(var.sqrt())
+        } else {
+            None
+        };
+
+        let ci95 = stddev.map(|stddev| {
+            let t = T_95.get(n - 2).copied().unwrap_or(1.960);
+            let half_width = t * stddev / (n as f64).sqrt();
+            [mean - half_width, mean + half_width]
+        });
+
+        Some(Self {
+            n,
+            mean,
+            median,
+            stddev,
+            min: sorted[0],
+            max: sorted[n - 1],
+            ci95,
+        })
+    }
+}
+
+/// Summarize each metric found in `samples`.
+///
+/// Metrics are the numbers in the samples, identified by their JSON Pointer
+/// (e.g., `/NewTask` or `/42/cycles`), for which `is_metric` returns `true`.
+/// The metrics missing from some samples are ignored.
+pub fn summarize(samples: &[Value], is_metric: impl Fn(&str) -> bool) -> Map<String, Value> {
+    let mut paths = Vec::new();
+    if let Some(first) = samples.first() {
+        collect_numbers(first, &mut String::new(), &mut paths);
+    }
+
+    let mut summaries = Map::new();
+    for path in paths {
+        if !is_metric(&path) {
+            continue;
+        }
+
+        let values: Option<Vec<f64>> = samples
+            .iter()
+            .map(|sample| sample.pointer(&path).and_then(Value::as_f64))
+            .collect();
+        let values = match values {
+            Some(values) => values,
+            None => {
+                log::debug!("The metric {:?} is missing from some samples", path);
+                continue;
+            }
+        };
+
+        if let Some(summary) = Summary::new(&values) {
+            summaries.insert(path, serde_json::to_value(summary).unwrap());
+        }
+    }
+    summaries
+}
+
+/// Get the metrics in `value`, i.e., the numbers for whose JSON Pointers
+/// `is_metric` returns `true`.
+pub fn metrics(value: &Value, is_metric: impl Fn(&str) -> bool) -> Vec<(String, f64)> {
+    let mut paths = Vec::new();
+    collect_numbers(value, &mut String::new(), &mut paths);
+    paths
+        .into_iter()
+        .filter(|path| is_metric(path))
+        .filter_map(|path| {
+            let x = value.pointer(&path)?.as_f64()?;
+            Some((path, x))
+        })
+        .collect()
+}
+
+/// Find the numbers in `value` and push their JSON Pointers to `out`.
+fn collect_numbers(value: &Value, path: &mut String, out: &mut Vec<String>) {
+    let len = path.len();
+    match value {
+        Value::Number(_) => out.push(path.clone()),
+        Value::Array(elems) => {
+            for (i, elem) in elems.iter().enumerate() {
+                path.push_str(&format!("/{}", i));
+                collect_numbers(elem, path, out);
+                path.truncate(len);
+            }
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields.iter() {
+                path.push('/');
+                path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                collect_numbers(field, path, out);
+                path.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    /// Check `ci95` against the critical value `t`.
+    fn assert_ci95(samples: &[f64], t: f64) {
+        let summary = Summary::new(samples).unwrap();
+        let half_width = t * summary.stddev.unwrap() / (samples.len() as f64).sqrt();
+        let [lower, upper] = summary.ci95.unwrap();
+        assert_close(lower, summary.mean - half_width);
+        assert_close(upper, summary.mean + half_width);
+    }
+
+    #[test]
+    fn single_sample() {
+        let summary = Summary::new(&[42.0]).unwrap();
+        assert_eq!((summary.n, summary.mean, summary.median), (1, 42.0, 42.0));
+        assert!(summary.stddev.is_none());
+        assert!(summary.ci95.is_none());
+    }
+
+    #[test]
+    fn no_samples() {
+        assert!(Summary::new(&[]).is_none());
+    }
+
+    #[test]
+    fn median() {
+        assert_eq!(Summary::new(&[3.0, 1.0, 2.0]).unwrap().median, 2.0);
+        assert_eq!(Summary::new(&[4.0, 1.0, 3.0, 2.0]).unwrap().median, 2.5);
+    }
+
+    #[test]
+    fn ci95_df_1() {
+        // mean = 2, stddev = sqrt(2), so the half width is exactly t
+        let summary = Summary::new(&[1.0, 3.0]).unwrap();
+        assert_close(summary.stddev.unwrap(), 2.0f64.sqrt());
+        let [lower, upper] = summary.ci95.unwrap();
+        assert_close(lower, 2.0 - 12.706);
+        assert_close(upper, 2.0 + 12.706);
+    }
+
+    #[test]
+    fn ci95_df_30() {
+        let samples: Vec<f64> = (0..31).map(f64::from).collect();
+        assert_ci95(&samples, 2.042);
+    }
+
+    #[test]
+    fn ci95_df_over_30() {
+        let samples: Vec<f64> = (0..31).chain(100..110).map(f64::from).collect();
+        assert_ci95(&samples, 1.960);
+    }
+
+    #[test]
+    fn summarize_metrics() {
+        let samples = [
+            serde_json::json!({ "a": 1, "b/c": [2, 3], "name": "x" }),
+            serde_json::json!({ "a": 3, "b/c": [4, 5], "name": "y" }),
+        ];
+        let summaries = summarize(&samples, |path| path != "/b~1c/1");
+        let paths: Vec<&str> = summaries.keys().map(|key| key.as_str()).collect();
+        assert_eq!(paths, ["/a", "/b~1c/0"]);
+        assert_eq!(summaries["/a"]["mean"], 2.0);
+    }
+}
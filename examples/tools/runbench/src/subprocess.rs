@@ -1,5 +1,6 @@
 use std::{
     ffi::{OsStr, OsString},
+    fmt,
     process::{ExitStatus, Stdio},
 };
 use thiserror::Error;
@@ -18,9 +19,14 @@ pub enum SubprocessError {
     FailStatus { cmd: Cmd, status: ExitStatus },
 }
 
-#[derive(Debug)]
 pub struct Cmd(Vec<OsString>);
 
+impl fmt::Debug for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 pub struct CmdBuilder {
     cmd: Vec<OsString>,
 }
@@ -113,12 +119,10 @@ impl CmdBuilder {
 
         match command.spawn() {
             Ok(child) => Ok(child),
-            Err(e) => {
-                return Err(SubprocessError::Spawn {
-                    cmd: self.into_cmd(),
-                    error: e,
-                })
-            }
+            Err(e) => Err(SubprocessError::Spawn {
+                cmd: self.into_cmd(),
+                error: e,
+            }),
         }
     }
 }
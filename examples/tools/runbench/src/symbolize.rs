@@ -0,0 +1,554 @@
+//! Resolves the program counters sampled by the profiling applications
+//! (`profile-ses` and `profile-rtos`) to functions and source lines.
+//!
+//! Each PC is looked up in whichever of the Secure and Non-Secure images
+//! contains it, using the ELF symbol table for the function and the DWARF
+//! line number information for the file and line. The symbolization is done
+//! automatically after each run, and can be redone for an existing artifacts
+//! directory by the `symbolize` subcommand.
+use gimli::{EndianSlice, LittleEndian};
+use goblin::elf::Elf;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    error::Error,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+use thiserror::Error;
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+
+#[derive(StructOpt)]
+pub struct SymbolizeOpt {
+    /// The artifacts directory containing the results of `profile-ses` or
+    /// `profile-rtos`
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+}
+
+#[derive(Error, Debug)]
+pub enum SymbolizeError {
+    #[error("Could not read the executable {0:?}.\n\n{1}")]
+    ReadImage(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("Could not read the metadata in {0:?}.\n\n{1}")]
+    ReadMetadata(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("Could not read the result {0:?}.\n\n{1}")]
+    ReadResult(PathBuf, #[source] Box<dyn Error>),
+
+    #[error("Could not write {0:?}.\n\n{1}")]
+    Write(PathBuf, #[source] std::io::Error),
+
+    #[error("The benchmark '{0}' does not sample program counters")]
+    NotProfile(String),
+}
+
+/// Resolves addresses in a set of executable images.
+pub struct Symbolizer {
+    images: Vec<Image>,
+}
+
+struct Image {
+    name: String,
+    /// The address ranges of the executable sections
+    code_ranges: Vec<Range<u64>>,
+    /// Sorted by start address
+    functions: Vec<Function>,
+    /// Sorted by address. A row with `end_sequence` marks the end of the
+    /// previous row's range.
+    lines: Vec<LineRow>,
+    /// The file names referenced by `lines`
+    files: Vec<String>,
+}
+
+struct Function {
+    range: Range<u64>,
+    name: String,
+}
+
+struct LineRow {
+    address: u64,
+    file: Option<usize>,
+    line: Option<u64>,
+    end_sequence: bool,
+}
+
+/// The result of [`Symbolizer::lookup`].
+#[derive(Debug)]
+pub struct Location<'a> {
+    pub image: &'a str,
+    /// The function and the offset of the address in it
+    pub function: Option<(&'a str, u64)>,
+    pub file: Option<&'a str>,
+    pub line: Option<u64>,
+}
+
+impl Symbolizer {
+    /// Load the images. Each element of `images` is a pair of an image name
+    /// (e.g., `"secure"`) and the path to the ELF file.
+    pub fn load(images: &[(&str, &Path)]) -> Result<Self, SymbolizeError> {
+        let images = images
+            .iter()
+            .map(|&(name, path)| {
+                std::fs::read(path)
+                    .map_err(Box::from)
+                    .and_then(|bytes| Image::parse(name, &bytes))
+                    .map_err(|e| SymbolizeError::ReadImage(path.to_owned(), e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { images })
+    }
+
+    /// Find the image, function, and source line containing `pc`. Returns
+    /// `None` if no images contain `pc`.
+    pub fn lookup(&self, pc: u64) -> Option<Location<'_>> {
+        let image = self
+            .images
+            .iter()
+            .find(|image| image.code_ranges.iter().any(|r| r.contains(&pc)))?;
+
+        // The last function starting at or before `pc`
+        let i = image.functions.partition_point(|f| f.range.start <= pc);
+        let function = image.functions[..i]
+            .iter()
+            .rev()
+            .find(|f| f.range.contains(&pc))
+            .map(|f| (&f.name[..], pc - f.range.start));
+
+        let i = image.lines.partition_point(|row| row.address <= pc);
+        let row = i
+            .checked_sub(1)
+            .map(|i| &image.lines[i])
+            .filter(|row| !row.end_sequence);
+
+        Some(Location {
+            image: &image.name,
+            function,
+            file: row.and_then(|row| row.file).map(|i| &image.files[i][..]),
+            line: row.and_then(|row| row.line),
+        })
+    }
+}
+
+impl Image {
+    fn parse(name: &str, bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let elf = Elf::parse(bytes)?;
+
+        let code_ranges = elf
+            .section_headers
+            .iter()
+            .filter(|shdr| shdr.is_alloc() && shdr.is_executable() && shdr.sh_size > 0)
+            .map(|shdr| shdr.sh_addr..shdr.sh_addr + shdr.sh_size)
+            .collect();
+
+        let mut functions: Vec<_> = elf
+            .syms
+            .iter()
+            .filter(|sym| sym.is_function() && sym.st_size > 0)
+            .filter_map(|sym| {
+                // Clear the Thumb bit
+                let start = sym.st_value & !1;
+                Some(Function {
+                    range: start..start + sym.st_size,
+                    name: elf.strtab.get_unsafe(sym.st_name)?.to_owned(),
+                })
+            })
+            .collect();
+        functions.sort_by_key(|f| f.range.start);
+
+        let mut image = Self {
+            name: name.to_owned(),
+            code_ranges,
+            functions,
+            lines: Vec::new(),
+            files: Vec::new(),
+        };
+
+        // Malformed or missing debugging information only makes the result
+        // less informative
+        if let Err(e) = image.load_lines(&elf, bytes) {
+            log::warn!(
+                "Could not read the line number information of '{}': {}",
+                name,
+                e
+            );
+        }
+
+        // Put `end_sequence` rows before the rows starting a sequence at the
+        // same address
+        image
+            .lines
+            .sort_by_key(|row| (row.address, !row.end_sequence));
+
+        Ok(image)
+    }
+
+    fn load_lines(&mut self, elf: &Elf<'_>, bytes: &[u8]) -> gimli::Result<()> {
+        let dwarf = load_dwarf(elf, bytes)?;
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match &unit.line_program {
+                Some(program) => program.clone(),
+                None => continue,
+            };
+
+            // Maps file indices in this unit to indices in `self.files`
+            let mut file_map = HashMap::new();
+
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let file = match file_map.get(&row.file_index()) {
+                    Some(&file) => file,
+                    None => {
+                        let file = match row.file(header) {
+                            Some(entry) => {
+                                let mut path = String::new();
+                                if let Some(dir) = entry.directory(header) {
+                                    path += &dwarf.attr_string(&unit, dir)?.to_string_lossy();
+                                }
+                                let name = dwarf.attr_string(&unit, entry.path_name())?;
+                                let name = name.to_string_lossy();
+                                if name.starts_with('/') || path.is_empty() {
+                                    path = name.into_owned();
+                                } else {
+                                    if !path.ends_with('/') {
+                                        path.push('/');
+                                    }
+                                    path += &name;
+                                }
+                                self.files.push(path);
+                                Some(self.files.len() - 1)
+                            }
+                            None => None,
+                        };
+                        file_map.insert(row.file_index(), file);
+                        file
+                    }
+                };
+
+                self.lines.push(LineRow {
+                    address: row.address(),
+                    file,
+                    line: row.line(),
+                    end_sequence: row.end_sequence(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Load the DWARF debugging information of `elf`. Missing sections are
+/// treated as empty.
+pub fn load_dwarf<'a>(elf: &Elf<'_>, bytes: &'a [u8]) -> gimli::Result<gimli::Dwarf<Reader<'a>>> {
+    let load_section = |id: gimli::SectionId| -> Result<Reader<'a>, gimli::Error> {
+        let data = elf
+            .section_headers
+            .iter()
+            .find(|shdr| elf.shdr_strtab.get_unsafe(shdr.sh_name) == Some(id.name()))
+            .and_then(|shdr| bytes.get(shdr.file_range()))
+            .unwrap_or(&[]);
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    let no_sup = |_| Ok(EndianSlice::new(&[][..], LittleEndian));
+
+    gimli::Dwarf::load(load_section, no_sup)
+}
+
+/// Annotate the records of sampled program counters (objects having a `pc`
+/// field) with their locations, and count the samples in each function.
+/// Returns the annotated records and the histogram.
+pub fn symbolize(symbolizer: &Symbolizer, samples: &Value) -> (Value, Value) {
+    let mut annotated = samples.clone();
+    // `(image, function) -> number of samples`, in the order of appearance
+    let mut counts: Vec<((Value, Value), u64)> = Vec::new();
+    let mut total = 0;
+
+    for record in annotated.as_array_mut().into_iter().flatten() {
+        let fields = match record.as_object_mut() {
+            Some(fields) => fields,
+            None => continue,
+        };
+        let pc = match fields.get("pc").and_then(Value::as_u64) {
+            Some(pc) => pc,
+            None => continue,
+        };
+
+        let location = symbolizer.lookup(pc);
+        let image = json!(location.as_ref().map(|l| l.image));
+        let function = json!(location.as_ref().and_then(|l| l.function).map(|f| f.0));
+        fields.insert("image".to_owned(), image.clone());
+        fields.insert("function".to_owned(), function.clone());
+        fields.insert(
+            "offset".to_owned(),
+            json!(location.as_ref().and_then(|l| l.function).map(|f| f.1)),
+        );
+        fields.insert(
+            "file".to_owned(),
+            json!(location.as_ref().and_then(|l| l.file)),
+        );
+        fields.insert(
+            "line".to_owned(),
+            json!(location.as_ref().and_then(|l| l.line)),
+        );
+
+        let key = (image, function);
+        match counts.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((key, 1)),
+        }
+        total += 1;
+    }
+
+    // The most frequent functions first (the sort is stable)
+    counts.sort_by(|(_, x), (_, y)| y.cmp(x));
+
+    let histogram = json!({
+        "total": total,
+        "functions": counts
+            .into_iter()
+            .map(|((image, function), count)| json!({
+                "image": image,
+                "function": function,
+                "samples": count,
+                "fraction": count as f64 / total as f64,
+            }))
+            .collect::<Vec<_>>(),
+    });
+
+    (annotated, histogram)
+}
+
+/// The part of `meta.json` needed to symbolize the results.
+#[derive(Deserialize)]
+struct ArtifactsMetadata {
+    benchmark: super::BenchmarkType,
+    exe_names: ArtifactsExeNames,
+    matrix: Vec<ArtifactsMetaRun>,
+}
+
+#[derive(Deserialize)]
+struct ArtifactsExeNames {
+    secure: String,
+    non_secure: String,
+}
+
+#[derive(Deserialize)]
+struct ArtifactsMetaRun {
+    name: String,
+    #[serde(default)]
+    repeat: Option<usize>,
+}
+
+pub fn run(opt: &SymbolizeOpt) -> Result<(), Box<dyn Error>> {
+    let dir = &opt.dir;
+    let meta_path = dir.join("meta.json");
+    let meta: ArtifactsMetadata = std::fs::read(&meta_path)
+        .map_err(Box::from)
+        .and_then(|json| serde_json::from_slice(&json).map_err(Box::from))
+        .map_err(|e| SymbolizeError::ReadMetadata(meta_path.clone(), e))?;
+
+    if !meta.benchmark.traits().has_pc_samples() {
+        return Err(SymbolizeError::NotProfile(meta.benchmark.to_string()).into());
+    }
+
+    for run in meta.matrix.iter() {
+        log::info!("* Build option: {}", run.name);
+
+        let secure_elf = dir.join(format!("{}.{}", run.name, meta.exe_names.secure));
+        let nonsecure_elf = dir.join(format!("{}.{}", run.name, meta.exe_names.non_secure));
+        let symbolizer =
+            Symbolizer::load(&[("secure", &secure_elf), ("non_secure", &nonsecure_elf)])?;
+
+        let repeat = run.repeat.unwrap_or(1);
+        for k in 0..repeat {
+            let stem = super::app::output_stem(&run.name, k, repeat);
+            let path = dir.join(format!("{}.json", stem));
+            let samples: Value = match std::fs::read(&path) {
+                Ok(json) => serde_json::from_slice(&json)
+                    .map_err(|e| SymbolizeError::ReadResult(path.clone(), e.into()))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    log::warn!("{:?} does not exist; skipping", path);
+                    continue;
+                }
+                Err(e) => return Err(SymbolizeError::ReadResult(path, e.into()).into()),
+            };
+
+            let (annotated, histogram) = symbolize(&symbolizer, &samples);
+            for (suffix, value) in [("symbolized", annotated), ("histogram", histogram)].iter() {
+                let save_path = dir.join(format!("{}.{}.json", stem, suffix));
+                log::info!("Saving {:?}", save_path);
+                std::fs::write(&save_path, serde_json::to_string_pretty(value).unwrap())
+                    .map_err(|e| SymbolizeError::Write(save_path.clone(), e))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(range: Range<u64>, name: &str) -> Function {
+        Function {
+            range,
+            name: name.to_owned(),
+        }
+    }
+
+    fn row(address: u64, line: Option<u64>, end_sequence: bool) -> LineRow {
+        LineRow {
+            address,
+            file: line.map(|_| 0),
+            line,
+            end_sequence,
+        }
+    }
+
+    fn symbolizer() -> Symbolizer {
+        Symbolizer {
+            images: vec![
+                Image {
+                    name: "secure".to_owned(),
+                    code_ranges: vec![0x1000_0000..0x1000_0100, 0x1000_0200..0x1000_0300],
+                    functions: vec![function(0x1000_0000..0x1000_0100, "TCHandler")],
+                    lines: vec![],
+                    files: vec![],
+                },
+                Image {
+                    name: "non_secure".to_owned(),
+                    code_ranges: vec![0x1000..0x1200, 0x2000..0x2100],
+                    functions: vec![
+                        function(0x1000..0x1100, "outer"),
+                        // Nested in `outer`
+                        function(0x1040..0x1060, "inner"),
+                        // Adjacent
+                        function(0x1100..0x1110, "first"),
+                        function(0x1110..0x1120, "second"),
+                    ],
+                    // Two sequences, the second of which starts where the
+                    // first one ends
+                    lines: vec![
+                        row(0x1000, Some(10), false),
+                        row(0x1020, Some(12), false),
+                        row(0x1040, None, true),
+                        row(0x1040, Some(30), false),
+                        row(0x1100, None, true),
+                    ],
+                    files: vec!["main.zig".to_owned()],
+                },
+            ],
+        }
+    }
+
+    /// Get the function, the offset, and the line of `pc`
+    fn lookup(symbolizer: &Symbolizer, pc: u64) -> (Option<(&str, u64)>, Option<u64>) {
+        let location = symbolizer.lookup(pc).unwrap();
+        (location.function, location.line)
+    }
+
+    #[test]
+    fn nested_functions() {
+        let s = symbolizer();
+        assert_eq!(lookup(&s, 0x1010).0, Some(("outer", 0x10)));
+        assert_eq!(lookup(&s, 0x1050).0, Some(("inner", 0x10)));
+        assert_eq!(lookup(&s, 0x1060).0, Some(("outer", 0x60)));
+    }
+
+    #[test]
+    fn adjacent_functions() {
+        let s = symbolizer();
+        assert_eq!(lookup(&s, 0x110e).0, Some(("first", 0xe)));
+        assert_eq!(lookup(&s, 0x1110).0, Some(("second", 0)));
+        // Past the last function, but still in the code
+        assert_eq!(lookup(&s, 0x1120).0, None);
+        assert_eq!(lookup(&s, 0x2000).0, None);
+    }
+
+    #[test]
+    fn end_sequence() {
+        let s = symbolizer();
+        assert_eq!(lookup(&s, 0x1000).1, Some(10));
+        assert_eq!(lookup(&s, 0x103e).1, Some(12));
+        assert_eq!(lookup(&s, 0x1040).1, Some(30));
+        assert_eq!(lookup(&s, 0x10fe).1, Some(30));
+        assert_eq!(lookup(&s, 0x1100).1, None);
+
+        let location = s.lookup(0x1020).unwrap();
+        assert_eq!(location.file, Some("main.zig"));
+    }
+
+    #[test]
+    fn select_image() {
+        let s = symbolizer();
+        assert_eq!(s.lookup(0x1000_0004).unwrap().image, "secure");
+        assert_eq!(s.lookup(0x1000).unwrap().image, "non_secure");
+        assert_eq!(s.lookup(0x2004).unwrap().image, "non_secure");
+        assert!(s.lookup(0x1200).is_none());
+        assert!(s.lookup(0x1000_0100).is_none());
+        assert_eq!(s.lookup(0x1000_0200).unwrap().image, "secure");
+    }
+
+    #[test]
+    fn histogram() {
+        let samples = json!([
+            { "pc": 0x1010 },
+            { "pc": 0x1110 },
+            { "pc": 0x1112 },
+            { "pc": 0x1000_0000 },
+            { "pc": 0x3000 },
+            { "pc": 0x1114 },
+            { "pc": 0x1020 },
+            { "cycles": 100 },
+            42,
+        ]);
+        let (annotated, histogram) = symbolize(&symbolizer(), &samples);
+
+        assert_eq!(
+            annotated[0],
+            json!({
+                "pc": 0x1010,
+                "image": "non_secure",
+                "function": "outer",
+                "offset": 0x10,
+                "file": "main.zig",
+                "line": 10,
+            })
+        );
+        assert_eq!(
+            annotated[4],
+            json!({
+                "pc": 0x3000,
+                "image": null,
+                "function": null,
+                "offset": null,
+                "file": null,
+                "line": null,
+            })
+        );
+        assert_eq!(annotated[7], json!({ "cycles": 100 }));
+
+        // The most frequent first, and then in the order of appearance
+        assert_eq!(
+            histogram,
+            json!({
+                "total": 7,
+                "functions": [
+                    { "image": "non_secure", "function": "second", "samples": 3, "fraction": 3.0 / 7.0 },
+                    { "image": "non_secure", "function": "outer", "samples": 2, "fraction": 2.0 / 7.0 },
+                    { "image": "secure", "function": "TCHandler", "samples": 1, "fraction": 1.0 / 7.0 },
+                    { "image": null, "function": null, "samples": 1, "fraction": 1.0 / 7.0 },
+                ],
+            })
+        );
+    }
+}
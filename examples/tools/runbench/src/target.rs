@@ -21,9 +21,6 @@ pub enum RunError {
 }
 
 pub trait Target {
-    /// The build flags to pass to `zig build`.
-    fn zig_build_flags(&self) -> &[&str];
-
     /// Program the specified ELF images. Previous images may be erased. The
     /// actual operation may be deferred until `reset_and_get_output` is called.
     ///
@@ -32,18 +29,21 @@ pub trait Target {
     /// target chip.
     ///
     /// [kill]: https://en.wikipedia.org/wiki/Killer_poke
-    fn program(
-        &mut self,
-        paths: &[&Path],
-    ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + '_>>;
+    fn program(&mut self, paths: &[&Path]) -> DynFuture<'_, ()>;
 
     /// Run the currently programmed application from the beginning and capture
     /// its output.
-    fn reset_and_get_output(
-        &mut self,
-    ) -> Pin<Box<dyn Future<Output = Result<DynAsyncRead<'_>, Box<dyn Error>>> + '_>>;
+    fn reset_and_get_output(&mut self) -> DynFuture<'_, DynAsyncRead<'_>>;
+
+    /// Get the names and version strings of the external tools used to
+    /// control the target.
+    fn tool_versions(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
 }
 
+type DynFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error>>> + 'a>>;
+
 type DynAsyncRead<'a> = Pin<Box<dyn AsyncRead + 'a>>;
 
 /// Run the currently programmed application from the start and capture its
@@ -113,7 +113,7 @@ fn choose_serial(opt: &super::Opt) -> Result<String, ChooseSerialError> {
     } else {
         let ports = mio_serial::available_ports()?;
         log::trace!("Available ports: {:?}", ports);
-        if ports.len() == 0 {
+        if ports.is_empty() {
             return Err(ChooseSerialError::NoPortsAvailable);
         } else if ports.len() > 1 {
             return Err(ChooseSerialError::MultiplePortsAvailable(
@@ -0,0 +1,143 @@
+use std::{
+    error::Error,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio_serial::{Serial, SerialPortSettings};
+
+use super::{choose_serial, DynAsyncRead, DynFuture, Target};
+use crate::subprocess;
+
+/// Runs a program on a LPC55S69 target board.
+pub struct Lpc55s69Target<'a> {
+    pyocd_cmd: &'a OsStr,
+    pyocd_uid: Option<&'a OsStr>,
+    serial_port: String,
+    pyocd_version: String,
+}
+
+impl<'a> Lpc55s69Target<'a> {
+    // `Self` can't used to write this return type because of
+    // <https://github.com/rust-lang/rust/pull/62849>
+    pub(crate) async fn new(opt: &'a crate::Opt) -> Result<Lpc55s69Target<'a>, Box<dyn Error>> {
+        // Try launching pyocd
+        let version_info = subprocess::CmdBuilder::new(&opt.pyocd_cmd)
+            .arg("--version")
+            .spawn_capturing_stdout()
+            .await?;
+
+        let version = String::from_utf8_lossy(&version_info).trim().to_owned();
+        log::info!("PyOCD version: {:?}", version);
+
+        // Find the serial port for reading output
+        let serial_port = choose_serial(opt)?;
+
+        Ok(Self {
+            pyocd_cmd: &opt.pyocd_cmd,
+            pyocd_uid: opt.pyocd_uid.as_deref(),
+            serial_port,
+            pyocd_version: version,
+        })
+    }
+
+    fn pyocd_uid_args(&self) -> impl Iterator<Item = &'_ OsStr> {
+        if let Some(uid) = self.pyocd_uid {
+            either::Either::Left(vec![OsStr::new("--uid"), uid].into_iter())
+        } else {
+            either::Either::Right(std::iter::empty())
+        }
+    }
+}
+
+impl Target for Lpc55s69Target<'_> {
+    fn program(&mut self, paths: &[&Path]) -> DynFuture<'_, ()> {
+        #[derive(Error, Debug)]
+        enum LocalError {
+            #[error("PyOCD returned an error while programming the target.\n\n{0}")]
+            ProgramError(#[source] Box<dyn Error>),
+
+            #[error("Could not get the absolute path = {0}.\n\n{1}")]
+            PathError(PathBuf, #[source] Box<dyn Error>),
+        }
+
+        let paths: Vec<_> = paths.iter().map(|p| Path::to_owned(p)).collect();
+        Box::pin(async move {
+            for path in paths.iter() {
+                let path = path
+                    .canonicalize()
+                    .map_err(|e| LocalError::PathError(path.clone(), e.into()))?;
+                subprocess::CmdBuilder::new(self.pyocd_cmd)
+                    .arg("flash")
+                    .arg("-t")
+                    .arg("lpc55s69")
+                    .args(self.pyocd_uid_args())
+                    .arg("--format")
+                    .arg("elf")
+                    .arg(path)
+                    .spawn_expecting_success()
+                    .await
+                    .map_err(|e| LocalError::ProgramError(e.into()))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn reset_and_get_output(&mut self) -> DynFuture<'_, DynAsyncRead<'_>> {
+        #[derive(Error, Debug)]
+        enum LocalError {
+            #[error("PyOCD returned an error while halting the target.\n\n{0}")]
+            Halt(#[source] Box<dyn Error>),
+
+            #[error("PyOCD returned an error while resetting the target.\n\n{0}")]
+            Reset(#[source] Box<dyn Error>),
+
+            #[error("Could not open the serial port.\n\n{0}")]
+            Serial(#[source] Box<dyn Error>),
+        }
+
+        Box::pin(async move {
+            // Halt the board
+            subprocess::CmdBuilder::new(self.pyocd_cmd)
+                .arg("cmd")
+                .arg("-t")
+                .arg("lpc55s69")
+                .args(self.pyocd_uid_args())
+                .arg("-c")
+                .arg("halt")
+                .spawn_expecting_success()
+                .await
+                .map_err(|e| LocalError::Halt(e.into()))?;
+
+            // Open the serial port first
+            let serial = Serial::from_path(
+                &self.serial_port,
+                &SerialPortSettings {
+                    baud_rate: 115200,
+                    timeout: std::time::Duration::from_secs(60),
+                    ..Default::default()
+                },
+            )
+            .map_err(|e| LocalError::Serial(e.into()))?;
+
+            // Reset the board
+            subprocess::CmdBuilder::new(self.pyocd_cmd)
+                .arg("cmd")
+                .arg("-t")
+                .arg("lpc55s69")
+                .args(self.pyocd_uid_args())
+                .arg("-c")
+                .arg("reset")
+                .spawn_expecting_success()
+                .await
+                .map_err(|e| LocalError::Reset(e.into()))?;
+
+            Ok(Box::pin(serial) as DynAsyncRead<'_>)
+        })
+    }
+
+    fn tool_versions(&self) -> Vec<(&'static str, String)> {
+        vec![("pyocd", self.pyocd_version.clone())]
+    }
+}
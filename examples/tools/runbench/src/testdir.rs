@@ -0,0 +1,37 @@
+//! Temporary directories for unit tests.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A directory in the temporary directory, which is removed with its
+/// contents when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory. `name` must be unique among tests.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("tzmcfi_runbench-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Create a file in the directory.
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
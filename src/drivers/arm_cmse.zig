@@ -244,6 +244,13 @@ test "AddressInfo has the correct layout" {
 /// See “ARM®v8-M Security Extensions: Requirements on Development Tools” for
 /// other guidelines regarding the use of Non-Secure-callable functions.
 pub fn exportNonSecureCallable(comptime name: []const u8, comptime func: extern fn (usize, usize, usize, usize) usize) void {
+    exportNonSecureCallableInSection(name, func, ".gnu.sgstubs");
+}
+
+/// Like `exportNonSecureCallable`, but places the veneer in the specified
+/// section, which must also be included in the Non-Secure-callable region.
+/// This allows a linker script to pin the veneer at a particular location.
+pub fn exportNonSecureCallableInSection(comptime name: []const u8, comptime func: extern fn (usize, usize, usize, usize) usize, comptime section: []const u8) void {
     const Veneer = struct {
         fn veneer() callconv(.Naked) void {
             // Work-around for a code generation issue in ReleaseSmall builds
@@ -289,8 +296,8 @@ pub fn exportNonSecureCallable(comptime name: []const u8, comptime func: extern
             unreachable;
         }
     };
-    @export(Veneer.veneer, .{ .name = name, .linkage = .Strong, .section = ".gnu.sgstubs" });
-    @export(Veneer.veneer, .{ .name = "__acle_se_" ++ name, .linkage = .Strong, .section = ".gnu.sgstubs" });
+    @export(Veneer.veneer, .{ .name = name, .linkage = .Strong, .section = section });
+    @export(Veneer.veneer, .{ .name = "__acle_se_" ++ name, .linkage = .Strong, .section = section });
 }
 
 /// Security Attribution Unit.
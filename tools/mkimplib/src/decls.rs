@@ -0,0 +1,672 @@
+//! Generates C and Zig declarations of Secure gateways.
+use gimli::{AttributeValue, EndianSlice, LittleEndian};
+use goblin::elf::Elf;
+use std::{collections::HashMap, io};
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+
+/// The signature of a Secure gateway.
+pub struct Signature {
+    pub ret: Type,
+    pub params: Vec<(Option<String>, Type)>,
+}
+
+impl Signature {
+    /// The signature shared by all functions exported through
+    /// `arm_cmse.exportNonSecureCallable`.
+    pub fn uniform() -> Self {
+        Self {
+            ret: Type::Usize,
+            params: (0..4)
+                .map(|i| (Some(format!("r{}", i)), Type::Usize))
+                .collect(),
+        }
+    }
+}
+
+/// A parameter or return type expressible in both of C and Zig.
+pub enum Type {
+    Void,
+    Bool,
+    Char,
+    Int {
+        signed: bool,
+        size: u8,
+    },
+    /// A pointer-sized unsigned integer
+    Usize,
+    Pointer {
+        is_const: bool,
+        pointee: Box<Type>,
+    },
+    /// The pointee type of a pointer whose type is not expressible
+    Opaque,
+}
+
+impl Type {
+    fn to_c(&self) -> String {
+        match self {
+            Type::Void | Type::Opaque => "void".to_owned(),
+            Type::Bool => "bool".to_owned(),
+            Type::Char => "char".to_owned(),
+            Type::Int { signed, size } => {
+                format!(
+                    "{}int{}_t",
+                    if *signed { "" } else { "u" },
+                    *size as u32 * 8
+                )
+            }
+            Type::Usize => "uintptr_t".to_owned(),
+            Type::Pointer { is_const, pointee } => {
+                let pointee = pointee.to_c();
+                let constness = if *is_const { "const " } else { "" };
+                if pointee.ends_with('*') {
+                    format!("{}{}*", constness, pointee)
+                } else {
+                    format!("{}{} *", constness, pointee)
+                }
+            }
+        }
+    }
+
+    fn to_zig(&self) -> String {
+        match self {
+            Type::Void => "void".to_owned(),
+            Type::Opaque => "c_void".to_owned(),
+            Type::Bool => "bool".to_owned(),
+            Type::Char => "u8".to_owned(),
+            Type::Int { signed, size } => {
+                format!("{}{}", if *signed { "i" } else { "u" }, *size as u32 * 8)
+            }
+            Type::Usize => "usize".to_owned(),
+            Type::Pointer { is_const, pointee } => {
+                let constness = if *is_const { "const " } else { "" };
+                match **pointee {
+                    Type::Void | Type::Opaque => format!("?*{}c_void", constness),
+                    _ => format!("[*c]{}{}", constness, pointee.to_zig()),
+                }
+            }
+        }
+    }
+}
+
+/// Look up the signatures of the specified functions in the DWARF debugging
+/// information of `elf`. Functions which are not found or have parameters of
+/// inexpressible types are omitted from the result.
+pub fn find_signatures(elf: &Elf<'_>, bytes: &[u8], names: &[&str]) -> HashMap<String, Signature> {
+    let load_section = |id: gimli::SectionId| -> Result<Reader<'_>, gimli::Error> {
+        let data = elf
+            .section_headers
+            .iter()
+            .find(|shdr| elf.shdr_strtab.get_unsafe(shdr.sh_name) == Some(id.name()))
+            .and_then(|shdr| bytes.get(shdr.file_range()))
+            .unwrap_or(&[]);
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    let no_sup = |_| Ok(EndianSlice::new(&[][..], LittleEndian));
+
+    let mut signatures = HashMap::new();
+
+    let dwarf = match gimli::Dwarf::load(load_section, no_sup) {
+        Ok(dwarf) => dwarf,
+        Err(_) => return signatures,
+    };
+
+    // Malformed debugging information is silently ignored because it is
+    // entirely optional
+    let _ = (|| -> gimli::Result<()> {
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if entry.tag() != gimli::DW_TAG_subprogram
+                    || entry.attr_value(gimli::DW_AT_low_pc)?.is_none()
+                {
+                    continue;
+                }
+
+                let name = match entry.attr_value(gimli::DW_AT_name)? {
+                    Some(value) => dwarf.attr_string(&unit, value)?,
+                    None => continue,
+                };
+                let name = name.to_string_lossy();
+                if !names.contains(&&*name) || signatures.contains_key(&*name) {
+                    continue;
+                }
+
+                if let Some(signature) = subprogram_signature(&dwarf, &unit, entry.offset())? {
+                    signatures.insert(name.into_owned(), signature);
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    signatures
+}
+
+fn subprogram_signature(
+    dwarf: &gimli::Dwarf<Reader<'_>>,
+    unit: &gimli::Unit<Reader<'_>>,
+    offset: gimli::UnitOffset,
+) -> gimli::Result<Option<Signature>> {
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let root = tree.root()?;
+
+    let ret = match resolve_type(unit, root.entry().attr_value(gimli::DW_AT_type)?, true, 0)? {
+        Some(ty) => ty,
+        None => return Ok(None),
+    };
+
+    let mut params = Vec::new();
+    let mut children = root.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        match entry.tag() {
+            gimli::DW_TAG_formal_parameter => {}
+            gimli::DW_TAG_unspecified_parameters => return Ok(None),
+            _ => continue,
+        }
+
+        let name = match entry.attr_value(gimli::DW_AT_name)? {
+            Some(value) => Some(
+                dwarf
+                    .attr_string(unit, value)?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            None => None,
+        };
+
+        match resolve_type(unit, entry.attr_value(gimli::DW_AT_type)?, true, 0)? {
+            Some(Type::Void) | None => return Ok(None),
+            Some(ty) => params.push((name, ty)),
+        }
+    }
+
+    Ok(Some(Signature { ret, params }))
+}
+
+/// Convert the type referenced by `DW_AT_type` to `Type`. Returns `None` if
+/// the type is not expressible.
+///
+/// `top_level` indicates that the type is of a parameter or a return value,
+/// in which case `const` qualifiers are dropped because they don't affect
+/// the calling convention.
+fn resolve_type(
+    unit: &gimli::Unit<Reader<'_>>,
+    attr: Option<AttributeValue<Reader<'_>>>,
+    top_level: bool,
+    depth: usize,
+) -> gimli::Result<Option<Type>> {
+    let offset = match attr {
+        // A missing `DW_AT_type` represents `void`
+        None => return Ok(Some(Type::Void)),
+        Some(AttributeValue::UnitRef(offset)) => offset,
+        Some(_) => return Ok(None),
+    };
+
+    if depth > 16 {
+        return Ok(None);
+    }
+
+    let entry = unit.entry(offset)?;
+    let ty_attr = entry.attr_value(gimli::DW_AT_type)?;
+    let byte_size = entry
+        .attr_value(gimli::DW_AT_byte_size)?
+        .and_then(|v| v.udata_value())
+        .filter(|size| [1, 2, 4, 8].contains(size));
+
+    Ok(match entry.tag() {
+        gimli::DW_TAG_base_type => {
+            let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                Some(AttributeValue::Encoding(encoding)) => encoding,
+                _ => return Ok(None),
+            };
+            match (encoding, byte_size) {
+                (gimli::DW_ATE_boolean, Some(1)) => Some(Type::Bool),
+                (gimli::DW_ATE_signed_char, Some(1)) | (gimli::DW_ATE_unsigned_char, Some(1)) => {
+                    Some(Type::Char)
+                }
+                (gimli::DW_ATE_signed, Some(size)) | (gimli::DW_ATE_signed_char, Some(size)) => {
+                    Some(Type::Int {
+                        signed: true,
+                        size: size as u8,
+                    })
+                }
+                (gimli::DW_ATE_unsigned, Some(size))
+                | (gimli::DW_ATE_unsigned_char, Some(size)) => Some(Type::Int {
+                    signed: false,
+                    size: size as u8,
+                }),
+                _ => None,
+            }
+        }
+        gimli::DW_TAG_enumeration_type => {
+            // The underlying type is only present in DWARF 3 and later.
+            // Otherwise, the enumeration is signed if any of the enumerators
+            // is negative.
+            let underlying = match ty_attr {
+                Some(_) => resolve_type(unit, ty_attr, true, depth + 1)?,
+                None => None,
+            };
+            let signed = match underlying {
+                Some(Type::Int { signed, .. }) => signed,
+                _ => has_negative_enumerator(unit, offset)?,
+            };
+            byte_size.map(|size| Type::Int {
+                signed,
+                size: size as u8,
+            })
+        }
+        gimli::DW_TAG_typedef | gimli::DW_TAG_volatile_type => {
+            resolve_type(unit, ty_attr, top_level, depth + 1)?
+        }
+        gimli::DW_TAG_const_type if top_level => resolve_type(unit, ty_attr, true, depth + 1)?,
+        gimli::DW_TAG_pointer_type => {
+            // Determine the constness of the pointee
+            let (is_const, pointee_attr) = match ty_attr {
+                Some(AttributeValue::UnitRef(pointee_offset)) => {
+                    let pointee = unit.entry(pointee_offset)?;
+                    if pointee.tag() == gimli::DW_TAG_const_type {
+                        (true, pointee.attr_value(gimli::DW_AT_type)?)
+                    } else {
+                        (false, ty_attr)
+                    }
+                }
+                _ => (false, ty_attr),
+            };
+            let pointee =
+                resolve_type(unit, pointee_attr, false, depth + 1)?.unwrap_or(Type::Opaque);
+            Some(Type::Pointer {
+                is_const,
+                pointee: Box::new(pointee),
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Check if any of the enumerators of the enumeration type at `offset` is
+/// negative.
+fn has_negative_enumerator(
+    unit: &gimli::Unit<Reader<'_>>,
+    offset: gimli::UnitOffset,
+) -> gimli::Result<bool> {
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        if entry.tag() != gimli::DW_TAG_enumerator {
+            continue;
+        }
+        // Only `DW_FORM_sdata` is unambiguously signed
+        if let Some(AttributeValue::Sdata(value)) = entry.attr_value(gimli::DW_AT_const_value)? {
+            if value < 0 {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Generate a C header file declaring the specified Secure gateways.
+pub fn write_c_header(
+    writer: &mut dyn io::Write,
+    gateways: &[(&str, &Signature)],
+) -> Result<(), io::Error> {
+    writeln!(writer, "#pragma once")?;
+    writeln!(writer, "/*")?;
+    writeln!(
+        writer,
+        " * Secure gateway declarations generated by tzmcfi_mkimplib. Do not edit."
+    )?;
+    writeln!(writer, " */")?;
+    writeln!(writer)?;
+    writeln!(writer, "#include <stdbool.h>")?;
+    writeln!(writer, "#include <stdint.h>")?;
+    writeln!(writer)?;
+    writeln!(writer, "#ifdef __cplusplus")?;
+    writeln!(writer, "extern \"C\" {{")?;
+    writeln!(writer, "#endif")?;
+    writeln!(writer)?;
+    for (name, signature) in gateways {
+        let params: Vec<_> = signature
+            .params
+            .iter()
+            .map(|(param_name, ty)| {
+                let ty = ty.to_c();
+                match param_name {
+                    Some(param_name) if ty.ends_with('*') => format!("{}{}", ty, param_name),
+                    Some(param_name) => format!("{} {}", ty, param_name),
+                    None => ty,
+                }
+            })
+            .collect();
+        let params = if params.is_empty() {
+            "void".to_owned()
+        } else {
+            params.join(", ")
+        };
+        let ret = signature.ret.to_c();
+        if ret.ends_with('*') {
+            writeln!(writer, "{}{}({});", ret, name, params)?;
+        } else {
+            writeln!(writer, "{} {}({});", ret, name, params)?;
+        }
+    }
+    writeln!(writer)?;
+    writeln!(writer, "#ifdef __cplusplus")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer, "#endif")?;
+    Ok(())
+}
+
+/// Generate a Zig source file declaring the specified Secure gateways.
+pub fn write_zig_decls(
+    writer: &mut dyn io::Write,
+    gateways: &[(&str, &Signature)],
+) -> Result<(), io::Error> {
+    writeln!(
+        writer,
+        "// Secure gateway declarations generated by `tzmcfi_mkimplib`. Do not edit."
+    )?;
+    for (name, signature) in gateways {
+        let params: Vec<_> = signature
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, (param_name, ty))| {
+                let param_name = match param_name {
+                    Some(param_name) => zig_ident(param_name),
+                    None => format!("_arg{}", i),
+                };
+                format!("{}: {}", param_name, ty.to_zig())
+            })
+            .collect();
+        writeln!(
+            writer,
+            "pub extern fn {}({}) {};",
+            zig_ident(name),
+            params.join(", "),
+            signature.ret.to_zig()
+        )?;
+    }
+    Ok(())
+}
+
+/// Quote an identifier if it's not a valid Zig identifier.
+fn zig_ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "align",
+        "allowzero",
+        "and",
+        "anyframe",
+        "asm",
+        "async",
+        "await",
+        "break",
+        "catch",
+        "comptime",
+        "const",
+        "continue",
+        "defer",
+        "else",
+        "enum",
+        "errdefer",
+        "error",
+        "export",
+        "extern",
+        "false",
+        "fn",
+        "for",
+        "if",
+        "inline",
+        "noalias",
+        "noinline",
+        "null",
+        "or",
+        "orelse",
+        "packed",
+        "pub",
+        "resume",
+        "return",
+        "struct",
+        "suspend",
+        "switch",
+        "test",
+        "threadlocal",
+        "true",
+        "try",
+        "undefined",
+        "union",
+        "unreachable",
+        "usingnamespace",
+        "var",
+        "volatile",
+        "while",
+    ];
+    let is_plain = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name);
+    if is_plain {
+        name.to_owned()
+    } else {
+        format!("@\"{}\"", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::ElfBuilder;
+    use goblin::elf::header::ET_REL;
+
+    fn write_c(gateways: &[(&str, &Signature)]) -> String {
+        let mut out = Vec::new();
+        write_c_header(&mut out, gateways).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn write_zig(gateways: &[(&str, &Signature)]) -> String {
+        let mut out = Vec::new();
+        write_zig_decls(&mut out, gateways).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Get the function declarations from the generated code
+    fn decls(code: &str) -> Vec<&str> {
+        code.lines()
+            .filter(|line| line.ends_with(';') && !line.starts_with("//"))
+            .collect()
+    }
+
+    fn int(signed: bool, size: u8) -> Type {
+        Type::Int { signed, size }
+    }
+
+    fn pointer(is_const: bool, pointee: Type) -> Type {
+        Type::Pointer {
+            is_const,
+            pointee: Box::new(pointee),
+        }
+    }
+
+    #[test]
+    fn write_declarations() {
+        let log = Signature {
+            ret: Type::Void,
+            params: vec![
+                (Some("message".to_owned()), pointer(true, Type::Char)),
+                (None, Type::Usize),
+            ],
+        };
+        let read = Signature {
+            ret: pointer(false, Type::Opaque),
+            params: vec![
+                (Some("error".to_owned()), pointer(false, int(true, 4))),
+                (
+                    Some("out".to_owned()),
+                    pointer(false, pointer(true, Type::Opaque)),
+                ),
+            ],
+        };
+        let poll = Signature {
+            ret: Type::Bool,
+            params: vec![],
+        };
+        let gateways = [("TCLog", &log), ("TCRead", &read), ("test", &poll)];
+
+        assert_eq!(
+            decls(&write_c(&gateways)),
+            [
+                "void TCLog(const char *message, uintptr_t);",
+                "void *TCRead(int32_t *error, const void **out);",
+                "bool test(void);",
+            ]
+        );
+        assert_eq!(
+            decls(&write_zig(&gateways)),
+            [
+                "pub extern fn TCLog(message: [*c]const u8, _arg1: usize) void;",
+                "pub extern fn TCRead(@\"error\": [*c]i32, out: [*c]?*const c_void) ?*c_void;",
+                "pub extern fn @\"test\"() bool;",
+            ]
+        );
+    }
+
+    /// Build a DWARF 4 compilation unit declaring
+    /// `enum1 get(enum1 a, enum2 b, enum3 c)`, where `enum1` has no negative
+    /// enumerators, `enum2` has one, and `enum3` is based on `int8_t`.
+    fn enum_image() -> Vec<u8> {
+        use gimli::constants::*;
+
+        const ABBREV_CU: u8 = 1;
+        const ABBREV_BASE: u8 = 2;
+        const ABBREV_ENUM: u8 = 3;
+        const ABBREV_ENUM_TYPED: u8 = 4;
+        const ABBREV_ENUMERATOR: u8 = 5;
+        const ABBREV_SUBPROGRAM: u8 = 6;
+        const ABBREV_PARAM: u8 = 7;
+
+        // The code, the tag, `DW_CHILDREN_yes`, and the attribute specifications
+        type Abbrev = (u8, DwTag, bool, &'static [(DwAt, DwForm)]);
+        let abbrevs: &[Abbrev] = &[
+            (ABBREV_CU, DW_TAG_compile_unit, true, &[]),
+            (
+                ABBREV_BASE,
+                DW_TAG_base_type,
+                false,
+                &[
+                    (DW_AT_encoding, DW_FORM_data1),
+                    (DW_AT_byte_size, DW_FORM_data1),
+                ],
+            ),
+            (
+                ABBREV_ENUM,
+                DW_TAG_enumeration_type,
+                true,
+                &[(DW_AT_byte_size, DW_FORM_data1)],
+            ),
+            (
+                ABBREV_ENUM_TYPED,
+                DW_TAG_enumeration_type,
+                true,
+                &[(DW_AT_byte_size, DW_FORM_data1), (DW_AT_type, DW_FORM_ref4)],
+            ),
+            (
+                ABBREV_ENUMERATOR,
+                DW_TAG_enumerator,
+                false,
+                &[(DW_AT_const_value, DW_FORM_sdata)],
+            ),
+            (
+                ABBREV_SUBPROGRAM,
+                DW_TAG_subprogram,
+                true,
+                &[
+                    (DW_AT_name, DW_FORM_string),
+                    (DW_AT_low_pc, DW_FORM_addr),
+                    (DW_AT_type, DW_FORM_ref4),
+                ],
+            ),
+            (
+                ABBREV_PARAM,
+                DW_TAG_formal_parameter,
+                false,
+                &[(DW_AT_name, DW_FORM_string), (DW_AT_type, DW_FORM_ref4)],
+            ),
+        ];
+        let mut debug_abbrev = Vec::new();
+        for (code, tag, has_children, attrs) in abbrevs.iter() {
+            debug_abbrev.extend_from_slice(&[*code, tag.0 as u8, *has_children as u8]);
+            for (at, form) in attrs.iter() {
+                debug_abbrev.extend_from_slice(&[at.0 as u8, form.0 as u8]);
+            }
+            debug_abbrev.extend_from_slice(&[0, 0]);
+        }
+        debug_abbrev.push(0);
+
+        // The unit header without `unit_length`: `version`,
+        // `debug_abbrev_offset`, and `address_size`
+        let mut unit = vec![4, 0, 0, 0, 0, 0, 4];
+        // References are relative to the start of the unit header
+        let offset = |unit: &Vec<u8>| (unit.len() as u32 + 4).to_le_bytes();
+
+        unit.push(ABBREV_CU);
+
+        let int8 = offset(&unit);
+        unit.extend_from_slice(&[ABBREV_BASE, DW_ATE_signed.0, 1]);
+
+        let enum1 = offset(&unit);
+        unit.extend_from_slice(&[ABBREV_ENUM, 4]);
+        unit.extend_from_slice(&[ABBREV_ENUMERATOR, 0, ABBREV_ENUMERATOR, 1, 0]);
+
+        // -1 is encoded as 0x7f in SLEB128
+        let enum2 = offset(&unit);
+        unit.extend_from_slice(&[ABBREV_ENUM, 4]);
+        unit.extend_from_slice(&[ABBREV_ENUMERATOR, 0x7f, ABBREV_ENUMERATOR, 1, 0]);
+
+        let enum3 = offset(&unit);
+        unit.extend_from_slice(&[ABBREV_ENUM_TYPED, 1]);
+        unit.extend_from_slice(&int8);
+        unit.extend_from_slice(&[ABBREV_ENUMERATOR, 1, 0]);
+
+        unit.push(ABBREV_SUBPROGRAM);
+        unit.extend_from_slice(b"get\0");
+        unit.extend_from_slice(&0x1001u32.to_le_bytes());
+        unit.extend_from_slice(&enum1);
+        for (name, ty) in [(b"a\0", enum1), (b"b\0", enum2), (b"c\0", enum3)].iter() {
+            unit.push(ABBREV_PARAM);
+            unit.extend_from_slice(*name);
+            unit.extend_from_slice(ty);
+        }
+        unit.extend_from_slice(&[0, 0]);
+
+        let mut debug_info = (unit.len() as u32).to_le_bytes().to_vec();
+        debug_info.extend_from_slice(&unit);
+
+        let mut elf = ElfBuilder::new(ET_REL);
+        elf.section(".debug_abbrev", 0, 0, &debug_abbrev);
+        elf.section(".debug_info", 0, 0, &debug_info);
+        elf.build()
+    }
+
+    #[test]
+    fn enumeration_signedness() {
+        let bytes = enum_image();
+        let elf = Elf::parse(&bytes).unwrap();
+        let signatures = find_signatures(&elf, &bytes, &["get"]);
+
+        assert_eq!(
+            decls(&write_c(&[("get", &signatures["get"])])),
+            ["uint32_t get(uint32_t a, int32_t b, int8_t c);"]
+        );
+        assert_eq!(
+            decls(&write_zig(&[("get", &signatures["get"])])),
+            ["pub extern fn get(a: u32, b: i32, c: i8) u32;"]
+        );
+    }
+}
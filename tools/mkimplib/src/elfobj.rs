@@ -0,0 +1,172 @@
+//! Generates an ELF relocatable object file serving as a CMSE import library.
+//!
+//! The produced object mirrors what `arm-none-eabi-ld --cmse-implib` emits:
+//! it contains no code or data, only a symbol table in which every entry is
+//! a global symbol bound to an absolute address (`SHN_ABS`).
+use goblin::elf::{
+    header::{EM_ARM, ET_REL},
+    section_header::{SHN_ABS, SHT_STRTAB, SHT_SYMTAB},
+    sym::{STB_GLOBAL, STT_FUNC, STT_NOTYPE},
+};
+use std::io;
+
+/// `EF_ARM_EABI_VER5`
+const EF_ARM_EABI_VER5: u32 = 0x0500_0000;
+
+const SIZEOF_EHDR: usize = 52;
+const SIZEOF_SHDR: usize = 40;
+const SIZEOF_SYM: usize = 16;
+
+/// A symbol to be included in an import library.
+pub struct ImplibSymbol<'a> {
+    pub name: &'a str,
+    /// The symbol value. For a Thumb function, the lowest bit must be set.
+    pub value: u32,
+    pub size: u32,
+    /// `false` to define a plain value (`STT_NOTYPE`)
+    pub is_function: bool,
+}
+
+/// Write an ELF32 relocatable object file defining `symbols` as absolute
+/// symbols.
+pub fn write_elf_implib(
+    writer: &mut dyn io::Write,
+    symbols: &[ImplibSymbol<'_>],
+) -> Result<(), io::Error> {
+    // Section indices (`.symtab` is at index 1)
+    const SHNDX_STRTAB: u32 = 2;
+    const SHNDX_SHSTRTAB: u32 = 3;
+    const NUM_SECTIONS: usize = 4;
+
+    // `.shstrtab`
+    let mut shstrtab = vec![0u8];
+    let mut add_shstr = |name: &str| {
+        let offset = shstrtab.len() as u32;
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+        offset
+    };
+    let name_symtab = add_shstr(".symtab");
+    let name_strtab = add_shstr(".strtab");
+    let name_shstrtab = add_shstr(".shstrtab");
+
+    // `.strtab` and `.symtab`. The first entry of the symbol table is reserved
+    // and must be all zero.
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; SIZEOF_SYM];
+    for sym in symbols {
+        let st_name = strtab.len() as u32;
+        strtab.extend_from_slice(sym.name.as_bytes());
+        strtab.push(0);
+
+        put_u32(&mut symtab, st_name);
+        put_u32(&mut symtab, sym.value);
+        put_u32(&mut symtab, sym.size);
+        let st_type = if sym.is_function {
+            STT_FUNC
+        } else {
+            STT_NOTYPE
+        };
+        symtab.push((STB_GLOBAL << 4) | st_type); // st_info
+        symtab.push(0); // st_other (STV_DEFAULT)
+        put_u16(&mut symtab, SHN_ABS as u16);
+    }
+
+    // Lay out the file
+    let symtab_offset = SIZEOF_EHDR;
+    let strtab_offset = symtab_offset + symtab.len();
+    let shstrtab_offset = strtab_offset + strtab.len();
+    let shdrs_offset = align4(shstrtab_offset + shstrtab.len());
+
+    let mut out = Vec::with_capacity(shdrs_offset + SIZEOF_SHDR * NUM_SECTIONS);
+
+    // ELF header
+    out.extend_from_slice(&[
+        0x7f, b'E', b'L', b'F', //
+        1,    // EI_CLASS = ELFCLASS32
+        1,    // EI_DATA = ELFDATA2LSB
+        1,    // EI_VERSION = EV_CURRENT
+        0,    // EI_OSABI = ELFOSABI_NONE
+        0,    // EI_ABIVERSION
+        0, 0, 0, 0, 0, 0, 0, // EI_PAD
+    ]);
+    put_u16(&mut out, ET_REL);
+    put_u16(&mut out, EM_ARM);
+    put_u32(&mut out, 1); // e_version = EV_CURRENT
+    put_u32(&mut out, 0); // e_entry
+    put_u32(&mut out, 0); // e_phoff
+    put_u32(&mut out, shdrs_offset as u32); // e_shoff
+    put_u32(&mut out, EF_ARM_EABI_VER5); // e_flags
+    put_u16(&mut out, SIZEOF_EHDR as u16); // e_ehsize
+    put_u16(&mut out, 0); // e_phentsize
+    put_u16(&mut out, 0); // e_phnum
+    put_u16(&mut out, SIZEOF_SHDR as u16); // e_shentsize
+    put_u16(&mut out, NUM_SECTIONS as u16); // e_shnum
+    put_u16(&mut out, SHNDX_SHSTRTAB as u16); // e_shstrndx
+    debug_assert_eq!(out.len(), SIZEOF_EHDR);
+
+    // Section contents
+    out.extend_from_slice(&symtab);
+    out.extend_from_slice(&strtab);
+    out.extend_from_slice(&shstrtab);
+    out.resize(shdrs_offset, 0);
+
+    // Section headers
+    let mut put_shdr = |shdr: [u32; 10]| {
+        for &field in shdr.iter() {
+            put_u32(&mut out, field);
+        }
+    };
+    put_shdr([0; 10]);
+    put_shdr([
+        name_symtab,
+        SHT_SYMTAB,
+        0, // sh_flags
+        0, // sh_addr
+        symtab_offset as u32,
+        symtab.len() as u32,
+        SHNDX_STRTAB, // sh_link
+        1,            // sh_info = the index of the first non-local symbol
+        4,            // sh_addralign
+        SIZEOF_SYM as u32,
+    ]);
+    put_shdr([
+        name_strtab,
+        SHT_STRTAB,
+        0,
+        0,
+        strtab_offset as u32,
+        strtab.len() as u32,
+        0,
+        0,
+        1,
+        0,
+    ]);
+    put_shdr([
+        name_shstrtab,
+        SHT_STRTAB,
+        0,
+        0,
+        shstrtab_offset as u32,
+        shstrtab.len() as u32,
+        0,
+        0,
+        1,
+        0,
+    ]);
+    debug_assert_eq!(out.len(), shdrs_offset + SIZEOF_SHDR * NUM_SECTIONS);
+
+    writer.write_all(&out)
+}
+
+fn put_u16(out: &mut Vec<u8>, x: u16) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, x: u32) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
@@ -0,0 +1,288 @@
+//! Secure image identities, which allow Non-Secure images to detect that
+//! they are running with a Secure image other than the one they were linked
+//! against.
+//!
+//! The Secure image reserves a 32-bit word named `__tzmcfi_image_id`, which
+//! is left as zero by the compiler. `stamp_file` makes a copy of the image in
+//! which the word is filled with a value derived from the SHA-256 hash of the
+//! file (computed with the word zeroed). The import library generated from
+//! the copy defines an absolute symbol `__tzmcfi_secure_image_id` having the
+//! same value. Each partition (see `Builder::partition_input`) may have its
+//! own identity.
+//!
+//! Non-Secure images retrieve the identity of the running Secure image by
+//! calling the Secure gateway `TCGetSecureImageId`, which must be the first
+//! veneer in the Non-Secure-callable region. This way, its address doesn't
+//! depend on the rest of the Secure image, and the call works even if the
+//! Non-Secure image was linked against a different Secure image.
+use goblin::{
+    elf::{header::ET_REL, section_header::SHT_NOBITS, sym::STT_FUNC, Elf},
+    Object,
+};
+use sha2::Digest;
+use std::{fs, ops::Range, path::Path};
+
+use super::{verify::find_symbol, Error};
+
+/// The name of the word reserved in a Secure image.
+pub const IMAGE_ID_SYMBOL: &str = "__tzmcfi_image_id";
+
+/// The name of the absolute symbol defined by import libraries.
+pub const SECURE_IMAGE_ID_SYMBOL: &str = "__tzmcfi_secure_image_id";
+
+/// The name of the Secure gateway returning the image identity.
+pub const IMAGE_ID_GATEWAY: &str = "TCGetSecureImageId";
+
+/// Locate the word reserved for the image identity in the file.
+fn locate(elf: &Elf<'_>, file_len: usize) -> Result<Option<Range<usize>>, String> {
+    let sym = match find_symbol(elf, IMAGE_ID_SYMBOL) {
+        Some(sym) => sym,
+        None => return Ok(None),
+    };
+
+    if sym.st_size != 4 {
+        return Err(format!(
+            "'{}' must be 4 bytes long (st_size = {})",
+            IMAGE_ID_SYMBOL, sym.st_size
+        ));
+    }
+
+    let shdr = elf
+        .section_headers
+        .get(sym.st_shndx)
+        .filter(|shdr| shdr.sh_type != SHT_NOBITS)
+        .ok_or_else(|| format!("'{}' is not backed by the file contents", IMAGE_ID_SYMBOL))?;
+
+    // The symbol value is a section offset in relocatable object files
+    let offset = if elf.header.e_type == ET_REL {
+        sym.st_value
+    } else {
+        sym.st_value.wrapping_sub(shdr.sh_addr)
+    };
+
+    if offset > shdr.sh_size || shdr.sh_size - offset < 4 {
+        return Err(format!("'{}' lies outside its section", IMAGE_ID_SYMBOL));
+    }
+
+    let start = shdr.sh_offset as usize + offset as usize;
+    if start + 4 > file_len {
+        return Err(format!("'{}' lies outside the file", IMAGE_ID_SYMBOL));
+    }
+
+    Ok(Some(start..start + 4))
+}
+
+/// Read the image identity. Returns `Ok(None)` if the image doesn't reserve a
+/// word for it and `Ok(Some(0))` if it's not stamped yet.
+pub(crate) fn read(elf: &Elf<'_>, bytes: &[u8]) -> Result<Option<u32>, String> {
+    Ok(locate(elf, bytes.len())?.map(|range| {
+        let word = &bytes[range];
+        u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+    }))
+}
+
+/// Check that `IMAGE_ID_GATEWAY` is placed at the start of the
+/// Non-Secure-callable region.
+pub(crate) fn check_gateway(elf: &Elf<'_>, nsc_section: &str) -> Result<(), String> {
+    let sym = find_symbol(elf, IMAGE_ID_GATEWAY).ok_or_else(|| "it is not defined".to_owned())?;
+    if sym.st_type() != STT_FUNC {
+        return Err("it is not a function".to_owned());
+    }
+
+    // Addresses are meaningless in relocatable object files
+    if elf.header.e_type == ET_REL {
+        return Ok(());
+    }
+
+    // The linker scripts define `__nsc_start`, which may be past the start of
+    // the section because of alignment padding
+    let start = match find_symbol(elf, "__nsc_start") {
+        Some(start) => start.st_value,
+        None => {
+            elf.section_headers
+                .iter()
+                .find(|shdr| elf.shdr_strtab.get_unsafe(shdr.sh_name) == Some(nsc_section))
+                .ok_or_else(|| format!("the section '{}' does not exist", nsc_section))?
+                .sh_addr
+        }
+    };
+
+    let addr = sym.st_value & !1;
+    if addr != start {
+        return Err(format!(
+            "it is at 0x{:08x}, but the region starts at 0x{:08x}. Place it first in the \
+            linker script",
+            addr, start
+        ));
+    }
+
+    Ok(())
+}
+
+/// Read the specified Secure image and fill the word reserved for the image
+/// identity in memory. Returns the new identity and the stamped contents, or
+/// `None` if the image doesn't reserve a word for it. The file is left
+/// untouched. Stamping an already stamped image yields the same result.
+pub fn stamp_file(path: &Path) -> Result<Option<(u32, Vec<u8>)>, Error> {
+    let mut bytes = fs::read(path).map_err(|error| Error::Read {
+        path: path.to_owned(),
+        error,
+    })?;
+
+    let parse_error = |message: String| Error::Parse {
+        path: path.to_owned(),
+        message,
+    };
+
+    let range = match Object::parse(&bytes).map_err(|e| parse_error(e.to_string()))? {
+        Object::Elf(elf) => locate(&elf, bytes.len()).map_err(parse_error)?,
+        _ => return Err(Error::NotElf(path.to_owned())),
+    };
+    let range = match range {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    for b in bytes[range.clone()].iter_mut() {
+        *b = 0;
+    }
+
+    let id = id_from_digest(&sha2::Sha256::digest(&bytes));
+    bytes[range].copy_from_slice(&id.to_le_bytes());
+
+    Ok(Some((id, bytes)))
+}
+
+/// Derive an image identity from the SHA-256 hash of the image.
+fn id_from_digest(digest: &[u8]) -> u32 {
+    match u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]) {
+        // Zero indicates an unstamped image
+        0 => 1,
+        id => id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::{ElfBuilder, TempFile, DATA, TEXT};
+    use goblin::elf::{
+        header::{ET_EXEC, ET_REL},
+        section_header::SHN_ABS,
+        sym::{STB_GLOBAL, STT_NOTYPE, STT_OBJECT},
+    };
+
+    const SG: [u8; 4] = [0x7f, 0xe9, 0x7f, 0xe9];
+
+    /// Build an image reserving the image identity at 0x3004, which is
+    /// initialized with `id`
+    fn id_image(id: u32) -> Vec<u8> {
+        let mut elf = ElfBuilder::new(ET_EXEC);
+        let data = elf.section(
+            ".data",
+            DATA,
+            0x3000,
+            &[[0xaa; 4], id.to_le_bytes()].concat(),
+        );
+        elf.symbol(
+            IMAGE_ID_SYMBOL,
+            0x3004,
+            4,
+            (STB_GLOBAL << 4) | STT_OBJECT,
+            data,
+        );
+        elf.build()
+    }
+
+    #[test]
+    fn stamp_zeroed_image() {
+        let original = id_image(0);
+        let file = TempFile::new("stamp_zeroed_image", &original);
+        let (id, stamped) = stamp_file(&file.0).unwrap().unwrap();
+
+        assert_eq!(id, id_from_digest(&sha2::Sha256::digest(&original)));
+        assert_eq!(stamped, id_image(id));
+        // The file is left untouched
+        assert_eq!(fs::read(&file.0).unwrap(), original);
+
+        let elf = Elf::parse(&stamped).unwrap();
+        assert_eq!(read(&elf, &stamped).unwrap(), Some(id));
+    }
+
+    #[test]
+    fn stamp_idempotent() {
+        // The hash is computed with the word zeroed
+        let file = TempFile::new("stamp_idempotent", &id_image(0x12345678));
+        let (id, stamped) = stamp_file(&file.0).unwrap().unwrap();
+        assert_eq!(stamped, id_image(id));
+        assert_eq!(id, id_from_digest(&sha2::Sha256::digest(&id_image(0))));
+
+        let file = TempFile::new("stamp_idempotent.stamped", &stamped);
+        assert_eq!(stamp_file(&file.0).unwrap(), Some((id, stamped)));
+    }
+
+    #[test]
+    fn stamp_without_id() {
+        let file = TempFile::new("stamp_without_id", &ElfBuilder::new(ET_EXEC).build());
+        assert_eq!(stamp_file(&file.0).unwrap(), None);
+    }
+
+    #[test]
+    fn zero_id_is_never_used() {
+        assert_eq!(id_from_digest(&[0; 32]), 1);
+        assert_eq!(id_from_digest(&[0x78, 0x56, 0x34, 0x12]), 0x12345678);
+    }
+
+    /// Build an image having the identity gateway at `gateway`. The
+    /// Non-Secure-callable region is located at 0x2000..0x2020, and starts
+    /// at `nsc_start` if specified.
+    fn gateway_image(e_type: u16, gateway: u32, nsc_start: Option<u32>) -> Vec<u8> {
+        let mut elf = ElfBuilder::new(e_type);
+        let nsc = elf.section(".gnu.sgstubs", TEXT, 0x2000, &[SG; 8].concat());
+        if let Some(nsc_start) = nsc_start {
+            let info = (STB_GLOBAL << 4) | STT_NOTYPE;
+            elf.symbol("__nsc_start", nsc_start, 0, info, SHN_ABS as usize);
+        }
+        elf.function(IMAGE_ID_GATEWAY, gateway | 1, nsc);
+        elf.build()
+    }
+
+    fn check(bytes: &[u8]) -> Result<(), String> {
+        check_gateway(&Elf::parse(bytes).unwrap(), ".gnu.sgstubs")
+    }
+
+    #[test]
+    fn accept_gateway_at_nsc_start() {
+        check(&gateway_image(ET_EXEC, 0x2000, None)).unwrap();
+        check(&gateway_image(ET_EXEC, 0x2010, Some(0x2010))).unwrap();
+    }
+
+    #[test]
+    fn reject_misplaced_gateway() {
+        let e = check(&gateway_image(ET_EXEC, 0x2008, None)).unwrap_err();
+        assert!(
+            e.starts_with("it is at 0x00002008, but the region starts at 0x00002000"),
+            "{}",
+            e
+        );
+
+        // `__nsc_start` takes precedence over the section address
+        let e = check(&gateway_image(ET_EXEC, 0x2000, Some(0x2010))).unwrap_err();
+        assert!(
+            e.starts_with("it is at 0x00002000, but the region starts at 0x00002010"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn skip_relocatable_object() {
+        check(&gateway_image(ET_REL, 0x8, None)).unwrap();
+    }
+
+    #[test]
+    fn reject_missing_gateway() {
+        let e = check(&ElfBuilder::new(ET_EXEC).build()).unwrap_err();
+        assert_eq!(e, "it is not defined");
+    }
+}
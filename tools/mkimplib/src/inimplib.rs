@@ -0,0 +1,191 @@
+//! Loads a previously generated import library to check the stability of
+//! the Secure gateway addresses.
+use goblin::{
+    elf::{
+        section_header::SHN_ABS,
+        sym::{STB_GLOBAL, STB_WEAK},
+    },
+    Object,
+};
+use std::{collections::HashMap, path::Path};
+
+use super::{imageid::SECURE_IMAGE_ID_SYMBOL, manifest::Manifest, Error};
+
+/// Load the symbols defined by an import library, which is either an ELF
+/// relocatable object file (like the one produced with `--format elf` or
+/// `arm-none-eabi-ld --cmse-implib`), an assembler input produced with
+/// `--format asm`, a linker script produced with `--format ld`, or a manifest
+/// produced with `--manifest`.
+///
+/// The Secure image identities (`__tzmcfi_secure_image_id`, possibly prefixed
+/// with partition names) are not a part of the result because they change on
+/// every build.
+pub fn load_import_library(path: &Path) -> Result<HashMap<String, u64>, Error> {
+    let bytes = std::fs::read(path).map_err(|error| Error::Read {
+        path: path.to_owned(),
+        error,
+    })?;
+
+    let parse_error = |message: String| Error::Parse {
+        path: path.to_owned(),
+        message,
+    };
+
+    let mut symbols = if bytes.starts_with(b"\x7fELF") {
+        load_elf(&bytes).map_err(parse_error)?
+    } else if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        Manifest::from_slice(&bytes)
+            .map(|manifest| manifest.symbol_addresses())
+            .map_err(|e| parse_error(e.to_string()))?
+    } else {
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|_| parse_error("neither an ELF file nor a text file".to_owned()))?;
+        load_text(text).map_err(parse_error)?
+    };
+
+    symbols.retain(|name, _| !name.ends_with(SECURE_IMAGE_ID_SYMBOL));
+
+    Ok(symbols)
+}
+
+fn load_elf(bytes: &[u8]) -> Result<HashMap<String, u64>, String> {
+    let elf = match Object::parse(bytes).map_err(|e| e.to_string())? {
+        Object::Elf(elf) => elf,
+        _ => unreachable!(),
+    };
+
+    Ok(elf
+        .syms
+        .iter()
+        .filter(|sym| {
+            sym.st_shndx == SHN_ABS as usize
+                && (sym.st_bind() == STB_GLOBAL || sym.st_bind() == STB_WEAK)
+        })
+        .filter_map(|sym| {
+            let name = elf.strtab.get_unsafe(sym.st_name)?;
+            Some((name.to_owned(), sym.st_value))
+        })
+        .collect())
+}
+
+/// Parse `.set` directives or `PROVIDE` statements.
+fn load_text(text: &str) -> Result<HashMap<String, u64>, String> {
+    let mut symbols = HashMap::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let (name, value) = if let Some(operands) = line.strip_prefix(".set") {
+            let mut operands = operands.splitn(2, ',').map(str::trim);
+            match (operands.next(), operands.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => (name, value),
+                _ => return Err(format!("line {}: malformed `.set` directive", i + 1)),
+            }
+        } else if let Some(assignment) = line.strip_prefix("PROVIDE(") {
+            let assignment = assignment
+                .strip_suffix(");")
+                .ok_or_else(|| format!("line {}: malformed `PROVIDE` statement", i + 1))?;
+            let mut operands = assignment.splitn(2, '=').map(str::trim);
+            match (operands.next(), operands.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => (name, value),
+                _ => return Err(format!("line {}: malformed `PROVIDE` statement", i + 1)),
+            }
+        } else {
+            continue;
+        };
+
+        let value = value
+            .strip_prefix("0x")
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("line {}: unsupported value '{}'", i + 1, value))?;
+
+        symbols.insert(name.to_owned(), value);
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        writer::{AsmWriter, ElfWriter, LinkerScriptWriter, Writer},
+        ImageIdSymbol, ImageInfo, ImportLibrary, Symbol,
+    };
+
+    fn implib() -> ImportLibrary {
+        let symbol = |name: &str, address: u64| Symbol {
+            name: name.to_owned(),
+            address,
+            size: 32,
+            section: ".gnu.sgstubs".to_owned(),
+            image: 0,
+            is_entry_point: true,
+            signature: None,
+        };
+        ImportLibrary {
+            images: vec![ImageInfo {
+                path: "secure.elf".into(),
+                partition: None,
+                sha256: String::new(),
+                image_id: Some(0xdeadbeef),
+            }],
+            symbols: vec![
+                symbol("TCGetSecureImageId", 0x10000001),
+                symbol("TCInitialize", 0x10000021),
+                symbol("TCDebugDumpLog", 0x10000041),
+            ],
+            image_ids: vec![ImageIdSymbol {
+                name: "__tzmcfi_secure_image_id".to_owned(),
+                value: 0xdeadbeef,
+                image: 0,
+            }],
+        }
+    }
+
+    fn write(writer: &dyn Writer) -> Vec<u8> {
+        let mut out = Vec::new();
+        implib().write(writer, &mut out).unwrap();
+        out
+    }
+
+    fn expected_symbols() -> HashMap<String, u64> {
+        [
+            ("TCGetSecureImageId", 0x10000001),
+            ("TCInitialize", 0x10000021),
+            ("TCDebugDumpLog", 0x10000041),
+            ("__tzmcfi_secure_image_id", 0xdeadbeef),
+        ]
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), value))
+        .collect()
+    }
+
+    #[test]
+    fn round_trip_asm() {
+        let text = String::from_utf8(write(&AsmWriter)).unwrap();
+        assert_eq!(load_text(&text).unwrap(), expected_symbols());
+    }
+
+    #[test]
+    fn round_trip_linker_script() {
+        let text = String::from_utf8(write(&LinkerScriptWriter)).unwrap();
+        assert_eq!(load_text(&text).unwrap(), expected_symbols());
+    }
+
+    #[test]
+    fn round_trip_elf() {
+        assert_eq!(load_elf(&write(&ElfWriter)).unwrap(), expected_symbols());
+    }
+
+    #[test]
+    fn reject_malformed_text() {
+        let e = load_text(".syntax unified\n.set TCInitialize\n").unwrap_err();
+        assert_eq!(e, "line 2: malformed `.set` directive");
+
+        let e = load_text("PROVIDE(TCInitialize = 0x10000021)\n").unwrap_err();
+        assert_eq!(e, "line 1: malformed `PROVIDE` statement");
+
+        let e = load_text(".set TCInitialize, 268435489\n").unwrap_err();
+        assert_eq!(e, "line 1: unsupported value '268435489'");
+    }
+}
@@ -0,0 +1,1268 @@
+//! Makes custom CMSE import libraries.
+//!
+//! ```no_run
+//! use tzmcfi_mkimplib::{writer::AsmWriter, ImportLibrary};
+//!
+//! let implib = ImportLibrary::builder()
+//!     .input("zig-cache/secure")
+//!     .scan_sg_gadgets(true)
+//!     .build()
+//!     .unwrap();
+//! implib
+//!     .write_to_file("zig-cache/secure_implib.s".as_ref(), &AsmWriter)
+//!     .unwrap();
+//! ```
+use goblin::{
+    elf::{
+        section_header::SHN_UNDEF,
+        sym::{STB_GLOBAL, STB_WEAK},
+        Elf, Sym,
+    },
+    Object,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+pub mod decls;
+mod elfobj;
+pub mod imageid;
+pub mod inimplib;
+pub mod manifest;
+pub mod pattern;
+#[cfg(test)]
+mod testelf;
+mod veneer;
+mod verify;
+pub mod writer;
+
+use self::{decls::Signature, pattern::Pattern, writer::Writer};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("failed to read {path:?}: {error}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error("failed to write {path:?}: {error}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error("failed to parse {path:?}: {message}")]
+    Parse { path: PathBuf, message: String },
+
+    #[error("{0:?} is not an ELF object file")]
+    NotElf(PathBuf),
+
+    #[error("invalid symbol pattern '{pattern}': {message}")]
+    InvalidPattern { pattern: String, message: String },
+
+    #[error("none of the input files reserves '{}'", imageid::IMAGE_ID_SYMBOL)]
+    NoImageIdSymbol,
+
+    #[error("more than one input file in a partition has an image identity:{}", list(.0))]
+    MultipleImageIds(Vec<DisplayPath>),
+
+    #[error(
+        "the image identity gateway must be the first entry of the Non-Secure-callable \
+        region: {0}"
+    )]
+    MisplacedImageIdGateway(SymbolError),
+
+    #[error("symbols defined in more than one partition:{}", list(.0))]
+    DuplicateDefinitions(Vec<SymbolError>),
+
+    #[error(
+        "every input file must be assigned to a partition to generate separate import libraries"
+    )]
+    UntaggedInput,
+
+    #[error(
+        "the output path {0:?} must contain '%partition%' to generate separate import libraries"
+    )]
+    NoPartitionPlaceholder(PathBuf),
+
+    #[error("invalid entry point markers:{}", list(.0))]
+    InvalidEntryPointMarkers(Vec<SymbolError>),
+
+    #[error("undefined symbols:{}", list(.0))]
+    UndefinedSymbols(Vec<SymbolError>),
+
+    #[error("entry points that are not valid Secure gateway veneers:{}", list(.0))]
+    InvalidEntryPoints(Vec<SymbolError>),
+
+    #[error("Secure gateway veneers with an unexpected structure:{}", list(.0))]
+    InvalidVeneers(Vec<SymbolError>),
+
+    #[error("stray SG instructions in the Non-Secure-callable region:{}", list(.0))]
+    NscSgGadgets(Vec<SgGadget>),
+}
+
+/// A path wrapped to implement `Display`.
+#[derive(Debug)]
+pub struct DisplayPath(pub PathBuf);
+
+impl fmt::Display for DisplayPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// A problem regarding a particular symbol.
+#[derive(Debug)]
+pub struct SymbolError {
+    pub name: String,
+    /// The input file where the problem was found
+    pub path: Option<PathBuf>,
+    pub reason: String,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}'", self.name)?;
+        if let Some(path) = &self.path {
+            write!(f, " in {:?}", path)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+/// An `SG` instruction which does not belong to a known Secure gateway
+/// veneer.
+#[derive(Debug)]
+pub struct SgGadget {
+    pub path: PathBuf,
+    pub section: String,
+    /// The offset within `section`
+    pub offset: u64,
+    pub address: u64,
+}
+
+impl fmt::Display for SgGadget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}+0x{:x} (0x{:08x}) in {:?}",
+            self.section, self.offset, self.address, self.path
+        )
+    }
+}
+
+/// A non-fatal problem found while building an import library.
+#[derive(Debug)]
+pub enum Warning {
+    DuplicateDefinition {
+        name: String,
+    },
+    /// A stray `SG` instruction outside the Non-Secure-callable region
+    StraySg(SgGadget),
+    TruncatedSection {
+        path: PathBuf,
+        section: String,
+    },
+    UnknownSignature {
+        name: String,
+    },
+    /// An inclusion or exclusion pattern did not match any symbols
+    UnusedPattern {
+        pattern: String,
+    },
+    /// The image reserves a word for the image identity, which is not
+    /// filled yet
+    UnstampedImageId {
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::DuplicateDefinition { name } => {
+                write!(f, "Symbol '{}' is defined more than once", name)
+            }
+            Warning::StraySg(gadget) => write!(f, "Stray SG instruction at {}", gadget),
+            Warning::TruncatedSection { path, section } => write!(
+                f,
+                "The section '{}' in {:?} is truncated; not scanning it",
+                section, path
+            ),
+            Warning::UnknownSignature { name } => write!(
+                f,
+                "Could not determine the signature of '{}' from the debugging \
+                information; not declaring it",
+                name
+            ),
+            Warning::UnusedPattern { pattern } => {
+                write!(f, "The pattern '{}' did not match any symbols", pattern)
+            }
+            Warning::UnstampedImageId { path } => write!(
+                f,
+                "The image identity of {:?} is not stamped; not exporting it",
+                path
+            ),
+        }
+    }
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|item| format!("\n  {}", item)).collect()
+}
+
+/// A CMSE import library, i.e., a set of symbols to be exported to
+/// Non-Secure images.
+pub struct ImportLibrary {
+    /// The Secure images from which the symbols were collected
+    pub images: Vec<ImageInfo>,
+    /// The exported symbols, sorted by address
+    pub symbols: Vec<Symbol>,
+    /// The identities of the Secure images, one per partition at most. See
+    /// the `imageid` module.
+    pub image_ids: Vec<ImageIdSymbol>,
+}
+
+pub struct ImageInfo {
+    pub path: PathBuf,
+    /// The partition the image belongs to. See `Builder::partition_input`.
+    pub partition: Option<String>,
+    /// The SHA-256 hash of the image file, represented in lowercase
+    /// hexadecimal digits
+    pub sha256: String,
+    /// The stamped identity of the image. See the `imageid` module.
+    pub image_id: Option<u32>,
+}
+
+pub struct Symbol {
+    pub name: String,
+    /// The symbol value. The lowest bit indicates the Thumb state.
+    pub address: u64,
+    pub size: u64,
+    /// The name of the section containing the symbol
+    pub section: String,
+    /// The index into `ImportLibrary::images`
+    pub image: usize,
+    /// `true` if it's a Secure gateway marked by `__acle_se_*`
+    pub is_entry_point: bool,
+    /// The signature of the Secure gateway. Only available for entry points
+    /// whose signatures are known and if requested by
+    /// `Builder::find_signatures`.
+    pub signature: Option<Signature>,
+}
+
+/// An absolute symbol conveying the identity of a Secure image.
+pub struct ImageIdSymbol {
+    /// `__tzmcfi_secure_image_id`, possibly prefixed by
+    /// `ImportLibrary::prefix_partition_names`
+    pub name: String,
+    pub value: u32,
+    /// The index into `ImportLibrary::images`
+    pub image: usize,
+}
+
+/// A difference between a previous import library and a new one.
+pub enum AddressChange {
+    Removed { name: String, old: u64 },
+    Moved { name: String, old: u64, new: u64 },
+    Added { name: String, new: u64 },
+}
+
+impl AddressChange {
+    /// Returns `true` if the change breaks Non-Secure images linked against
+    /// the previous import library.
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            AddressChange::Removed { .. } | AddressChange::Moved { .. } => true,
+            AddressChange::Added { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for AddressChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressChange::Removed { name, old } => write!(
+                f,
+                "Symbol '{}' (0x{:08x}) was removed since the previous import library",
+                name, old
+            ),
+            AddressChange::Moved { name, old, new } => write!(
+                f,
+                "Symbol '{}' moved from 0x{:08x} to 0x{:08x}",
+                name, old, new
+            ),
+            AddressChange::Added { name, new } => {
+                write!(f, "Symbol '{}' (0x{:08x}) is new", name, new)
+            }
+        }
+    }
+}
+
+impl ImportLibrary {
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Split the import library by the partitions of the images. The
+    /// partitions are returned in the order they first appear in `images`.
+    pub fn split_partitions(self) -> Vec<(Option<String>, ImportLibrary)> {
+        let mut partitions: Vec<(Option<String>, ImportLibrary)> = Vec::new();
+        // The indices into `partitions` and `ImportLibrary::images` of each
+        // image
+        let mut new_indices = Vec::with_capacity(self.images.len());
+
+        for image in self.images {
+            let i = match partitions
+                .iter()
+                .position(|(name, _)| *name == image.partition)
+            {
+                Some(i) => i,
+                None => {
+                    partitions.push((
+                        image.partition.clone(),
+                        ImportLibrary {
+                            images: Vec::new(),
+                            symbols: Vec::new(),
+                            image_ids: Vec::new(),
+                        },
+                    ));
+                    partitions.len() - 1
+                }
+            };
+            let implib = &mut partitions[i].1;
+            new_indices.push((i, implib.images.len()));
+            implib.images.push(image);
+        }
+
+        for mut sym in self.symbols {
+            let (i, image) = new_indices[sym.image];
+            sym.image = image;
+            partitions[i].1.symbols.push(sym);
+        }
+        for mut id in self.image_ids {
+            let (i, image) = new_indices[id.image];
+            id.image = image;
+            partitions[i].1.image_ids.push(id);
+        }
+
+        partitions
+    }
+
+    /// Prefix the names of the symbols with the partitions they belong to
+    /// (e.g., `vendor_PowerSetMode`) so that the import libraries of multiple
+    /// partitions can be combined into one.
+    pub fn prefix_partition_names(&mut self) {
+        let images = &self.images;
+        let prefix = |name: &mut String, image: usize| {
+            if let Some(partition) = &images[image].partition {
+                *name = format!("{}_{}", partition, name);
+            }
+        };
+        for sym in self.symbols.iter_mut() {
+            prefix(&mut sym.name, sym.image);
+        }
+        for id in self.image_ids.iter_mut() {
+            prefix(&mut id.name, id.image);
+        }
+    }
+
+    /// Write the import library in the format implemented by `writer`.
+    pub fn write(&self, writer: &dyn Writer, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        writer.write(self, out)
+    }
+
+    /// Create a file and write the import library to it in the format
+    /// implemented by `writer`.
+    pub fn write_to_file(&self, path: &Path, writer: &dyn Writer) -> Result<(), Error> {
+        (|| -> Result<(), io::Error> {
+            let mut out = io::BufWriter::new(fs::File::create(path)?);
+            writer.write(self, &mut out)?;
+            io::Write::flush(&mut out)
+        })()
+        .map_err(|error| Error::Write {
+            path: path.to_owned(),
+            error,
+        })
+    }
+
+    /// Compare the symbols against those of a previous import library.
+    pub fn compare_addresses(&self, old_symbols: &HashMap<String, u64>) -> Vec<AddressChange> {
+        let mut changes = Vec::new();
+
+        let mut old_symbols: Vec<_> = old_symbols.iter().collect();
+        old_symbols.sort_by_key(|(_, addr)| **addr);
+
+        for (name, &old) in old_symbols.iter() {
+            match self.symbols.iter().find(|sym| &sym.name == *name) {
+                None => changes.push(AddressChange::Removed {
+                    name: (*name).clone(),
+                    old,
+                }),
+                Some(sym) if sym.address != old => changes.push(AddressChange::Moved {
+                    name: (*name).clone(),
+                    old,
+                    new: sym.address,
+                }),
+                Some(_) => {}
+            }
+        }
+
+        for sym in self.symbols.iter() {
+            if !old_symbols.iter().any(|(n, _)| **n == sym.name) {
+                changes.push(AddressChange::Added {
+                    name: sym.name.clone(),
+                    new: sym.address,
+                });
+            }
+        }
+
+        changes
+    }
+}
+
+/// Builds an `ImportLibrary`.
+pub struct Builder {
+    inputs: Vec<Input>,
+    symbols: Vec<String>,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    nsc_section: String,
+    scan_sg_gadgets: bool,
+    verify_veneers: bool,
+    find_signatures: bool,
+    on_warning: Box<dyn FnMut(&Warning)>,
+}
+
+struct Input {
+    path: PathBuf,
+    partition: Option<String>,
+    /// The contents of the file. `None` if it's yet to be read.
+    bytes: Option<Vec<u8>>,
+}
+
+/// A loaded input ELF file.
+pub(crate) struct Image<'a> {
+    pub path: &'a Path,
+    pub partition: Option<&'a str>,
+    pub bytes: &'a [u8],
+    pub elf: Elf<'a>,
+}
+
+/// A resolved symbol.
+struct SymInfo {
+    /// The index into the list of loaded `Image`s
+    image: usize,
+    sym: Sym,
+}
+
+/// The location of an entry point marker symbol (`__acle_se_*`).
+struct EntryMarker {
+    /// The index into the list of loaded `Image`s
+    image: usize,
+    addr: u64,
+}
+
+impl EntryMarker {
+    /// Veneers generated by `exportNonSecureCallable` share the address with
+    /// their marker symbols. Otherwise, the marker symbol designates the
+    /// actual function.
+    fn is_veneer(&self, info: &SymInfo) -> bool {
+        self.image == info.image && self.addr == info.sym.st_value
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            inputs: Vec::new(),
+            symbols: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            nsc_section: ".gnu.sgstubs".to_owned(),
+            scan_sg_gadgets: false,
+            verify_veneers: false,
+            find_signatures: false,
+            on_warning: Box::new(|warning| eprintln!("warning: {}", warning)),
+        }
+    }
+
+    /// Add an ELF file to load absolute addresses from.
+    pub fn input(mut self, path: impl Into<PathBuf>) -> Self {
+        self.inputs.push(Input {
+            path: path.into(),
+            partition: None,
+            bytes: None,
+        });
+        self
+    }
+
+    /// Add an ELF file which is already loaded to memory. `path` is only
+    /// used for diagnostics and the manifest.
+    pub fn input_bytes(mut self, path: impl Into<PathBuf>, bytes: Vec<u8>) -> Self {
+        self.inputs.push(Input {
+            path: path.into(),
+            partition: None,
+            bytes: Some(bytes),
+        });
+        self
+    }
+
+    /// Add an ELF file belonging to the specified partition. A partition is
+    /// a set of Secure images (e.g., the TZmCFI monitor and vendor Secure
+    /// services) sharing a symbol namespace. A symbol may not be defined in
+    /// more than one partition, and each partition may have its own image
+    /// identity.
+    pub fn partition_input(
+        mut self,
+        partition: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.inputs.push(Input {
+            path: path.into(),
+            partition: Some(partition.into()),
+            bytes: None,
+        });
+        self
+    }
+
+    /// Include the specified symbol in addition to those marked by
+    /// `__acle_se_*`.
+    pub fn symbol(mut self, name: impl Into<String>) -> Self {
+        self.symbols.push(name.into());
+        self
+    }
+
+    /// Include the global Thumb functions matching `pattern` in addition to
+    /// those marked by `__acle_se_*`.
+    pub fn include(mut self, pattern: Pattern) -> Self {
+        self.include.push(pattern);
+        self
+    }
+
+    /// Exclude the symbols matching `pattern`, even if they are marked by
+    /// `__acle_se_*` or explicitly specified by `symbol`. The excluded entry
+    /// points are still verified and known to the `SG` gadget scan.
+    pub fn exclude(mut self, pattern: Pattern) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Set the name of the section constituting the Non-Secure-callable
+    /// region. Every Secure gateway veneer must reside in this section.
+    /// Defaults to `.gnu.sgstubs`.
+    pub fn nsc_section(mut self, name: impl Into<String>) -> Self {
+        self.nsc_section = name.into();
+        self
+    }
+
+    /// Scan executable sections for `SG` instructions other than those of the
+    /// known entry points. `build` fails if any of them is found in the
+    /// Non-Secure-callable region.
+    pub fn scan_sg_gadgets(mut self, enable: bool) -> Self {
+        self.scan_sg_gadgets = enable;
+        self
+    }
+
+    /// Decode the veneers generated by `exportNonSecureCallable` and check
+    /// that they clear the registers and return to Non-Secure by `BXNS` in
+    /// all paths. Other entry points are only checked for `SG`.
+    pub fn verify_veneers(mut self, enable: bool) -> Self {
+        self.verify_veneers = enable;
+        self
+    }
+
+    /// Determine the signatures of Secure gateways, which are required to
+    /// generate C and Zig declarations.
+    pub fn find_signatures(mut self, enable: bool) -> Self {
+        self.find_signatures = enable;
+        self
+    }
+
+    /// Set the function to report warnings with. By default, they are
+    /// printed to the standard error output.
+    pub fn on_warning(mut self, f: impl FnMut(&Warning) + 'static) -> Self {
+        self.on_warning = Box::new(f);
+        self
+    }
+
+    pub fn build(self) -> Result<ImportLibrary, Error> {
+        let Builder {
+            mut inputs,
+            symbols,
+            include,
+            exclude,
+            nsc_section,
+            scan_sg_gadgets,
+            verify_veneers,
+            find_signatures,
+            mut on_warning,
+        } = self;
+        let on_warning = &mut *on_warning;
+
+        for input in inputs.iter_mut() {
+            if input.bytes.is_none() {
+                input.bytes = Some(fs::read(&input.path).map_err(|error| Error::Read {
+                    path: input.path.clone(),
+                    error,
+                })?);
+            }
+        }
+
+        let images = inputs
+            .iter()
+            .map(|input| {
+                let bytes = input.bytes.as_ref().unwrap();
+                let object = Object::parse(bytes).map_err(|e| Error::Parse {
+                    path: input.path.clone(),
+                    message: e.to_string(),
+                })?;
+                match object {
+                    Object::Elf(elf) => Ok(Image {
+                        path: &input.path,
+                        partition: input.partition.as_deref(),
+                        bytes,
+                        elf,
+                    }),
+                    _ => Err(Error::NotElf(input.path.clone())),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut included_symbols: HashMap<&str, Option<SymInfo>> =
+            symbols.iter().map(|name| (name.as_str(), None)).collect();
+
+        // The names of entry points and the locations of their marker symbols
+        // (`__acle_se_*`)
+        let mut entry_points: HashMap<&str, EntryMarker> = HashMap::new();
+        let mut bad_markers = Vec::new();
+
+        // The reasons why some definitions were rejected
+        let mut rejected_symbols: HashMap<&str, SymbolError> = HashMap::new();
+
+        let mut duplicate_symbols = Vec::new();
+
+        let mut include_used = vec![false; include.len()];
+
+        // Get symbol addresses
+        for (i, image) in images.iter().enumerate() {
+            let elf = &image.elf;
+
+            // Symbols marked using special symbols `__acle_se_*` are
+            // automatically included
+            const ENTRY_PREFIX: &str = "__acle_se_";
+            for sym in elf.syms.iter() {
+                let name = elf.strtab.get_unsafe(sym.st_name).unwrap();
+                if let Some(name) = name.strip_prefix(ENTRY_PREFIX) {
+                    if sym.st_shndx == SHN_UNDEF as usize {
+                        // Just a reference
+                        continue;
+                    }
+                    if let Err(reason) = verify::check_function_symbol(&sym) {
+                        bad_markers.push(SymbolError {
+                            name: format!("{}{}", ENTRY_PREFIX, name),
+                            path: Some(image.path.to_owned()),
+                            reason,
+                        });
+                        continue;
+                    }
+
+                    included_symbols.entry(name).or_insert(None);
+                    entry_points.insert(
+                        name,
+                        EntryMarker {
+                            image: i,
+                            addr: sym.st_value,
+                        },
+                    );
+                }
+            }
+
+            // Global functions matching the inclusion patterns
+            for sym in elf.syms.iter() {
+                let name = elf.strtab.get_unsafe(sym.st_name).unwrap();
+                if !(sym.st_bind() == STB_GLOBAL || sym.st_bind() == STB_WEAK)
+                    || verify::check_function_symbol(&sym).is_err()
+                {
+                    continue;
+                }
+                for (pattern, used) in include.iter().zip(include_used.iter_mut()) {
+                    if pattern.is_match(name) {
+                        *used = true;
+                        included_symbols.entry(name).or_insert(None);
+                    }
+                }
+            }
+
+            for sym in elf.syms.iter() {
+                let name = elf.strtab.get_unsafe(sym.st_name).unwrap();
+
+                let addr_cell = included_symbols.get_mut(name);
+
+                if let Some(addr_cell) = addr_cell {
+                    // Only accept a defined global Thumb function. Otherwise,
+                    // remember why it was rejected so that we can report it
+                    // later if no acceptable definition is found.
+                    let reason = if !(sym.st_bind() == STB_GLOBAL || sym.st_bind() == STB_WEAK) {
+                        Err("it is a local symbol".to_owned())
+                    } else {
+                        verify::check_function_symbol(&sym)
+                    };
+                    if let Err(reason) = reason {
+                        rejected_symbols.insert(
+                            name,
+                            SymbolError {
+                                name: name.to_owned(),
+                                path: Some(image.path.to_owned()),
+                                reason: format!("it was rejected because {}", reason),
+                            },
+                        );
+                        continue;
+                    }
+
+                    if let Some(existing) = addr_cell {
+                        let existing_image = &images[existing.image];
+                        if existing_image.partition != image.partition {
+                            duplicate_symbols.push(SymbolError {
+                                name: name.to_owned(),
+                                path: Some(image.path.to_owned()),
+                                reason: match existing_image.partition {
+                                    Some(partition) => format!(
+                                        "it is also defined in {:?} of the partition '{}'",
+                                        existing_image.path, partition
+                                    ),
+                                    None => format!(
+                                        "it is also defined in {:?}, which is not assigned \
+                                        to a partition",
+                                        existing_image.path
+                                    ),
+                                },
+                            });
+                        } else {
+                            on_warning(&Warning::DuplicateDefinition {
+                                name: name.to_owned(),
+                            });
+                        }
+                    }
+                    *addr_cell = Some(SymInfo { image: i, sym });
+                    continue;
+                }
+            }
+        }
+
+        if !bad_markers.is_empty() {
+            return Err(Error::InvalidEntryPointMarkers(bad_markers));
+        }
+
+        if !duplicate_symbols.is_empty() {
+            return Err(Error::DuplicateDefinitions(duplicate_symbols));
+        }
+
+        let mut undefined_symbols: Vec<_> = included_symbols
+            .iter()
+            .filter(|(_, info)| info.is_none())
+            .map(|(&name, _)| {
+                rejected_symbols
+                    .remove(name)
+                    .unwrap_or_else(|| SymbolError {
+                        name: name.to_owned(),
+                        path: None,
+                        reason: "it is not defined".to_owned(),
+                    })
+            })
+            .collect();
+        if !undefined_symbols.is_empty() {
+            undefined_symbols.sort_by(|a, b| a.name.cmp(&b.name));
+            return Err(Error::UndefinedSymbols(undefined_symbols));
+        }
+
+        let mut symbols: Vec<_> = included_symbols
+            .into_iter()
+            .map(|(name, info)| (name, info.unwrap()))
+            .collect();
+        symbols.sort_by_key(|(name, info)| (info.sym.st_value, *name));
+
+        // Make sure the entry points are genuine Secure gateway veneers. A
+        // function exported from elsewhere would silently open up a hole in
+        // the security boundary.
+        let bad_entry_points: Vec<_> = symbols
+            .iter()
+            .filter(|(name, _)| entry_points.contains_key(name))
+            .filter_map(|(name, info)| {
+                let image = &images[info.image];
+                verify::check_entry_point(image, &info.sym, &nsc_section)
+                    .err()
+                    .map(|reason| SymbolError {
+                        name: (*name).to_owned(),
+                        path: Some(image.path.to_owned()),
+                        reason,
+                    })
+            })
+            .collect();
+
+        if !bad_entry_points.is_empty() {
+            return Err(Error::InvalidEntryPoints(bad_entry_points));
+        }
+
+        if verify_veneers {
+            let bad_veneers: Vec<_> = symbols
+                .iter()
+                .filter(|(name, info)| {
+                    entry_points
+                        .get(name)
+                        .is_some_and(|marker| marker.is_veneer(info))
+                })
+                .filter_map(|(name, info)| {
+                    let image = &images[info.image];
+                    verify::check_veneer(image, &info.sym)
+                        .err()
+                        .map(|reason| SymbolError {
+                            name: (*name).to_owned(),
+                            path: Some(image.path.to_owned()),
+                            reason,
+                        })
+                })
+                .collect();
+
+            if !bad_veneers.is_empty() {
+                return Err(Error::InvalidVeneers(bad_veneers));
+            }
+        }
+
+        if scan_sg_gadgets {
+            let mut nsc_gadgets = Vec::new();
+            for (i, image) in images.iter().enumerate() {
+                let known_veneers: HashSet<(usize, u64)> = symbols
+                    .iter()
+                    .filter(|(name, info)| entry_points.contains_key(name) && info.image == i)
+                    .map(|(_, info)| (info.sym.st_shndx, info.sym.st_value & !1))
+                    .collect();
+                nsc_gadgets.extend(verify::scan_sg_gadgets(
+                    image,
+                    &known_veneers,
+                    &nsc_section,
+                    on_warning,
+                ));
+            }
+
+            if !nsc_gadgets.is_empty() {
+                return Err(Error::NscSgGadgets(nsc_gadgets));
+            }
+        }
+
+        // Remove the excluded symbols
+        let mut exclude_used = vec![false; exclude.len()];
+        symbols.retain(|(name, _)| {
+            let mut keep = true;
+            for (pattern, used) in exclude.iter().zip(exclude_used.iter_mut()) {
+                if pattern.is_match(name) {
+                    *used = true;
+                    keep = false;
+                }
+            }
+            keep
+        });
+
+        for (pattern, used) in include
+            .iter()
+            .zip(include_used)
+            .chain(exclude.iter().zip(exclude_used))
+        {
+            if !used {
+                on_warning(&Warning::UnusedPattern {
+                    pattern: pattern.to_string(),
+                });
+            }
+        }
+
+        let mut signatures = if find_signatures {
+            gateway_signatures(&symbols, &entry_points, &images, on_warning)
+        } else {
+            HashMap::new()
+        };
+
+        let mut images_info = Vec::with_capacity(images.len());
+        for image in images.iter() {
+            let mut image_id =
+                imageid::read(&image.elf, image.bytes).map_err(|message| Error::Parse {
+                    path: image.path.to_owned(),
+                    message,
+                })?;
+            if image_id.is_some() {
+                // Non-Secure images call the gateway without trusting the
+                // address from the import library
+                imageid::check_gateway(&image.elf, &nsc_section).map_err(|reason| {
+                    Error::MisplacedImageIdGateway(SymbolError {
+                        name: imageid::IMAGE_ID_GATEWAY.to_owned(),
+                        path: Some(image.path.to_owned()),
+                        reason,
+                    })
+                })?;
+            }
+            if image_id == Some(0) {
+                on_warning(&Warning::UnstampedImageId {
+                    path: image.path.to_owned(),
+                });
+                image_id = None;
+            }
+            images_info.push(ImageInfo {
+                path: image.path.to_owned(),
+                partition: image.partition.map(str::to_owned),
+                sha256: manifest::sha256_hex(image.bytes),
+                image_id,
+            });
+        }
+
+        let image_ids: Vec<_> = images_info
+            .iter()
+            .enumerate()
+            .filter_map(|(i, image)| {
+                Some(ImageIdSymbol {
+                    name: imageid::SECURE_IMAGE_ID_SYMBOL.to_owned(),
+                    value: image.image_id?,
+                    image: i,
+                })
+            })
+            .collect();
+
+        // Non-Secure images can only check one identity per partition
+        for id in image_ids.iter() {
+            let partition = &images_info[id.image].partition;
+            let stamped_paths: Vec<_> = image_ids
+                .iter()
+                .map(|other| &images_info[other.image])
+                .filter(|other| other.partition == *partition)
+                .map(|other| DisplayPath(other.path.clone()))
+                .collect();
+            if stamped_paths.len() > 1 {
+                return Err(Error::MultipleImageIds(stamped_paths));
+            }
+        }
+
+        Ok(ImportLibrary {
+            images: images_info,
+            image_ids,
+            symbols: symbols
+                .iter()
+                .map(|(name, info)| {
+                    let elf = &images[info.image].elf;
+                    let section = elf
+                        .section_headers
+                        .get(info.sym.st_shndx)
+                        .and_then(|shdr| elf.shdr_strtab.get_unsafe(shdr.sh_name))
+                        .unwrap_or("");
+                    Symbol {
+                        name: (*name).to_owned(),
+                        address: info.sym.st_value,
+                        size: info.sym.st_size,
+                        section: section.to_owned(),
+                        image: info.image,
+                        is_entry_point: entry_points.contains_key(name),
+                        signature: signatures.remove(name),
+                    }
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Determine the signatures of the Secure gateways among `symbols`.
+fn gateway_signatures<'a>(
+    symbols: &[(&'a str, SymInfo)],
+    entry_points: &HashMap<&str, EntryMarker>,
+    images: &[Image<'_>],
+    on_warning: &mut dyn FnMut(&Warning),
+) -> HashMap<&'a str, Signature> {
+    // Veneers generated by `exportNonSecureCallable` always have the uniform
+    // signature. Otherwise, the type of the actual function may be found in
+    // the debugging information. Gateways of unknown signatures are not
+    // declared at all because a wrong prototype is worse than none.
+
+    let mut dwarf_names: Vec<Vec<&str>> = vec![Vec::new(); images.len()];
+    for (name, info) in symbols.iter() {
+        if let Some(marker) = entry_points.get(name) {
+            if !marker.is_veneer(info) {
+                dwarf_names[marker.image].push(name);
+            }
+        }
+    }
+    let mut dwarf_signatures: Vec<_> = images
+        .iter()
+        .zip(dwarf_names.iter())
+        .map(|(image, names)| {
+            if names.is_empty() {
+                HashMap::new()
+            } else {
+                decls::find_signatures(&image.elf, image.bytes, names)
+            }
+        })
+        .collect();
+
+    symbols
+        .iter()
+        .filter_map(|(name, info)| {
+            let marker = entry_points.get(name)?;
+            if marker.is_veneer(info) {
+                return Some((*name, Signature::uniform()));
+            }
+
+            let signature = dwarf_signatures[marker.image].remove(*name);
+            if signature.is_none() {
+                on_warning(&Warning::UnknownSignature {
+                    name: (*name).to_owned(),
+                });
+            }
+            Some((*name, signature?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::{ElfBuilder, TempFile, TEXT};
+    use goblin::elf::{
+        header::ET_EXEC,
+        sym::{STB_LOCAL, STT_FUNC},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    const SG: [u8; 4] = [0x7f, 0xe9, 0x7f, 0xe9];
+    const BX_LR: [u8; 2] = [0x70, 0x47];
+
+    /// Build a Secure image exporting `TCLog` through a veneer at 0x2000.
+    /// `TCLog` is also defined as a local function at 0x1000 if
+    /// `local_shadow` is `true`, and as a global one at 0x2000 if
+    /// `global` is `true`.
+    fn shadowing_image(local_shadow: bool, global: bool) -> Vec<u8> {
+        let mut elf = ElfBuilder::new(ET_EXEC);
+        let text = elf.section(".text", TEXT, 0x1000, &BX_LR);
+        let nsc = elf.section(".gnu.sgstubs", TEXT, 0x2000, &[&SG[..], &BX_LR].concat());
+        if local_shadow {
+            elf.symbol("TCLog", 0x1001, 2, (STB_LOCAL << 4) | STT_FUNC, text);
+        }
+        elf.function("__acle_se_TCLog", 0x2001, nsc);
+        if global {
+            elf.function("TCLog", 0x2001, nsc);
+        }
+        elf.build()
+    }
+
+    fn build(builder: Builder) -> Result<ImportLibrary, Error> {
+        builder
+            .on_warning(|w| panic!("unexpected warning: {}", w))
+            .build()
+    }
+
+    fn symbol_addresses(implib: &ImportLibrary) -> Vec<(&str, u64)> {
+        implib
+            .symbols
+            .iter()
+            .map(|sym| (sym.name.as_str(), sym.address))
+            .collect()
+    }
+
+    #[test]
+    fn ignore_local_definition() {
+        for &local_shadow in [false, true].iter() {
+            let implib = build(
+                Builder::new().input_bytes("secure.elf", shadowing_image(local_shadow, true)),
+            )
+            .unwrap();
+            assert_eq!(symbol_addresses(&implib), [("TCLog", 0x2001)]);
+        }
+    }
+
+    #[test]
+    fn reject_local_definition() {
+        let e = build(Builder::new().input_bytes("secure.elf", shadowing_image(true, false)));
+        match e {
+            Err(Error::UndefinedSymbols(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].name, "TCLog");
+                assert_eq!(
+                    errors[0].reason,
+                    "it was rejected because it is a local symbol"
+                );
+            }
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    /// Build a Secure image exporting the specified veneers in
+    /// `.gnu.sgstubs` at 0x2000
+    fn gateway_image(names: &[&str]) -> Vec<u8> {
+        let mut elf = ElfBuilder::new(ET_EXEC);
+        let veneers = vec![[&SG[..], &BX_LR, &BX_LR].concat(); names.len()].concat();
+        let nsc = elf.section(".gnu.sgstubs", TEXT, 0x2000, &veneers);
+        for (i, name) in names.iter().enumerate() {
+            let addr = 0x2001 + i as u32 * 8;
+            elf.function(&format!("__acle_se_{}", name), addr, nsc);
+            elf.function(name, addr, nsc);
+        }
+        elf.build()
+    }
+
+    fn image_info(partition: Option<&str>) -> ImageInfo {
+        ImageInfo {
+            path: "secure.elf".into(),
+            partition: partition.map(str::to_owned),
+            sha256: String::new(),
+            image_id: None,
+        }
+    }
+
+    fn symbol(name: &str, image: usize) -> Symbol {
+        Symbol {
+            name: name.to_owned(),
+            address: 0x2001,
+            size: 0,
+            section: ".gnu.sgstubs".to_owned(),
+            image,
+            is_entry_point: true,
+            signature: None,
+        }
+    }
+
+    fn image_id(image: usize, value: u32) -> ImageIdSymbol {
+        ImageIdSymbol {
+            name: imageid::SECURE_IMAGE_ID_SYMBOL.to_owned(),
+            value,
+            image,
+        }
+    }
+
+    #[test]
+    fn split_partitions() {
+        let implib = ImportLibrary {
+            images: vec![
+                image_info(Some("monitor")),
+                image_info(Some("vendor")),
+                image_info(Some("monitor")),
+            ],
+            symbols: vec![
+                symbol("TCInitialize", 0),
+                symbol("PowerSetMode", 1),
+                symbol("TCLog", 2),
+            ],
+            image_ids: vec![image_id(1, 0x1234), image_id(2, 0x5678)],
+        };
+
+        let partitions = implib.split_partitions();
+        let names: Vec<_> = partitions.iter().map(|(name, _)| name.as_deref()).collect();
+        assert_eq!(names, [Some("monitor"), Some("vendor")]);
+
+        let monitor = &partitions[0].1;
+        assert_eq!(monitor.images.len(), 2);
+        let symbols: Vec<_> = monitor
+            .symbols
+            .iter()
+            .map(|sym| (sym.name.as_str(), sym.image))
+            .collect();
+        assert_eq!(symbols, [("TCInitialize", 0), ("TCLog", 1)]);
+        let ids: Vec<_> = monitor
+            .image_ids
+            .iter()
+            .map(|id| (id.value, id.image))
+            .collect();
+        assert_eq!(ids, [(0x5678, 1)]);
+
+        let vendor = &partitions[1].1;
+        assert_eq!(vendor.images.len(), 1);
+        assert_eq!(vendor.symbols[0].name, "PowerSetMode");
+        assert_eq!(vendor.symbols[0].image, 0);
+        assert_eq!(vendor.image_ids[0].value, 0x1234);
+        assert_eq!(vendor.image_ids[0].image, 0);
+    }
+
+    #[test]
+    fn prefix_partition_names() {
+        let mut implib = ImportLibrary {
+            images: vec![image_info(Some("vendor")), image_info(None)],
+            symbols: vec![symbol("PowerSetMode", 0), symbol("TCInitialize", 1)],
+            image_ids: vec![image_id(0, 0x1234), image_id(1, 0x5678)],
+        };
+        implib.prefix_partition_names();
+
+        let names: Vec<_> = implib.symbols.iter().map(|sym| sym.name.as_str()).collect();
+        assert_eq!(names, ["vendor_PowerSetMode", "TCInitialize"]);
+        let names: Vec<_> = implib.image_ids.iter().map(|id| id.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "vendor___tzmcfi_secure_image_id",
+                "__tzmcfi_secure_image_id"
+            ]
+        );
+    }
+
+    #[test]
+    fn build_partitions() {
+        let monitor = TempFile::new("build_partitions.monitor", &gateway_image(&["TCLog"]));
+        let vendor = TempFile::new("build_partitions.vendor", &gateway_image(&["PowerSetMode"]));
+
+        let mut implib = build(
+            Builder::new()
+                .partition_input("monitor", &monitor.0)
+                .partition_input("vendor", &vendor.0),
+        )
+        .unwrap();
+        implib.prefix_partition_names();
+
+        let names: Vec<_> = implib.symbols.iter().map(|sym| sym.name.as_str()).collect();
+        assert_eq!(names, ["vendor_PowerSetMode", "monitor_TCLog"]);
+    }
+
+    #[test]
+    fn reject_cross_partition_duplicates() {
+        let monitor = TempFile::new("duplicates.monitor", &gateway_image(&["TCLog"]));
+        let vendor = TempFile::new("duplicates.vendor", &gateway_image(&["TCLog"]));
+
+        let e = build(
+            Builder::new()
+                .partition_input("monitor", &monitor.0)
+                .partition_input("vendor", &vendor.0),
+        );
+        match e {
+            Err(Error::DuplicateDefinitions(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].name, "TCLog");
+                assert_eq!(errors[0].path.as_ref(), Some(&vendor.0));
+                assert_eq!(
+                    errors[0].reason,
+                    format!(
+                        "it is also defined in {:?} of the partition 'monitor'",
+                        monitor.0
+                    )
+                );
+            }
+            _ => panic!("unexpected result"),
+        }
+
+        // Duplicates within a partition are only warned about
+        let warnings = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&warnings);
+        let implib = Builder::new()
+            .partition_input("monitor", &monitor.0)
+            .partition_input("monitor", &vendor.0)
+            .on_warning(move |w| sink.borrow_mut().push(w.to_string()))
+            .build();
+        assert!(implib.is_ok());
+        assert_eq!(
+            *warnings.borrow(),
+            ["Symbol 'TCLog' is defined more than once"]
+        );
+    }
+}
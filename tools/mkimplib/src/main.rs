@@ -1,122 +1,438 @@
-use goblin::Object;
-use memmap::MmapOptions;
-use std::{collections::HashMap, fs::File, io, path::PathBuf};
-use structopt::StructOpt;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use structopt::{
+    clap::{arg_enum, AppSettings},
+    StructOpt,
+};
+use tzmcfi_mkimplib::{
+    imageid, inimplib, manifest,
+    pattern::Pattern,
+    writer::{self, Writer},
+    DisplayPath, Error, ImportLibrary,
+};
 
 /// Makes a custom CMSE import library
 #[derive(StructOpt)]
-#[structopt(name = "tzmcfi_mkimplib")]
+#[structopt(name = "tzmcfi_mkimplib", setting = AppSettings::SubcommandsNegateReqs)]
 struct Opt {
-    /// ELF files to load absolute address from
-    #[structopt(parse(from_os_str), required = true)]
-    input: Vec<PathBuf>,
+    /// ELF files to load absolute address from. `NAME=PATH` assigns the file
+    /// to the partition `NAME`, which must be a valid C identifier
+    #[structopt(required = true)]
+    input: Vec<InputArg>,
 
-    /// Path to the generated assembly file. Defaults to stdout
+    /// Path to the generated import library. Defaults to stdout
     #[structopt(short = "o", parse(from_os_str))]
     output: Option<PathBuf>,
 
-    /// Addiitonal names of symbols to include
+    /// How to generate import libraries for multiple partitions. `prefixed`
+    /// prefixes the symbol names with the partition names (e.g.,
+    /// `vendor_PowerSetMode`). `separate` generates one import library per
+    /// partition, in which case every output path must contain `%partition%`
+    #[structopt(
+        long = "partition-output",
+        default_value = "prefixed",
+        possible_values(&PartitionOutput::variants()),
+        case_insensitive = true
+    )]
+    partition_output: PartitionOutput,
+
+    /// The format of the generated import library
+    #[structopt(
+        short = "f",
+        long = "format",
+        default_value = "asm",
+        possible_values(&OutputFormat::variants()),
+        case_insensitive = true
+    )]
+    format: OutputFormat,
+
+    /// Addiitonal names of symbols to include. `@PATH` reads the names from
+    /// the specified file, one per line. Lines starting with `#` are ignored
     #[structopt(short = "s")]
     symbols: Vec<String>,
+
+    /// Include the global functions matching the specified glob pattern.
+    /// Prefix with `re:` to use a regular expression instead
+    #[structopt(long = "include")]
+    include: Vec<Pattern>,
+
+    /// Exclude the symbols matching the specified glob pattern, including
+    /// those marked by `__acle_se_*`. Prefix with `re:` to use a regular
+    /// expression instead
+    #[structopt(long = "exclude")]
+    exclude: Vec<Pattern>,
+
+    /// The name of the section constituting the Non-Secure-callable region.
+    /// Every Secure gateway veneer must reside in this section
+    #[structopt(long = "nsc-section", default_value = ".gnu.sgstubs")]
+    nsc_section: String,
+
+    /// Scan executable sections for `SG` instructions other than those of the
+    /// known entry points. Fails if any of them is found in the
+    /// Non-Secure-callable region
+    #[structopt(long = "scan-sg-gadgets")]
+    scan_sg_gadgets: bool,
+
+    /// Decode the veneers generated by `exportNonSecureCallable` and check that
+    /// they clear the registers and return to Non-Secure by `BXNS` in all paths
+    #[structopt(long = "verify-veneers")]
+    verify_veneers: bool,
+
+    /// A previously generated import library or manifest. Fails if any of the symbols
+    /// defined in it was removed or moved so that Non-Secure images linked
+    /// against it keep working
+    #[structopt(long = "in-implib", parse(from_os_str))]
+    in_implib: Option<PathBuf>,
+
+    /// Path to a C header file to generate, declaring the Secure gateways
+    #[structopt(long = "c-header", parse(from_os_str))]
+    c_header: Option<PathBuf>,
+
+    /// Path to a Zig source file to generate, declaring the Secure gateways
+    #[structopt(long = "zig-decls", parse(from_os_str))]
+    zig_decls: Option<PathBuf>,
+
+    /// Write a copy of the input file reserving the word `__tzmcfi_image_id`
+    /// to the specified path, with the word filled with a value derived from
+    /// the file contents, and export the value as an absolute symbol
+    /// `__tzmcfi_secure_image_id`. The import library is generated from the
+    /// copy, which is the one to be programmed. `%partition%` is replaced with
+    /// the partition name. The input files are left untouched
+    #[structopt(long = "stamp-image-id", parse(from_os_str))]
+    stamp_image_id: Option<PathBuf>,
+
+    /// Path to a JSON manifest to generate, listing the exported symbols
+    #[structopt(long = "manifest", parse(from_os_str))]
+    manifest: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-fn main() {
-    let opt = Opt::from_args();
+#[derive(StructOpt)]
+enum Command {
+    /// Compares two manifests and exits with a non-zero status if the changes
+    /// break Non-Secure images linked against the old one
+    Diff {
+        /// The manifest of the old Secure image
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
 
-    let mut included_symbols: HashMap<&str, Option<u64>> = opt
-        .symbols
-        .iter()
-        .map(|name| (name.as_str(), None))
-        .collect();
+        /// The manifest of the new Secure image
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+}
 
-    // Get symbol addresses
-    for input in opt.input.iter() {
-        let file = File::open(input)
-            .unwrap_or_else(|x| panic!("failed to open file {:?}: {:?}", input, x));
-        let mmap = unsafe { MmapOptions::new().map(&file) }
-            .unwrap_or_else(|x| panic!("failed to mmap {:?}: {:?}", input, x));
-
-        // The program is short-lived. Just leak `mmap` so that the symbol names
-        // newly inserted to `included_symbols` live long enough
-        let mmap: &'static _ = Box::leak(Box::new(mmap));
-
-        let object = Object::parse(&mmap)
-            .unwrap_or_else(|x| panic!("failed to parse file {:?}: {:?}", input, x));
-        let elf = match &object {
-            Object::Elf(elf) => elf,
-            _ => panic!("{:?} is not an ELF object file", input),
-        };
+arg_enum! {
+    #[derive(Clone, Copy, PartialEq)]
+    enum OutputFormat {
+        // Assembler input defining the symbols using `.set` directives
+        Asm,
+        // ELF relocatable object file, like the one produced by
+        // `arm-none-eabi-ld --cmse-implib`
+        Elf,
+        // Linker script fragment defining the symbols using `PROVIDE`
+        Ld,
+    }
+}
 
-        // Symbols marked using special symbols `__acle_se_*` are automatically
-        // included
-        const ENTRY_PREFIX: &str = "__acle_se_";
-        for sym in elf.syms.iter() {
-            let name = elf.strtab.get_unsafe(sym.st_name).unwrap();
-            if name.starts_with(ENTRY_PREFIX) {
-                let _ = included_symbols.insert(&name[ENTRY_PREFIX.len()..], None);
+arg_enum! {
+    #[derive(Clone, Copy, PartialEq)]
+    enum PartitionOutput {
+        Prefixed,
+        Separate,
+    }
+}
+
+/// An input file, optionally tagged with a partition name.
+struct InputArg {
+    partition: Option<String>,
+    path: PathBuf,
+}
+
+impl std::str::FromStr for InputArg {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Paths containing `=` are still accepted as long as the part
+        // preceding it is not an identifier
+        if let Some((partition, path)) = s.split_once('=') {
+            if is_identifier(partition) {
+                return Ok(Self {
+                    partition: Some(partition.to_owned()),
+                    path: path.into(),
+                });
             }
         }
+        Ok(Self {
+            partition: None,
+            path: s.into(),
+        })
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl OutputFormat {
+    fn writer(self) -> &'static dyn Writer {
+        match self {
+            OutputFormat::Asm => &writer::AsmWriter,
+            OutputFormat::Elf => &writer::ElfWriter,
+            OutputFormat::Ld => &writer::LinkerScriptWriter,
+        }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
 
-        for sym in elf.syms.iter() {
-            let name = elf.strtab.get_unsafe(sym.st_name).unwrap();
+    if let Err(e) = run(&opt) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: &Opt) -> Result<(), Error> {
+    if let Some(Command::Diff { old, new }) = &opt.cmd {
+        let old = manifest::Manifest::load(old)?;
+        let new = manifest::Manifest::load(new)?;
+        let compatible =
+            manifest::diff(&old, &new, &mut io::stdout()).map_err(|error| Error::Write {
+                path: "-".into(),
+                error,
+            })?;
+        std::process::exit(if compatible { 0 } else { 1 });
+    }
+
+    let mut inputs: Vec<(Option<&str>, PathBuf)> = opt
+        .input
+        .iter()
+        .map(|input| (input.partition.as_deref(), input.path.clone()))
+        .collect();
 
-            let addr_cell = included_symbols.get_mut(name);
+    // Read the stamped copies instead of the original files
+    if let Some(stamped_path) = &opt.stamp_image_id {
+        // The stamped copies and the original files
+        let mut stamped: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for (partition, path) in inputs.iter_mut() {
+            let bytes = match imageid::stamp_file(path)? {
+                Some((_, bytes)) => bytes,
+                None => continue,
+            };
+            let stamped_path: PathBuf = match partition {
+                Some(partition) => stamped_path
+                    .to_string_lossy()
+                    .replace("%partition%", partition)
+                    .into(),
+                None => stamped_path.clone(),
+            };
 
-            if let Some(addr_cell) = addr_cell {
-                if addr_cell.is_some() {
-                    eprintln!("warning: Symbol '{}' is defined more than once", name);
-                }
-                *addr_cell = Some(sym.st_value);
-                continue;
+            // Don't overwrite the previous one
+            if let Some((_, other)) = stamped.iter().find(|(p, _)| *p == stamped_path) {
+                return Err(Error::MultipleImageIds(vec![
+                    DisplayPath(other.clone()),
+                    DisplayPath(path.clone()),
+                ]));
             }
+
+            std::fs::write(&stamped_path, &bytes).map_err(|error| Error::Write {
+                path: stamped_path.clone(),
+                error,
+            })?;
+            stamped.push((stamped_path.clone(), std::mem::replace(path, stamped_path)));
+        }
+        if stamped.is_empty() {
+            return Err(Error::NoImageIdSymbol);
         }
     }
 
-    if included_symbols.iter().any(|x| x.1.is_none()) {
-        for (name, addr) in included_symbols.iter() {
-            if addr.is_none() {
-                eprintln!("error: Symbol '{}' is not defined", name);
+    let mut builder = ImportLibrary::builder()
+        .nsc_section(opt.nsc_section.as_str())
+        .scan_sg_gadgets(opt.scan_sg_gadgets)
+        .verify_veneers(opt.verify_veneers)
+        .find_signatures(opt.c_header.is_some() || opt.zig_decls.is_some());
+    for (partition, path) in inputs.iter() {
+        builder = match partition {
+            Some(partition) => builder.partition_input(*partition, path),
+            None => builder.input(path),
+        };
+    }
+    for name in opt.symbols.iter() {
+        if let Some(path) = name.strip_prefix('@') {
+            for name in read_symbol_list(path.as_ref())? {
+                builder = builder.symbol(name);
             }
+        } else {
+            builder = builder.symbol(name.as_str());
         }
-        panic!("Aborting due to undefined symbols");
+    }
+    for pattern in opt.include.iter() {
+        builder = builder.include(pattern.clone());
+    }
+    for pattern in opt.exclude.iter() {
+        builder = builder.exclude(pattern.clone());
     }
 
-    let mut symbols: Vec<_> = included_symbols
-        .into_iter()
-        .map(|(name, addr)| (name, addr.unwrap()))
-        .collect();
-    symbols.sort_by_key(|(_, addr)| *addr);
-
-    // Open the output stream
-    let (mut out_file, mut out_stdout);
-    let writer: &mut dyn io::Write;
-    let path_display;
-    let out_name: &dyn std::fmt::Display;
-    if let Some(out_path) = &opt.output {
-        let file = std::fs::File::create(out_path)
-            .unwrap_or_else(|x| panic!("failed to open file {:?}: {:?}", out_path, x));
-        out_file = io::BufWriter::new(file);
-        writer = &mut out_file;
-
-        path_display = out_path.display();
-        out_name = &path_display;
+    let mut implib = builder.build()?;
+
+    let is_partitioned = opt.input.iter().any(|input| input.partition.is_some());
+    let implibs = if is_partitioned && opt.partition_output == PartitionOutput::Separate {
+        let mut implibs = Vec::new();
+        for (partition, implib) in implib.split_partitions() {
+            let partition = partition.ok_or(Error::UntaggedInput)?;
+            let output_path = |path: &Option<PathBuf>| {
+                path.as_ref()
+                    .map(|path| partition_path(path, &partition))
+                    .transpose()
+            };
+            let outputs = Outputs {
+                in_implib: output_path(&opt.in_implib)?,
+                c_header: output_path(&opt.c_header)?,
+                zig_decls: output_path(&opt.zig_decls)?,
+                manifest: output_path(&opt.manifest)?,
+                output: Some(
+                    output_path(&opt.output)?.ok_or(Error::NoPartitionPlaceholder("-".into()))?,
+                ),
+            };
+            implibs.push((implib, outputs));
+        }
+        implibs
     } else {
-        out_stdout = io::stdout();
-        writer = &mut out_stdout;
+        implib.prefix_partition_names();
+        let outputs = Outputs {
+            in_implib: opt.in_implib.clone(),
+            c_header: opt.c_header.clone(),
+            zig_decls: opt.zig_decls.clone(),
+            manifest: opt.manifest.clone(),
+            output: opt.output.clone(),
+        };
+        vec![(implib, outputs)]
+    };
 
-        out_name = &"-";
+    // Don't write anything unless all import libraries are compatible with
+    // the previous ones
+    let mut compatible = true;
+    for (implib, outputs) in implibs.iter() {
+        if let Some(in_implib) = &outputs.in_implib {
+            compatible &= check_compatibility(implib, in_implib)?;
+        }
     }
+    if !compatible {
+        std::process::exit(1);
+    }
+
+    for (implib, outputs) in implibs.iter() {
+        write_outputs(opt, implib, outputs)?;
+    }
+
+    Ok(())
+}
+
+/// The paths of the files to generate from an import library.
+struct Outputs {
+    in_implib: Option<PathBuf>,
+    c_header: Option<PathBuf>,
+    zig_decls: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    /// Defaults to stdout
+    output: Option<PathBuf>,
+}
+
+/// Substitute `%partition%` in `path` with the partition name.
+fn partition_path(path: &Path, partition: &str) -> Result<PathBuf, Error> {
+    let path_str = path.to_string_lossy();
+    if !path_str.contains("%partition%") {
+        return Err(Error::NoPartitionPlaceholder(path.to_owned()));
+    }
+    Ok(path_str.replace("%partition%", partition).into())
+}
 
-    (|| -> Result<(), io::Error> {
-        // Generate an assembler input
-        // FIXME: Probably this isn't the right way to create an import library.
-        writeln!(writer, ".syntax unified")?;
-        for (name, addr) in &symbols {
-            writeln!(writer, ".type {} function", name)?;
-            writeln!(writer, ".set {}, 0x{:08x}", name, addr)?;
-            writeln!(writer, ".global {}", name)?;
+/// Compare `implib` against a previous import library and report the
+/// changes. Returns `false` if any of them is breaking.
+fn check_compatibility(implib: &ImportLibrary, in_implib: &Path) -> Result<bool, Error> {
+    let old_symbols = inimplib::load_import_library(in_implib)?;
+
+    let mut compatible = true;
+    for change in implib.compare_addresses(&old_symbols) {
+        if change.is_breaking() {
+            eprintln!("error: {}", change);
+            compatible = false;
+        } else {
+            eprintln!("note: {}", change);
         }
-        Ok(())
-    })()
-    .unwrap_or_else(|x| panic!("failed to write '{}': {:?}", out_name, x));
+    }
+
+    if !compatible {
+        eprintln!(
+            "error: Aborting due to changes incompatible with the previous import library {:?}",
+            in_implib
+        );
+    }
+
+    Ok(compatible)
+}
+
+fn write_outputs(opt: &Opt, implib: &ImportLibrary, outputs: &Outputs) -> Result<(), Error> {
+    if let Some(path) = &outputs.c_header {
+        implib.write_to_file(path, &writer::CHeaderWriter)?;
+    }
+    if let Some(path) = &outputs.zig_decls {
+        implib.write_to_file(path, &writer::ZigDeclsWriter)?;
+    }
+    if let Some(path) = &outputs.manifest {
+        implib.write_to_file(path, &writer::ManifestWriter)?;
+    }
+
+    let writer = opt.format.writer();
+    if let Some(out_path) = &outputs.output {
+        implib.write_to_file(out_path, writer)?;
+    } else {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        implib
+            .write(writer, &mut out)
+            .and_then(|()| io::Write::flush(&mut out))
+            .map_err(|error| Error::Write {
+                path: "-".into(),
+                error,
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Read a list of symbol names, one per line.
+fn read_symbol_list(path: &Path) -> Result<Vec<String>, Error> {
+    let text = std::fs::read_to_string(path).map_err(|error| Error::Read {
+        path: path.to_owned(),
+        error,
+    })?;
+    Ok(parse_symbol_list(&text))
+}
+
+/// Parse a list of symbol names, skipping blank lines and comments.
+fn parse_symbol_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_list() {
+        let text = "# Gateways exported to the RTOS\nTCInitialize\n\n  TCLog  \r\n#TCDebug\n";
+        assert_eq!(parse_symbol_list(text), ["TCInitialize", "TCLog"]);
+    }
 }
@@ -0,0 +1,266 @@
+//! The machine-readable manifest of exported Secure gateways.
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io, path::Path};
+
+use super::{Error, ImportLibrary};
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    /// The Secure images from which the symbols were collected
+    pub images: Vec<ManifestImage>,
+    pub symbols: Vec<ManifestSymbol>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ManifestImage {
+    pub path: String,
+    /// The partition the image belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<String>,
+    /// The SHA-256 hash of the image file, represented in lowercase
+    /// hexadecimal digits
+    pub sha256: String,
+    /// The stamped identity of the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ManifestSymbol {
+    pub name: String,
+    /// The symbol value. The lowest bit indicates the Thumb state.
+    pub address: u64,
+    pub size: u64,
+    /// The name of the section containing the symbol
+    pub section: String,
+    /// The index into `Manifest::images`
+    pub image: usize,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bytes = std::fs::read(path).map_err(|error| Error::Read {
+            path: path.to_owned(),
+            error,
+        })?;
+        Self::from_slice(&bytes).map_err(|e| Error::Parse {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+
+    pub fn write(&self, writer: &mut dyn io::Write) -> Result<(), io::Error> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)
+    }
+
+    /// Get the addresses of the symbols indexed by their names.
+    pub fn symbol_addresses(&self) -> HashMap<String, u64> {
+        self.symbols
+            .iter()
+            .map(|sym| (sym.name.clone(), sym.address))
+            .collect()
+    }
+}
+
+impl From<&ImportLibrary> for Manifest {
+    fn from(implib: &ImportLibrary) -> Self {
+        Self {
+            images: implib
+                .images
+                .iter()
+                .map(|image| ManifestImage {
+                    path: image.path.display().to_string(),
+                    partition: image.partition.clone(),
+                    sha256: image.sha256.clone(),
+                    image_id: image.image_id,
+                })
+                .collect(),
+            symbols: implib
+                .symbols
+                .iter()
+                .map(|sym| ManifestSymbol {
+                    name: sym.name.clone(),
+                    address: sym.address,
+                    size: sym.size,
+                    section: sym.section.clone(),
+                    image: sym.image,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Get the SHA-256 hash of the specified bytes in hexadecimal digits.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(bytes))
+}
+
+/// Compare two manifests and print the differences. Returns `true` if the
+/// new one is backward compatible with the old one, i.e., Non-Secure images
+/// linked against the old one keep working with the new Secure image.
+pub fn diff(old: &Manifest, new: &Manifest, writer: &mut dyn io::Write) -> Result<bool, io::Error> {
+    let old_symbols: HashMap<&str, &ManifestSymbol> =
+        old.symbols.iter().map(|s| (s.name.as_str(), s)).collect();
+    let new_symbols: HashMap<&str, &ManifestSymbol> =
+        new.symbols.iter().map(|s| (s.name.as_str(), s)).collect();
+
+    let mut compatible = true;
+
+    for old_sym in old.symbols.iter() {
+        match new_symbols.get(old_sym.name.as_str()) {
+            None => {
+                writeln!(
+                    writer,
+                    "breaking: '{}' (0x{:08x}) was removed",
+                    old_sym.name, old_sym.address
+                )?;
+                compatible = false;
+            }
+            Some(new_sym) if new_sym.address != old_sym.address => {
+                writeln!(
+                    writer,
+                    "breaking: '{}' moved from 0x{:08x} to 0x{:08x}",
+                    old_sym.name, old_sym.address, new_sym.address
+                )?;
+                compatible = false;
+            }
+            Some(new_sym) if new_sym.size != old_sym.size => {
+                writeln!(
+                    writer,
+                    "compatible: '{}' (0x{:08x}) changed its size from {} to {}",
+                    old_sym.name, old_sym.address, old_sym.size, new_sym.size
+                )?;
+            }
+            Some(_) => {}
+        }
+    }
+
+    for new_sym in new.symbols.iter() {
+        if !old_symbols.contains_key(new_sym.name.as_str()) {
+            writeln!(
+                writer,
+                "compatible: '{}' (0x{:08x}) was added",
+                new_sym.name, new_sym.address
+            )?;
+        }
+    }
+
+    if compatible {
+        writeln!(
+            writer,
+            "The new Secure image is compatible with the old one."
+        )?;
+    } else {
+        writeln!(
+            writer,
+            "The new Secure image is NOT compatible with the old one. \
+            Non-Secure images must be relinked."
+        )?;
+    }
+
+    Ok(compatible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(symbols: &[(&str, u64, u64)]) -> Manifest {
+        Manifest {
+            images: vec![ManifestImage {
+                path: "secure.elf".to_owned(),
+                partition: None,
+                sha256: String::new(),
+                image_id: None,
+            }],
+            symbols: symbols
+                .iter()
+                .map(|&(name, address, size)| ManifestSymbol {
+                    name: name.to_owned(),
+                    address,
+                    size,
+                    section: ".gnu.sgstubs".to_owned(),
+                    image: 0,
+                })
+                .collect(),
+        }
+    }
+
+    fn diff_lines(old: &Manifest, new: &Manifest) -> (bool, Vec<String>) {
+        let mut out = Vec::new();
+        let compatible = diff(old, new, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        (compatible, out.lines().map(str::to_owned).collect())
+    }
+
+    const OLD: &[(&str, u64, u64)] = &[("TCInitialize", 0x10000001, 32), ("TCLog", 0x10000021, 32)];
+
+    #[test]
+    fn diff_identical() {
+        let (compatible, lines) = diff_lines(&manifest(OLD), &manifest(OLD));
+        assert!(compatible);
+        assert_eq!(
+            lines,
+            ["The new Secure image is compatible with the old one."]
+        );
+    }
+
+    #[test]
+    fn diff_added() {
+        let new = manifest(&[
+            ("TCInitialize", 0x10000001, 32),
+            ("TCLog", 0x10000021, 32),
+            ("TCReset", 0x10000041, 32),
+        ]);
+        let (compatible, lines) = diff_lines(&manifest(OLD), &new);
+        assert!(compatible);
+        assert_eq!(lines[0], "compatible: 'TCReset' (0x10000041) was added");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn diff_resized() {
+        let new = manifest(&[("TCInitialize", 0x10000001, 32), ("TCLog", 0x10000021, 24)]);
+        let (compatible, lines) = diff_lines(&manifest(OLD), &new);
+        assert!(compatible);
+        assert_eq!(
+            lines[0],
+            "compatible: 'TCLog' (0x10000021) changed its size from 32 to 24"
+        );
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn diff_removed() {
+        let new = manifest(&[("TCInitialize", 0x10000001, 32)]);
+        let (compatible, lines) = diff_lines(&manifest(OLD), &new);
+        assert!(!compatible);
+        assert_eq!(lines[0], "breaking: 'TCLog' (0x10000021) was removed");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn diff_moved() {
+        let new = manifest(&[
+            ("TCInitialize", 0x10000001, 32),
+            ("TCReset", 0x10000021, 32),
+            ("TCLog", 0x10000041, 32),
+        ]);
+        let (compatible, lines) = diff_lines(&manifest(OLD), &new);
+        assert!(!compatible);
+        assert_eq!(
+            lines[..2],
+            [
+                "breaking: 'TCLog' moved from 0x10000021 to 0x10000041",
+                "compatible: 'TCReset' (0x10000021) was added",
+            ]
+        );
+        assert!(lines[2].contains("NOT compatible"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Symbol name patterns.
+use std::{fmt, str::FromStr};
+
+use super::Error;
+
+/// A pattern to select symbols by name. Parsed as a glob pattern (e.g.,
+/// `TCDebug*`) unless prefixed with `re:`, in which case the rest is parsed
+/// as a regular expression matching the whole name (e.g., `re:TC(Get|Set).*`).
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    matcher: Matcher,
+}
+
+#[derive(Clone)]
+enum Matcher {
+    Glob(globset::GlobMatcher),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    pub fn is_match(&self, name: &str) -> bool {
+        match &self.matcher {
+            Matcher::Glob(glob) => glob.is_match(name),
+            Matcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: String| Error::InvalidPattern {
+            pattern: source.to_owned(),
+            message,
+        };
+
+        let matcher = if let Some(regex) = source.strip_prefix("re:") {
+            Matcher::Regex(
+                regex::Regex::new(&format!("^(?:{})$", regex))
+                    .map_err(|e| invalid(e.to_string()))?,
+            )
+        } else {
+            Matcher::Glob(
+                globset::GlobBuilder::new(source)
+                    .literal_separator(false)
+                    .build()
+                    .map_err(|e| invalid(e.kind().to_string()))?
+                    .compile_matcher(),
+            )
+        };
+
+        Ok(Self {
+            source: source.to_owned(),
+            matcher,
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pattern({:?})", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(source: &str) -> Pattern {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn glob() {
+        let p = pattern("TCDebug*");
+        assert!(p.is_match("TCDebug"));
+        assert!(p.is_match("TCDebugDumpLog"));
+        assert!(!p.is_match("__acle_se_TCDebugDumpLog"));
+
+        let p = pattern("TC?et[A-Z]*");
+        assert!(p.is_match("TCGetSecureImageId"));
+        assert!(p.is_match("TCSetMode"));
+        assert!(!p.is_match("TCReset"));
+
+        // Symbol names are not paths
+        assert!(pattern("*").is_match("a/b"));
+    }
+
+    #[test]
+    fn regex_is_anchored() {
+        let p = pattern("re:TC(Get|Set).*");
+        assert!(p.is_match("TCGetSecureImageId"));
+        assert!(!p.is_match("__acle_se_TCGetSecureImageId"));
+
+        let p = pattern("re:TCLog|TCReset");
+        assert!(p.is_match("TCLog"));
+        assert!(p.is_match("TCReset"));
+        assert!(!p.is_match("TCLogger"));
+        assert!(!p.is_match("MyTCReset"));
+    }
+
+    #[test]
+    fn display_source() {
+        assert_eq!(pattern("re:TC.*").to_string(), "re:TC.*");
+        assert_eq!(pattern("TC*").to_string(), "TC*");
+    }
+
+    #[test]
+    fn reject_invalid_patterns() {
+        for source in ["re:TC(", "TC[a-"].iter() {
+            match source.parse::<Pattern>() {
+                Err(Error::InvalidPattern { pattern, .. }) => assert_eq!(pattern, *source),
+                _ => panic!("'{}' was accepted", source),
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Builds small ELF32 images for unit tests.
+//!
+//! The section headers are placed right after the ELF header, followed by
+//! the symbol and string tables and then the contents of the sections added
+//! by `section`, in that order. Truncating the image thus cuts off the last
+//! added section first.
+use goblin::elf::{
+    header::EM_ARM,
+    section_header::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB},
+    sym::{STB_GLOBAL, STB_LOCAL, STT_FUNC},
+};
+use std::{fs, path::PathBuf};
+
+const SIZEOF_EHDR: usize = 52;
+const SIZEOF_SHDR: usize = 40;
+const SIZEOF_SYM: usize = 16;
+
+/// The flags of a code section
+pub const TEXT: u32 = SHF_ALLOC | SHF_EXECINSTR;
+
+/// The flags of a data section
+pub const DATA: u32 = SHF_ALLOC | SHF_WRITE;
+
+struct Section {
+    name: String,
+    flags: u32,
+    addr: u32,
+    data: Vec<u8>,
+}
+
+struct Symbol {
+    name: String,
+    value: u32,
+    size: u32,
+    info: u8,
+    shndx: u16,
+}
+
+pub struct ElfBuilder {
+    e_type: u16,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+}
+
+impl ElfBuilder {
+    /// Construct an `ElfBuilder` producing a file of the specified type
+    /// (e.g., `ET_EXEC`).
+    pub fn new(e_type: u16) -> Self {
+        Self {
+            e_type,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Add a `SHT_PROGBITS` section. Returns its section index.
+    pub fn section(&mut self, name: &str, flags: u32, addr: u32, data: &[u8]) -> usize {
+        self.sections.push(Section {
+            name: name.to_owned(),
+            flags,
+            addr,
+            data: data.to_vec(),
+        });
+        self.sections.len()
+    }
+
+    /// Add a symbol with the specified `st_info`. Local symbols must be added
+    /// before global ones.
+    pub fn symbol(&mut self, name: &str, value: u32, size: u32, info: u8, shndx: usize) {
+        self.symbols.push(Symbol {
+            name: name.to_owned(),
+            value,
+            size,
+            info,
+            shndx: shndx as u16,
+        });
+    }
+
+    /// Add a global function symbol. `value` should include the Thumb bit.
+    pub fn function(&mut self, name: &str, value: u32, shndx: usize) {
+        self.symbol(name, value, 0, (STB_GLOBAL << 4) | STT_FUNC, shndx);
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        // Section indices following those of `self.sections`
+        let shndx_symtab = self.sections.len() as u32 + 1;
+        let shndx_strtab = shndx_symtab + 1;
+        let shndx_shstrtab = shndx_symtab + 2;
+        let num_sections = self.sections.len() + 4;
+
+        let mut shstrtab = vec![0u8];
+        let mut add_shstr = |name: &str| {
+            let offset = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            offset
+        };
+        let section_names: Vec<u32> = self.sections.iter().map(|s| add_shstr(&s.name)).collect();
+        let name_symtab = add_shstr(".symtab");
+        let name_strtab = add_shstr(".strtab");
+        let name_shstrtab = add_shstr(".shstrtab");
+
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; SIZEOF_SYM];
+        for sym in self.symbols.iter() {
+            let st_name = strtab.len() as u32;
+            strtab.extend_from_slice(sym.name.as_bytes());
+            strtab.push(0);
+
+            put_u32(&mut symtab, st_name);
+            put_u32(&mut symtab, sym.value);
+            put_u32(&mut symtab, sym.size);
+            symtab.push(sym.info);
+            symtab.push(0); // st_other
+            put_u16(&mut symtab, sym.shndx);
+        }
+        let num_locals = self
+            .symbols
+            .iter()
+            .take_while(|sym| sym.info >> 4 == STB_LOCAL)
+            .count();
+
+        let mut out = Vec::new();
+
+        out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        put_u16(&mut out, self.e_type);
+        put_u16(&mut out, EM_ARM);
+        put_u32(&mut out, 1); // e_version
+        put_u32(&mut out, 0); // e_entry
+        put_u32(&mut out, 0); // e_phoff
+        put_u32(&mut out, SIZEOF_EHDR as u32); // e_shoff
+        put_u32(&mut out, 0x0500_0000); // e_flags
+        put_u16(&mut out, SIZEOF_EHDR as u16);
+        put_u16(&mut out, 0); // e_phentsize
+        put_u16(&mut out, 0); // e_phnum
+        put_u16(&mut out, SIZEOF_SHDR as u16);
+        put_u16(&mut out, num_sections as u16);
+        put_u16(&mut out, shndx_shstrtab as u16);
+
+        // Lay out the contents
+        let mut offset = SIZEOF_EHDR + SIZEOF_SHDR * num_sections;
+        let mut place = |len: usize| {
+            let start = offset;
+            offset += len;
+            start as u32
+        };
+        let symtab_offset = place(symtab.len());
+        let strtab_offset = place(strtab.len());
+        let shstrtab_offset = place(shstrtab.len());
+        let section_offsets: Vec<u32> = self.sections.iter().map(|s| place(s.data.len())).collect();
+
+        let mut put_shdr = |shdr: [u32; 10]| {
+            for &field in shdr.iter() {
+                put_u32(&mut out, field);
+            }
+        };
+        put_shdr([0; 10]);
+        for ((section, &name), &offset) in self
+            .sections
+            .iter()
+            .zip(&section_names)
+            .zip(&section_offsets)
+        {
+            put_shdr([
+                name,
+                SHT_PROGBITS,
+                section.flags,
+                section.addr,
+                offset,
+                section.data.len() as u32,
+                0,
+                0,
+                2,
+                0,
+            ]);
+        }
+        put_shdr([
+            name_symtab,
+            SHT_SYMTAB,
+            0,
+            0,
+            symtab_offset,
+            symtab.len() as u32,
+            shndx_strtab,
+            num_locals as u32 + 1,
+            4,
+            SIZEOF_SYM as u32,
+        ]);
+        put_shdr([
+            name_strtab,
+            SHT_STRTAB,
+            0,
+            0,
+            strtab_offset,
+            strtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ]);
+        put_shdr([
+            name_shstrtab,
+            SHT_STRTAB,
+            0,
+            0,
+            shstrtab_offset,
+            shstrtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ]);
+
+        out.extend_from_slice(&symtab);
+        out.extend_from_slice(&strtab);
+        out.extend_from_slice(&shstrtab);
+        for section in self.sections.iter() {
+            out.extend_from_slice(&section.data);
+        }
+
+        out
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, x: u16) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, x: u32) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+/// A file in the temporary directory, which is removed when dropped.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    /// Create a file with the specified contents. `name` must be unique
+    /// among tests.
+    pub fn new(name: &str, bytes: &[u8]) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("tzmcfi_mkimplib-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
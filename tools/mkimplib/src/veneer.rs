@@ -0,0 +1,491 @@
+//! Verifies the structure of Secure gateway veneers generated by
+//! `arm_cmse.exportNonSecureCallable`, which look like this:
+//!
+//! ```text
+//!     sg
+//!     push {r4, lr}
+//!     ldr r4, =func           @ or `movw` + `movt`
+//!     blx r4
+//!     pop.w {r4, lr}
+//!     mov r1, lr
+//!     mov r2, lr
+//!     mov r3, lr
+//!     mov ip, lr
+//!     msr apsr_nzcvq, lr
+//!     bxns lr
+//! ```
+//!
+//! Only the small subset of the Thumb-2 instruction set needed to describe
+//! such veneers is decoded. Everything else is rejected.
+
+/// The registers which must be cleared before returning to Non-Secure.
+/// `r0` holds the return value.
+const CALLER_SAVED: [u8; 4] = [1, 2, 3, 12];
+
+const REG_R4: u8 = 4;
+const REG_SP: u8 = 13;
+const REG_LR: u8 = 14;
+const REG_PC: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Insn {
+    Sg,
+    /// `push` with the register list
+    Push(u16),
+    /// `pop` with the register list
+    Pop(u16),
+    /// `blx Rm`
+    Blx(u8),
+    /// `bxns Rm`
+    Bxns(u8),
+    /// An instruction writing to a single register without branching
+    Write {
+        rd: u8,
+        value: Value,
+    },
+    /// `msr apsr_nzcvq, Rn` or `msr apsr_nzcvqg, Rn`
+    MsrApsr(u8),
+    Nop,
+    /// A branch, a write to `pc`, or an `IT` instruction
+    Branch,
+    Unknown,
+}
+
+/// The value written by `Insn::Write`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    /// A copy of `lr`, which is already known to Non-Secure
+    Lr,
+    Zero,
+    Other,
+}
+
+fn is_32bit(hw1: u16) -> bool {
+    // The first halfword begins with 0b11101, 0b11110, or 0b11111
+    hw1 >= 0xe800
+}
+
+fn decode16(hw: u16) -> Insn {
+    let low3 = |shift: u16| ((hw >> shift) & 7) as u8;
+
+    if hw == 0xbf00 {
+        Insn::Nop
+    } else if hw & 0xff00 == 0xbf00 {
+        // IT
+        Insn::Branch
+    } else if hw & 0xff87 == 0x4704 {
+        Insn::Bxns(((hw >> 3) & 0xf) as u8)
+    } else if hw & 0xff87 == 0x4780 {
+        Insn::Blx(((hw >> 3) & 0xf) as u8)
+    } else if hw & 0xff00 == 0x4700 {
+        // BX, BLXNS
+        Insn::Branch
+    } else if hw & 0xff00 == 0x4600 {
+        // MOV (register)
+        let rd = (((hw >> 4) & 8) as u8) | low3(0);
+        let rm = ((hw >> 3) & 0xf) as u8;
+        if rd == REG_PC {
+            Insn::Branch
+        } else {
+            Insn::Write {
+                rd,
+                value: if rm == REG_LR {
+                    Value::Lr
+                } else {
+                    Value::Other
+                },
+            }
+        }
+    } else if hw & 0xf800 == 0x2000 {
+        // MOVS (immediate)
+        Insn::Write {
+            rd: low3(8),
+            value: if hw & 0xff == 0 {
+                Value::Zero
+            } else {
+                Value::Other
+            },
+        }
+    } else if hw & 0xf800 == 0x4800 {
+        // LDR (literal)
+        Insn::Write {
+            rd: low3(8),
+            value: Value::Other,
+        }
+    } else if hw & 0xfe00 == 0xb400 {
+        Insn::Push((hw & 0xff) | ((hw & 0x100) << 6))
+    } else if hw & 0xfe00 == 0xbc00 {
+        Insn::Pop((hw & 0xff) | ((hw & 0x100) << 7))
+    } else if hw & 0xf000 == 0xd000 {
+        if hw & 0x0f00 == 0x0e00 {
+            // UDF
+            Insn::Unknown
+        } else {
+            // B<c>, SVC
+            Insn::Branch
+        }
+    } else if hw & 0xf800 == 0xe000 || hw & 0xf500 == 0xb100 {
+        // B, CBZ, CBNZ
+        Insn::Branch
+    } else {
+        Insn::Unknown
+    }
+}
+
+fn decode32(hw1: u16, hw2: u16) -> Insn {
+    let rd = ((hw2 >> 8) & 0xf) as u8;
+
+    if hw1 == 0xe97f && hw2 == 0xe97f {
+        Insn::Sg
+    } else if hw1 == 0xe92d {
+        Insn::Push(hw2)
+    } else if hw1 == 0xe8bd {
+        Insn::Pop(hw2)
+    } else if hw1 & 0xfff0 == 0xf380 && hw2 & 0xf3ff == 0x8000 && hw2 & 0x0800 != 0 {
+        // MSR APSR_nzcvq or APSR_nzcvqg (`mask<1>` must be set)
+        Insn::MsrApsr((hw1 & 0xf) as u8)
+    } else if hw1 & 0xf800 == 0xf000 && hw2 & 0x8000 != 0 {
+        // B.W, BL, and miscellaneous control instructions
+        Insn::Branch
+    } else if hw1 & 0xfbf0 == 0xf240 {
+        // MOVW
+        let is_zero = hw1 & 0x040f == 0 && hw2 & 0x70ff == 0;
+        Insn::Write {
+            rd,
+            value: if is_zero { Value::Zero } else { Value::Other },
+        }
+    } else if hw1 & 0xfbf0 == 0xf2c0 {
+        // MOVT
+        Insn::Write {
+            rd,
+            value: Value::Other,
+        }
+    } else if hw1 & 0xfbef == 0xf04f && hw2 & 0x8000 == 0 {
+        // MOV.W (immediate)
+        let is_zero = hw1 & 0x0400 == 0 && hw2 & 0x70ff == 0;
+        Insn::Write {
+            rd,
+            value: if is_zero { Value::Zero } else { Value::Other },
+        }
+    } else if hw1 & 0xff7f == 0xf85f {
+        // LDR.W (literal)
+        let rt = ((hw2 >> 12) & 0xf) as u8;
+        if rt == REG_PC {
+            Insn::Branch
+        } else {
+            Insn::Write {
+                rd: rt,
+                value: Value::Other,
+            }
+        }
+    } else {
+        Insn::Unknown
+    }
+}
+
+fn reg_name(reg: u8) -> String {
+    match reg {
+        12 => "ip".to_owned(),
+        REG_SP => "sp".to_owned(),
+        REG_LR => "lr".to_owned(),
+        REG_PC => "pc".to_owned(),
+        _ => format!("r{}", reg),
+    }
+}
+
+/// Check that `code` (located at `addr`) has the shape of a veneer generated
+/// by `exportNonSecureCallable`.
+///
+/// The code must be straight-line: the only control transfers allowed are
+/// the call to the function in `blx r4` and the return to Non-Secure in
+/// `bxns lr`. Between them, `r4` and `lr` must be restored and the caller-saved
+/// registers and the flags must be cleared so that no Secure information is
+/// leaked to Non-Secure.
+pub fn check(code: &[u8], addr: u64) -> Result<(), String> {
+    const PUSH_LIST: u16 = (1 << REG_R4) | (1 << REG_LR);
+
+    let mut offset = 0;
+    let mut pushed = false;
+    let mut r4_loaded = false;
+    let mut called = false;
+    let mut popped = false;
+    // The registers cleared since the call
+    let mut cleared: u16 = 0;
+    let mut apsr_cleared = false;
+
+    loop {
+        let read_hw = |offset: usize| {
+            code.get(offset..offset + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let insn_addr = addr + offset as u64;
+
+        let hw1 = read_hw(offset).ok_or_else(|| {
+            format!(
+                "it ends at 0x{:08x} without returning by `bxns lr`",
+                insn_addr
+            )
+        })?;
+        let (insn, len) = if is_32bit(hw1) {
+            let hw2 = read_hw(offset + 2).ok_or_else(|| {
+                format!(
+                    "it ends in the middle of an instruction at 0x{:08x}",
+                    insn_addr
+                )
+            })?;
+            (decode32(hw1, hw2), 4)
+        } else {
+            (decode16(hw1), 2)
+        };
+
+        let unexpected = |what: &str| {
+            let encoding = if len == 4 {
+                format!("0x{:04x} 0x{:04x}", hw1, read_hw(offset + 2).unwrap())
+            } else {
+                format!("0x{:04x}", hw1)
+            };
+            Err(format!(
+                "{} at 0x{:08x} (found {})",
+                what, insn_addr, encoding
+            ))
+        };
+
+        if offset == 0 && insn != Insn::Sg {
+            return unexpected("the first instruction is not SG");
+        }
+
+        match insn {
+            Insn::Sg if offset == 0 => {}
+            Insn::Sg => return unexpected("unexpected SG"),
+            Insn::Push(list) if list == PUSH_LIST && !pushed => pushed = true,
+            Insn::Push(_) => return unexpected("unexpected push"),
+            Insn::Blx(REG_R4) => {
+                if !pushed {
+                    return unexpected("`blx r4` precedes `push {r4, lr}`");
+                }
+                if !r4_loaded {
+                    return unexpected(
+                        "`r4` is not loaded with the function address before `blx r4`",
+                    );
+                }
+                if called {
+                    return unexpected("more than one call");
+                }
+                called = true;
+                cleared = 0;
+                apsr_cleared = false;
+            }
+            Insn::Blx(reg) => {
+                return unexpected(&format!("unexpected call through {}", reg_name(reg)))
+            }
+            Insn::Pop(list) if list & (1 << REG_PC) != 0 => {
+                return unexpected("it returns without `bxns lr`")
+            }
+            Insn::Pop(list) if list == PUSH_LIST && called && !popped => popped = true,
+            Insn::Pop(_) => return unexpected("unexpected pop"),
+            Insn::Write { rd, value } => {
+                if rd == REG_LR || rd == REG_SP {
+                    return unexpected(&format!("unexpected write to {}", reg_name(rd)));
+                }
+                if rd == REG_R4 && popped {
+                    return unexpected("unexpected write to r4 after restoring it");
+                }
+                if rd == REG_R4 && pushed && !called {
+                    r4_loaded = true;
+                }
+                if value == Value::Other {
+                    cleared &= !(1 << rd);
+                } else {
+                    cleared |= 1 << rd;
+                }
+            }
+            Insn::MsrApsr(rn) => {
+                if !called {
+                    return unexpected("the flags are cleared before the call");
+                }
+                if rn != REG_LR && cleared & (1 << rn) == 0 {
+                    return unexpected(&format!(
+                        "the flags are set from {}, which may hold Secure information",
+                        reg_name(rn)
+                    ));
+                }
+                apsr_cleared = true;
+            }
+            Insn::Nop => {}
+            Insn::Bxns(REG_LR) => {
+                if !called {
+                    return unexpected("it returns without calling the function");
+                }
+                if !popped {
+                    return unexpected("it returns without restoring `r4` and `lr`");
+                }
+                let uncleared: Vec<_> = CALLER_SAVED
+                    .iter()
+                    .filter(|&&reg| cleared & (1 << reg) == 0)
+                    .map(|&reg| reg_name(reg))
+                    .collect();
+                if !uncleared.is_empty() {
+                    return unexpected(&format!(
+                        "{} {} not cleared before returning",
+                        uncleared.join(", "),
+                        if uncleared.len() == 1 { "is" } else { "are" }
+                    ));
+                }
+                if !apsr_cleared {
+                    return unexpected("the flags are not cleared before returning");
+                }
+                return Ok(());
+            }
+            Insn::Bxns(reg) => return unexpected(&format!("it returns through {}", reg_name(reg))),
+            Insn::Branch => return unexpected("unexpected branch"),
+            Insn::Unknown => return unexpected("unexpected instruction"),
+        }
+
+        offset += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SG: [u16; 2] = [0xe97f, 0xe97f];
+    const PUSH: [u16; 1] = [0xb510];
+    const LDR_R4: [u16; 1] = [0x4c01];
+    const MOVW_MOVT_R4: [u16; 4] = [0xf241, 0x2434, 0xf2c5, 0x6478];
+    const BLX_R4: [u16; 1] = [0x47a0];
+    const POP_W: [u16; 2] = [0xe8bd, 0x4010];
+    const CLEAR_REGS: [u16; 4] = [0x4671, 0x4672, 0x4673, 0x46f4];
+    const MSR_NZCVQ: [u16; 2] = [0xf38e, 0x8800];
+    const MSR_NZCVQG: [u16; 2] = [0xf38e, 0x8c00];
+    const MSR_G: [u16; 2] = [0xf38e, 0x8400];
+    const BXNS_LR: [u16; 1] = [0x4774];
+    const UDF: [u16; 1] = [0xdefe];
+
+    fn assemble(parts: &[&[u16]]) -> Vec<u8> {
+        parts
+            .iter()
+            .flat_map(|part| part.iter())
+            .flat_map(|hw| hw.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn veneer(load: &[u16], msr: &[u16]) -> Vec<u8> {
+        assemble(&[
+            &SG,
+            &PUSH,
+            load,
+            &BLX_R4,
+            &POP_W,
+            &CLEAR_REGS,
+            msr,
+            &BXNS_LR,
+            &UDF,
+        ])
+    }
+
+    #[test]
+    fn accept_ldr() {
+        assert_eq!(check(&veneer(&LDR_R4, &MSR_NZCVQ), 0x1000), Ok(()));
+    }
+
+    #[test]
+    fn accept_movw_movt() {
+        assert_eq!(check(&veneer(&MOVW_MOVT_R4, &MSR_NZCVQ), 0x1000), Ok(()));
+    }
+
+    #[test]
+    fn accept_msr_nzcvqg() {
+        assert_eq!(check(&veneer(&LDR_R4, &MSR_NZCVQG), 0x1000), Ok(()));
+    }
+
+    #[test]
+    fn reject_msr_g() {
+        let error = check(&veneer(&LDR_R4, &MSR_G), 0x1000).unwrap_err();
+        // Decoded as a miscellaneous control instruction
+        assert!(
+            error.starts_with("unexpected branch at 0x00001016"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn reject_missing_sg() {
+        let code = assemble(&[&PUSH, &LDR_R4, &BLX_R4, &POP_W, &BXNS_LR]);
+        let error = check(&code, 0x1000).unwrap_err();
+        assert!(
+            error.starts_with("the first instruction is not SG"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn reject_uncleared_registers() {
+        let code = assemble(&[
+            &SG,
+            &PUSH,
+            &LDR_R4,
+            &BLX_R4,
+            &POP_W,
+            &CLEAR_REGS[..2],
+            &MSR_NZCVQ,
+            &BXNS_LR,
+        ]);
+        let error = check(&code, 0x1000).unwrap_err();
+        assert!(
+            error.starts_with("r3, ip are not cleared before returning"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn reject_write_to_r4_after_pop() {
+        // `movs r4, #0` after `pop.w {r4, lr}`
+        let code = assemble(&[
+            &SG,
+            &PUSH,
+            &LDR_R4,
+            &BLX_R4,
+            &POP_W,
+            &[0x2400],
+            &CLEAR_REGS,
+            &MSR_NZCVQ,
+            &BXNS_LR,
+        ]);
+        let error = check(&code, 0x1000).unwrap_err();
+        assert!(
+            error.starts_with("unexpected write to r4 after restoring it at 0x0000100e"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn reject_return_through_bx() {
+        let code = assemble(&[
+            &SG,
+            &PUSH,
+            &LDR_R4,
+            &BLX_R4,
+            &POP_W,
+            &CLEAR_REGS,
+            &MSR_NZCVQ,
+            &[0x4770],
+        ]);
+        let error = check(&code, 0x1000).unwrap_err();
+        assert!(error.starts_with("unexpected branch"), "{}", error);
+    }
+
+    #[test]
+    fn reject_truncated() {
+        let code = assemble(&[&SG, &PUSH, &LDR_R4, &BLX_R4]);
+        let error = check(&code, 0x1000).unwrap_err();
+        assert_eq!(
+            error,
+            "it ends at 0x0000100a without returning by `bxns lr`"
+        );
+    }
+}
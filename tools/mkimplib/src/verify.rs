@@ -0,0 +1,412 @@
+//! Checks the soundness of Secure gateways.
+use goblin::elf::{
+    section_header::{SHN_UNDEF, SHT_NOBITS},
+    sym::{self, STT_FUNC},
+    Elf, SectionHeader, Sym,
+};
+use std::{collections::HashSet, ops::Range};
+
+use super::{veneer, Image, SgGadget, Warning};
+
+/// The encoding of the `SG` instruction, represented as a sequence of
+/// halfwords.
+const SG_HALFWORDS: [u16; 2] = [0xe97f, 0xe97f];
+
+/// Check that `sym` is a defined Thumb function.
+pub fn check_function_symbol(sym: &Sym) -> Result<(), String> {
+    if sym.st_shndx == SHN_UNDEF as usize {
+        return Err("it is referenced but not defined".to_owned());
+    }
+
+    if sym.st_type() != STT_FUNC {
+        return Err(format!(
+            "it is not a function (type = {})",
+            sym::type_to_str(sym.st_type())
+        ));
+    }
+
+    // Armv8-M only supports the Thumb instruction set. Branching to an
+    // address without the Thumb bit causes a fault.
+    if sym.st_value & 1 == 0 {
+        return Err(format!(
+            "its value 0x{:08x} does not have the Thumb bit set",
+            sym.st_value
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that `sym` points to an `SG` instruction placed in the
+/// Non-Secure-callable section.
+pub fn check_entry_point(image: &Image<'_>, sym: &Sym, nsc_section: &str) -> Result<(), String> {
+    let elf = &image.elf;
+    let shdr = elf
+        .section_headers
+        .get(sym.st_shndx)
+        .filter(|_| sym.st_shndx != 0)
+        .ok_or_else(|| {
+            format!(
+                "it does not belong to a section (st_shndx = {})",
+                sym.st_shndx
+            )
+        })?;
+    let section_name = elf.shdr_strtab.get_unsafe(shdr.sh_name).unwrap_or("");
+
+    if section_name != nsc_section {
+        return Err(format!(
+            "it is located in the section '{}', not the Non-Secure-callable section '{}'",
+            section_name, nsc_section
+        ));
+    }
+
+    // Locate the first instruction. The lowest bit of the symbol value
+    // indicates the Thumb state and is not a part of the address.
+    let addr = sym.st_value & !1;
+    let code = section_contents_from(image, shdr, addr)
+        .filter(|code| code.len() >= 4)
+        .map(|code| &code[..4]);
+    let code = code.ok_or_else(|| {
+        format!(
+            "the contents at 0x{:08x} are not present in the section '{}'",
+            addr, section_name
+        )
+    })?;
+
+    let halfwords = [
+        u16::from_le_bytes([code[0], code[1]]),
+        u16::from_le_bytes([code[2], code[3]]),
+    ];
+    if halfwords != SG_HALFWORDS {
+        return Err(format!(
+            "the instruction at 0x{:08x} is not SG (found 0x{:04x} 0x{:04x})",
+            addr, halfwords[0], halfwords[1]
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that `sym` has the shape of a veneer generated by
+/// `arm_cmse.exportNonSecureCallable`. `sym` must have passed
+/// `check_entry_point`.
+pub fn check_veneer(image: &Image<'_>, sym: &Sym) -> Result<(), String> {
+    // Veneers are short. Don't let a missing `.size` make us decode the whole
+    // section.
+    const MAX_VENEER_SIZE: usize = 64;
+
+    let shdr = &image.elf.section_headers[sym.st_shndx];
+    let addr = sym.st_value & !1;
+    let code = section_contents_from(image, shdr, addr).unwrap();
+
+    let size = if sym.st_size > 0 {
+        sym.st_size as usize
+    } else {
+        MAX_VENEER_SIZE
+    };
+    let code = &code[..size.min(code.len())];
+
+    veneer::check(code, addr)
+}
+
+/// Get the contents of the section starting at `addr` and extending to the
+/// end of the section.
+fn section_contents_from<'a>(
+    image: &Image<'a>,
+    shdr: &SectionHeader,
+    addr: u64,
+) -> Option<&'a [u8]> {
+    if shdr.sh_type == SHT_NOBITS {
+        return None;
+    }
+    let offset = addr.wrapping_sub(shdr.sh_addr);
+    if offset > shdr.sh_size {
+        return None;
+    }
+    let start = shdr.file_range().start.checked_add(offset as usize)?;
+    image.bytes.get(start..shdr.file_range().end)
+}
+
+/// Find `SG` instructions that are not known to be a part of Secure gateway
+/// veneers. `known_veneers` is a set of section indices and addresses of
+/// known veneers. Returns those found in the Non-Secure-callable region.
+/// The others are reported through `on_warning`.
+///
+/// Any halfword-aligned occurrence of the `SG` encoding counts, including
+/// those appearing in the middle of another instruction or a literal pool.
+pub fn scan_sg_gadgets(
+    image: &Image<'_>,
+    known_veneers: &HashSet<(usize, u64)>,
+    nsc_section: &str,
+    on_warning: &mut dyn FnMut(&Warning),
+) -> Vec<SgGadget> {
+    let elf = &image.elf;
+
+    // The linker scripts define these symbols to indicate the extent of the
+    // Non-Secure-callable region, which may be larger than the section
+    // because of alignment padding
+    let nsc_range: Option<Range<u64>> = match (
+        find_symbol(elf, "__nsc_start"),
+        find_symbol(elf, "__nsc_end"),
+    ) {
+        (Some(start), Some(end)) => Some(start.st_value..end.st_value),
+        _ => None,
+    };
+
+    let mut nsc_gadgets = Vec::new();
+
+    for (shndx, shdr) in elf.section_headers.iter().enumerate() {
+        if !shdr.is_executable() || shdr.sh_type == SHT_NOBITS {
+            continue;
+        }
+        let section_name = elf.shdr_strtab.get_unsafe(shdr.sh_name).unwrap_or("");
+        let code = match image.bytes.get(shdr.file_range()) {
+            Some(code) => code,
+            None => {
+                on_warning(&Warning::TruncatedSection {
+                    path: image.path.to_owned(),
+                    section: section_name.to_owned(),
+                });
+                continue;
+            }
+        };
+
+        for offset in (0..code.len().saturating_sub(3)).step_by(2) {
+            let halfwords = [
+                u16::from_le_bytes([code[offset], code[offset + 1]]),
+                u16::from_le_bytes([code[offset + 2], code[offset + 3]]),
+            ];
+            if halfwords != SG_HALFWORDS {
+                continue;
+            }
+
+            let addr = shdr.sh_addr + offset as u64;
+            if known_veneers.contains(&(shndx, addr)) {
+                continue;
+            }
+
+            let gadget = SgGadget {
+                path: image.path.to_owned(),
+                section: section_name.to_owned(),
+                offset: offset as u64,
+                address: addr,
+            };
+
+            if is_in_nsc_region(shdr, section_name, addr, nsc_section, &nsc_range) {
+                nsc_gadgets.push(gadget);
+            } else {
+                on_warning(&Warning::StraySg(gadget));
+            }
+        }
+    }
+
+    nsc_gadgets
+}
+
+fn is_in_nsc_region(
+    shdr: &SectionHeader,
+    section_name: &str,
+    addr: u64,
+    nsc_section: &str,
+    nsc_range: &Option<Range<u64>>,
+) -> bool {
+    if section_name == nsc_section {
+        return true;
+    }
+
+    // Section addresses are meaningless in relocatable object files
+    match nsc_range {
+        Some(range) if shdr.sh_addr != 0 => range.contains(&addr),
+        _ => false,
+    }
+}
+
+/// Find a defined symbol by name.
+pub fn find_symbol(elf: &Elf<'_>, name: &str) -> Option<Sym> {
+    elf.syms
+        .iter()
+        .find(|sym| sym.st_shndx != 0 && elf.strtab.get_unsafe(sym.st_name) == Some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::{ElfBuilder, DATA, TEXT};
+    use goblin::elf::{
+        header::ET_EXEC,
+        section_header::SHN_ABS,
+        sym::{STB_GLOBAL, STT_NOTYPE, STT_OBJECT},
+    };
+    use std::path::Path;
+
+    const SG: [u8; 4] = [0x7f, 0xe9, 0x7f, 0xe9];
+    const NOP: [u8; 2] = [0x00, 0xbf];
+    const BX_LR: [u8; 2] = [0x70, 0x47];
+
+    fn sym(st_type: u8, st_shndx: usize, st_value: u64) -> Sym {
+        Sym {
+            st_info: (STB_GLOBAL << 4) | st_type,
+            st_shndx,
+            st_value,
+            ..Sym::default()
+        }
+    }
+
+    #[test]
+    fn accept_thumb_function() {
+        check_function_symbol(&sym(STT_FUNC, 1, 0x1001)).unwrap();
+    }
+
+    #[test]
+    fn reject_undefined_symbol() {
+        let e = check_function_symbol(&sym(STT_FUNC, SHN_UNDEF as usize, 0)).unwrap_err();
+        assert_eq!(e, "it is referenced but not defined");
+    }
+
+    #[test]
+    fn reject_non_function() {
+        let e = check_function_symbol(&sym(STT_OBJECT, 1, 0x1001)).unwrap_err();
+        assert_eq!(e, "it is not a function (type = OBJECT)");
+    }
+
+    #[test]
+    fn reject_missing_thumb_bit() {
+        let e = check_function_symbol(&sym(STT_FUNC, 1, 0x1000)).unwrap_err();
+        assert_eq!(e, "its value 0x00001000 does not have the Thumb bit set");
+    }
+
+    fn image(bytes: &[u8]) -> Image<'_> {
+        Image {
+            path: Path::new("secure.elf"),
+            partition: None,
+            bytes,
+            elf: Elf::parse(bytes).unwrap(),
+        }
+    }
+
+    /// An image with a function `plain` in `.text` and two entry points in
+    /// `.gnu.sgstubs`, of which only `gateway` starts with `SG`
+    fn entry_point_image() -> Vec<u8> {
+        let mut elf = ElfBuilder::new(ET_EXEC);
+        let text = elf.section(".text", TEXT, 0x1000, &[NOP, BX_LR].concat());
+        let nsc = elf.section(
+            ".gnu.sgstubs",
+            TEXT,
+            0x2000,
+            &[&SG[..], &NOP, &BX_LR].concat(),
+        );
+        elf.function("plain", 0x1001, text);
+        elf.function("gateway", 0x2001, nsc);
+        elf.function("not_sg", 0x2005, nsc);
+        elf.build()
+    }
+
+    fn check(bytes: &[u8], name: &str, nsc_section: &str) -> Result<(), String> {
+        let image = image(bytes);
+        let sym = find_symbol(&image.elf, name).unwrap();
+        check_entry_point(&image, &sym, nsc_section)
+    }
+
+    #[test]
+    fn accept_sg_in_nsc_section() {
+        check(&entry_point_image(), "gateway", ".gnu.sgstubs").unwrap();
+    }
+
+    #[test]
+    fn reject_entry_point_outside_nsc_section() {
+        let bytes = entry_point_image();
+
+        let e = check(&bytes, "plain", ".gnu.sgstubs").unwrap_err();
+        assert!(e.contains("located in the section '.text'"), "{}", e);
+
+        let e = check(&bytes, "gateway", ".nsc").unwrap_err();
+        assert!(e.contains("located in the section '.gnu.sgstubs'"), "{}", e);
+    }
+
+    #[test]
+    fn reject_non_sg() {
+        let e = check(&entry_point_image(), "not_sg", ".gnu.sgstubs").unwrap_err();
+        assert!(e.contains("is not SG (found 0xbf00 0x4770)"), "{}", e);
+    }
+
+    #[test]
+    fn reject_truncated_section() {
+        // Cut off the second halfword of `SG`
+        let mut bytes = entry_point_image();
+        bytes.truncate(bytes.len() - 6);
+
+        let e = check(&bytes, "gateway", ".gnu.sgstubs").unwrap_err();
+        assert!(e.contains("are not present"), "{}", e);
+    }
+
+    /// Scan an image having a known veneer at 0x2000. Returns the addresses
+    /// of the gadgets in the Non-Secure-callable region and those of the
+    /// others.
+    fn scan(bytes: &[u8]) -> (Vec<u64>, Vec<u64>) {
+        let image = image(bytes);
+        // `.gnu.sgstubs` is the second section of `gadget_image`
+        let known_veneers = [(2, 0x2000)].iter().cloned().collect();
+
+        let mut stray = Vec::new();
+        let nsc_gadgets =
+            scan_sg_gadgets(&image, &known_veneers, ".gnu.sgstubs", &mut |w| match w {
+                Warning::StraySg(gadget) => stray.push(gadget.address),
+                w => panic!("unexpected warning: {}", w),
+            });
+
+        (nsc_gadgets.iter().map(|g| g.address).collect(), stray)
+    }
+
+    fn gadget_image() -> ElfBuilder {
+        let mut elf = ElfBuilder::new(ET_EXEC);
+        // `SG` in the middle of a 32-bit instruction
+        elf.section(".text", TEXT, 0x1000, &[&NOP[..], &SG, &BX_LR].concat());
+        // The veneer is followed by a stray `SG`
+        elf.section(
+            ".gnu.sgstubs",
+            TEXT,
+            0x2000,
+            &[&SG[..], &BX_LR, &NOP, &SG].concat(),
+        );
+        // Padding after `.gnu.sgstubs`, which is still a part of the
+        // Non-Secure-callable region
+        elf.section(".text.pad", TEXT, 0x2010, &[&NOP[..], &SG].concat());
+        // Not executable
+        elf.section(".rodata", DATA, 0x3000, &SG);
+        elf
+    }
+
+    fn nsc_symbols(elf: &mut ElfBuilder, start: u32, end: u32) {
+        let info = (STB_GLOBAL << 4) | STT_NOTYPE;
+        elf.symbol("__nsc_start", start, 0, info, SHN_ABS as usize);
+        elf.symbol("__nsc_end", end, 0, info, SHN_ABS as usize);
+    }
+
+    #[test]
+    fn scan_nsc_region() {
+        let mut elf = gadget_image();
+        nsc_symbols(&mut elf, 0x2000, 0x2020);
+
+        let (nsc_gadgets, stray) = scan(&elf.build());
+        assert_eq!(nsc_gadgets, [0x2008, 0x2012]);
+        assert_eq!(stray, [0x1002]);
+    }
+
+    #[test]
+    fn scan_outside_nsc_range() {
+        // `.text.pad` is past `__nsc_end`
+        let mut elf = gadget_image();
+        nsc_symbols(&mut elf, 0x2000, 0x2010);
+
+        let (nsc_gadgets, stray) = scan(&elf.build());
+        assert_eq!(nsc_gadgets, [0x2008]);
+        assert_eq!(stray, [0x1002, 0x2012]);
+    }
+
+    #[test]
+    fn scan_without_nsc_range() {
+        let (nsc_gadgets, stray) = scan(&gadget_image().build());
+        assert_eq!(nsc_gadgets, [0x2008]);
+        assert_eq!(stray, [0x1002, 0x2012]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Output formats of import libraries.
+use std::io;
+
+use super::{decls, elfobj, manifest::Manifest, ImportLibrary};
+
+/// Writes an `ImportLibrary` in a particular format.
+pub trait Writer {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error>;
+}
+
+/// Generates an assembler input defining the symbols using `.set` directives.
+pub struct AsmWriter;
+
+impl Writer for AsmWriter {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        writeln!(out, ".syntax unified")?;
+        for sym in implib.symbols.iter() {
+            writeln!(out, ".type {} function", sym.name)?;
+            writeln!(out, ".set {}, 0x{:08x}", sym.name, sym.address)?;
+            writeln!(out, ".global {}", sym.name)?;
+        }
+        for id in implib.image_ids.iter() {
+            writeln!(out, ".set {}, 0x{:08x}", id.name, id.value)?;
+            writeln!(out, ".global {}", id.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates a linker script fragment defining the symbols using `PROVIDE`
+/// statements, which can be passed to GNU ld or LLD in place of an assembler
+/// input.
+pub struct LinkerScriptWriter;
+
+impl Writer for LinkerScriptWriter {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        // Linker scripts can't specify symbol types. The values are emitted
+        // as they are (i.e., with the Thumb bit set) so that branches to them
+        // are resolved exactly the same way as with `AsmWriter`.
+        writeln!(
+            out,
+            "/* Secure gateway addresses generated by tzmcfi_mkimplib. Do not edit. */"
+        )?;
+        for sym in implib.symbols.iter() {
+            writeln!(out, "PROVIDE({} = 0x{:08x});", sym.name, sym.address)?;
+        }
+        for id in implib.image_ids.iter() {
+            writeln!(out, "PROVIDE({} = 0x{:08x});", id.name, id.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates an ELF relocatable object file, like the one produced by
+/// `arm-none-eabi-ld --cmse-implib`, which can be directly passed to the
+/// linker.
+pub struct ElfWriter;
+
+impl Writer for ElfWriter {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        let mut symbols: Vec<_> = implib
+            .symbols
+            .iter()
+            .map(|sym| elfobj::ImplibSymbol {
+                name: &sym.name,
+                value: sym.address as u32,
+                size: sym.size as u32,
+                is_function: true,
+            })
+            .collect();
+        for id in implib.image_ids.iter() {
+            symbols.push(elfobj::ImplibSymbol {
+                name: &id.name,
+                value: id.value,
+                size: 0,
+                is_function: false,
+            });
+        }
+        elfobj::write_elf_implib(out, &symbols)
+    }
+}
+
+/// Generates a JSON manifest listing the exported symbols.
+pub struct ManifestWriter;
+
+impl Writer for ManifestWriter {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        Manifest::from(implib).write(out)
+    }
+}
+
+/// Generates a C header file declaring the Secure gateways. Requires
+/// `Builder::find_signatures`.
+pub struct CHeaderWriter;
+
+impl Writer for CHeaderWriter {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        decls::write_c_header(out, &gateways(implib))
+    }
+}
+
+/// Generates a Zig source file declaring the Secure gateways. Requires
+/// `Builder::find_signatures`.
+pub struct ZigDeclsWriter;
+
+impl Writer for ZigDeclsWriter {
+    fn write(&self, implib: &ImportLibrary, out: &mut dyn io::Write) -> Result<(), io::Error> {
+        decls::write_zig_decls(out, &gateways(implib))
+    }
+}
+
+fn gateways(implib: &ImportLibrary) -> Vec<(&str, &decls::Signature)> {
+    implib
+        .symbols
+        .iter()
+        .filter_map(|sym| Some((sym.name.as_str(), sym.signature.as_ref()?)))
+        .collect()
+}